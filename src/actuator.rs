@@ -0,0 +1,208 @@
+//! Device-agnostic traits for motors, servos and solenoids.
+//!
+//! Mechanism code written against these traits keeps working when, for example, a brushed
+//! MD is swapped for a brushless BLMD. The traits are implemented by the objects of the
+//! `device` module.
+//!
+//! ```rust,no_run
+//! use motor_lib::actuator::Motor;
+//! use motor_lib::device::{BlMd, Md};
+//! use motor_lib::{Error, USBHandle};
+//!
+//! fn drive(motor: &mut impl Motor, velocity: i16) -> Result<(), Error> {
+//!     let status = motor.set_velocity(velocity)?;
+//!     println!("speed: {}", status.speed);
+//!     Ok(())
+//! }
+//!
+//! fn main() -> Result<(), Error> {
+//!     let handle = USBHandle::new(0x483, 0x5740, 1);
+//!     drive(&mut Md::new(&handle, 0x00)?, 100)?;
+//!     drive(&mut BlMd::new(&handle, 0x30, 1)?, 100)?;
+//!     Ok(())
+//! }
+//! ```
+
+use crate::blmd::BlMdStatus;
+use crate::device::{BlMd, Md, SdPort, SmdPort};
+use crate::md::{LimSwStatus, MdStatus};
+use crate::HandleTrait;
+
+/// The status reported by any motor driver.
+#[derive(Debug, Clone, Copy)]
+pub struct MotorStatus {
+    pub angle: i16,
+    pub speed: i16,
+    /// The motor current, if the driver measures it.
+    pub current: Option<i16>,
+    /// The limit switch states, if the driver has limit switches.
+    pub limsw: Option<LimSwStatus>,
+}
+
+impl From<MdStatus> for MotorStatus {
+    fn from(status: MdStatus) -> Self {
+        Self {
+            angle: status.angle,
+            speed: status.speed,
+            current: None,
+            limsw: Some(status.limsw),
+        }
+    }
+}
+
+impl From<BlMdStatus> for MotorStatus {
+    fn from(status: BlMdStatus) -> Self {
+        Self {
+            angle: status.angle,
+            speed: status.speed,
+            current: Some(status.current),
+            limsw: None,
+        }
+    }
+}
+
+/// A motor driven by an MD or a BLMD controller.
+///
+/// Commands a driver cannot execute return `Error::Unsupported`.
+pub trait Motor {
+    /// Sets the PWM duty cycle.
+    fn set_duty(&mut self, duty: i16) -> Result<MotorStatus, crate::Error>;
+    /// Sets the target velocity.
+    fn set_velocity(&mut self, velocity: i16) -> Result<MotorStatus, crate::Error>;
+    /// Sets the target position.
+    fn set_position(&mut self, position: i16) -> Result<MotorStatus, crate::Error>;
+    /// Sets the motor current.
+    fn set_current(&mut self, current: i16) -> Result<MotorStatus, crate::Error>;
+    /// Returns the last status received from the driver.
+    fn status(&self) -> Option<MotorStatus>;
+    /// Requests the status from the driver and waits for it.
+    fn read_status(&mut self) -> Result<MotorStatus, crate::Error>;
+}
+
+/// A servo connected to one SMD port.
+pub trait Servo {
+    /// Sets the target angle and returns the angle reported by the driver.
+    fn set_angle(&mut self, angle: i16) -> Result<i16, crate::Error>;
+    /// Returns the last angle received from the driver.
+    fn angle(&self) -> Option<i16>;
+}
+
+/// A solenoid connected to one SD port.
+pub trait Solenoid {
+    /// Sets the output power and returns the power reported by the driver.
+    fn set_power(&mut self, power: i16) -> Result<i16, crate::Error>;
+    /// Returns the last power received from the driver.
+    fn power(&self) -> Option<i16>;
+}
+
+impl<M: Motor + ?Sized> Motor for Box<M> {
+    fn set_duty(&mut self, duty: i16) -> Result<MotorStatus, crate::Error> {
+        (**self).set_duty(duty)
+    }
+
+    fn set_velocity(&mut self, velocity: i16) -> Result<MotorStatus, crate::Error> {
+        (**self).set_velocity(velocity)
+    }
+
+    fn set_position(&mut self, position: i16) -> Result<MotorStatus, crate::Error> {
+        (**self).set_position(position)
+    }
+
+    fn set_current(&mut self, current: i16) -> Result<MotorStatus, crate::Error> {
+        (**self).set_current(current)
+    }
+
+    fn status(&self) -> Option<MotorStatus> {
+        (**self).status()
+    }
+
+    fn read_status(&mut self) -> Result<MotorStatus, crate::Error> {
+        (**self).read_status()
+    }
+}
+
+impl<H: HandleTrait> Motor for Md<'_, H> {
+    fn set_duty(&mut self, duty: i16) -> Result<MotorStatus, crate::Error> {
+        self.send_pwm(duty).map(MotorStatus::from)
+    }
+
+    fn set_velocity(&mut self, velocity: i16) -> Result<MotorStatus, crate::Error> {
+        self.send_speed(velocity).map(MotorStatus::from)
+    }
+
+    fn set_position(&mut self, position: i16) -> Result<MotorStatus, crate::Error> {
+        self.send_angle(position).map(MotorStatus::from)
+    }
+
+    fn set_current(&mut self, _current: i16) -> Result<MotorStatus, crate::Error> {
+        Err(crate::Error::Unsupported("MD has no current control"))
+    }
+
+    fn status(&self) -> Option<MotorStatus> {
+        self.last_status().copied().map(MotorStatus::from)
+    }
+
+    fn read_status(&mut self) -> Result<MotorStatus, crate::Error> {
+        self.request_status()?;
+        self.receive_status().map(MotorStatus::from)
+    }
+}
+
+impl<H: HandleTrait> Motor for BlMd<'_, H> {
+    fn set_duty(&mut self, _duty: i16) -> Result<MotorStatus, crate::Error> {
+        Err(crate::Error::Unsupported("BLMD has no duty cycle control"))
+    }
+
+    fn set_velocity(&mut self, velocity: i16) -> Result<MotorStatus, crate::Error> {
+        self.send_velocity(velocity).map(MotorStatus::from)
+    }
+
+    fn set_position(&mut self, _position: i16) -> Result<MotorStatus, crate::Error> {
+        Err(crate::Error::Unsupported("BLMD has no position control"))
+    }
+
+    fn set_current(&mut self, current: i16) -> Result<MotorStatus, crate::Error> {
+        self.send_current(current).map(MotorStatus::from)
+    }
+
+    fn status(&self) -> Option<MotorStatus> {
+        self.last_status().copied().map(MotorStatus::from)
+    }
+
+    fn read_status(&mut self) -> Result<MotorStatus, crate::Error> {
+        self.request_status()?;
+        self.receive_status().map(MotorStatus::from)
+    }
+}
+
+impl<H: HandleTrait> Servo for SmdPort<'_, '_, H> {
+    fn set_angle(&mut self, angle: i16) -> Result<i16, crate::Error> {
+        self.smd.send_angle(self.port, angle)?;
+        Ok(self.angle().unwrap_or_default())
+    }
+
+    fn angle(&self) -> Option<i16> {
+        let status = self.smd.last_status()?;
+        Some(if self.port == 0 {
+            status.angle_0
+        } else {
+            status.angle_1
+        })
+    }
+}
+
+impl<H: HandleTrait> Solenoid for SdPort<'_, '_, H> {
+    fn set_power(&mut self, power: i16) -> Result<i16, crate::Error> {
+        self.sd.send_power(self.port, power)?;
+        Ok(self.power().unwrap_or_default())
+    }
+
+    fn power(&self) -> Option<i16> {
+        let status = self.sd.last_status()?;
+        Some(if self.port == 0 {
+            status.port_0
+        } else {
+            status.port_1
+        })
+    }
+}
@@ -0,0 +1,428 @@
+//! Live terminal dashboard of every device on the bus.
+//!
+//! Usage: motor_dash [--usb [VID:PID] | --grpc URL] [--poll TYPE:ADDR | --poll SR]...
+//!        [--interval MILLISECONDS]
+//!
+//! The SR has no address, so it is polled with `--poll SR`.
+//!
+//! Keys: space or `e` sends an emergency stop, `q` quits.
+
+use motor_lib::cli::{self, parse_number};
+use motor_lib::frame::{self, Frame};
+use motor_lib::recording::Direction;
+use motor_lib::{blmd, device_type, md, sd, send_emergency, smd, sr, Error, HandleTrait};
+use std::collections::{BTreeMap, VecDeque};
+use std::io::{self, Write};
+use std::{env, process, time};
+
+const USAGE: &str = "Usage: motor_dash [--usb [VID:PID] | --grpc URL] \
+[--poll TYPE:ADDR | --poll SR]... [--interval MILLISECONDS]";
+
+const HISTORY: usize = 40;
+const REDRAW_INTERVAL: time::Duration = time::Duration::from_millis(100);
+const SPARKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+#[cfg(unix)]
+mod terminal {
+    use std::io::{self, Write};
+
+    /// Puts the terminal into raw, non-blocking mode and restores it when dropped.
+    pub struct RawTerminal {
+        original: libc::termios,
+    }
+
+    impl RawTerminal {
+        pub fn enter() -> io::Result<Self> {
+            let mut termios: libc::termios = unsafe { std::mem::zeroed() };
+            if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut termios) } < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let original = termios;
+            termios.c_lflag &= !(libc::ICANON | libc::ECHO);
+            termios.c_cc[libc::VMIN] = 0;
+            termios.c_cc[libc::VTIME] = 0;
+            if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &termios) } < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            // Alternate screen, hidden cursor.
+            print!("\x1b[?1049h\x1b[?25l");
+            io::stdout().flush()?;
+            Ok(Self { original })
+        }
+
+        /// Returns the next pressed key, if any.
+        pub fn key(&self) -> Option<u8> {
+            let mut key = 0u8;
+            let size = unsafe { libc::read(libc::STDIN_FILENO, &mut key as *mut u8 as *mut _, 1) };
+            (size == 1).then_some(key)
+        }
+    }
+
+    impl Drop for RawTerminal {
+        fn drop(&mut self) {
+            print!("\x1b[?25h\x1b[?1049l");
+            io::stdout().flush().unwrap_or(());
+            unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.original) };
+        }
+    }
+}
+
+#[cfg(not(unix))]
+mod terminal {
+    use std::io;
+
+    pub struct RawTerminal;
+
+    impl RawTerminal {
+        pub fn enter() -> io::Result<Self> {
+            Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "motor_dash needs a Unix terminal",
+            ))
+        }
+
+        pub fn key(&self) -> Option<u8> {
+            None
+        }
+    }
+}
+
+struct DeviceState {
+    frame: Frame,
+    last_seen: time::Instant,
+    frames: u64,
+    history: VecDeque<i16>,
+}
+
+/// The value drawn in the sparkline of a device.
+fn trend(frame: &Frame) -> i16 {
+    match frame {
+        Frame::Md(status) => status.speed,
+        Frame::Sd(status) => status.port_0,
+        Frame::Smd(status) => status.angle_0,
+        Frame::BlMd(status) => status.speed,
+        Frame::Sr(status) => (status.freq * 4.0) as i16,
+        _ => 0,
+    }
+}
+
+fn sparkline(history: &VecDeque<i16>) -> String {
+    let min = history.iter().copied().min().unwrap_or(0) as i32;
+    let max = history.iter().copied().max().unwrap_or(0) as i32;
+    let range = (max - min).max(1);
+    history
+        .iter()
+        .map(|&value| SPARKS[((value as i32 - min) * (SPARKS.len() as i32 - 1) / range) as usize])
+        .collect()
+}
+
+fn limsw(limsw_0: bool, limsw_1: bool) -> String {
+    let mark = |pressed| if pressed { '●' } else { '○' };
+    format!("{}{}", mark(limsw_0), mark(limsw_1))
+}
+
+struct Dashboard {
+    devices: BTreeMap<(u8, u8), DeviceState>,
+    unknown_frames: u64,
+    message: String,
+    emergency: Option<time::Instant>,
+    started: time::Instant,
+}
+
+impl Dashboard {
+    fn update(&mut self, buf: &[u8; 8]) {
+        let frame = frame::decode(Direction::Read, buf);
+        let Some(device) = frame.device() else {
+            self.unknown_frames += 1;
+            return;
+        };
+        let value = trend(&frame);
+        let now = time::Instant::now();
+        let state = self.devices.entry(device).or_insert_with(|| DeviceState {
+            frame: Frame::Unknown(*buf),
+            last_seen: now,
+            frames: 0,
+            history: VecDeque::with_capacity(HISTORY),
+        });
+        state.frame = frame;
+        state.last_seen = now;
+        state.frames += 1;
+        if state.history.len() == HISTORY {
+            state.history.pop_front();
+        }
+        state.history.push_back(value);
+    }
+
+    fn draw(&self) -> io::Result<()> {
+        let mut out = String::from("\x1b[H");
+        let mut line = |text: String| {
+            out.push_str(&text);
+            out.push_str("\x1b[K\r\n");
+        };
+        line(format!(
+            "\x1b[1mmotor_dash\x1b[0m  up {:.0} s  [space/e] EMERGENCY STOP  [q] quit",
+            self.started.elapsed().as_secs_f64()
+        ));
+        match self.emergency {
+            Some(sent) => line(format!(
+                "\x1b[1;41;97m  EMERGENCY STOP SENT {:.1} s ago  \x1b[0m",
+                sent.elapsed().as_secs_f64()
+            )),
+            None => line(String::new()),
+        }
+        line(format!(
+            "\x1b[7m{:<5} {:>4} {:>13} {:>7} {:>7} {:>5} {:>7} {:>7}  {:<width$}\x1b[0m",
+            "TYPE",
+            "ADDR",
+            "ANGLE/PORTS",
+            "SPEED",
+            "CURRENT",
+            "LIMSW",
+            "FRAMES",
+            "SEEN",
+            "TREND",
+            width = HISTORY
+        ));
+        for (&(device_type, address), state) in &self.devices {
+            let (angle, speed, current, switches) = match &state.frame {
+                Frame::Md(status) => (
+                    status.angle.to_string(),
+                    status.speed.to_string(),
+                    "-".to_string(),
+                    limsw(status.limsw.limsw_0, status.limsw.limsw_1),
+                ),
+                Frame::Sd(status) => (
+                    format!("{}/{}", status.port_0, status.port_1),
+                    "-".to_string(),
+                    "-".to_string(),
+                    limsw(status.limsw.limsw_0, status.limsw.limsw_1),
+                ),
+                Frame::Smd(status) => (
+                    format!("{}/{}", status.angle_0, status.angle_1),
+                    "-".to_string(),
+                    "-".to_string(),
+                    "-".to_string(),
+                ),
+                Frame::BlMd(status) => (
+                    status.angle.to_string(),
+                    status.speed.to_string(),
+                    status.current.to_string(),
+                    "-".to_string(),
+                ),
+                // The color in the angle column, the blinking frequency in the speed column
+                // and whether the LEDs are powered in the limit switch column.
+                Frame::Sr(status) => (
+                    format!(
+                        "#{:02x}{:02x}{:02x}",
+                        status.color.red, status.color.green, status.color.blue
+                    ),
+                    format!("{}Hz", status.freq),
+                    "-".to_string(),
+                    limsw(status.voltage, false),
+                ),
+                _ => Default::default(),
+            };
+            let age = state.last_seen.elapsed().as_secs_f64();
+            let seen = if age < 1.0 {
+                format!("\x1b[32m{:>6.0}ms\x1b[0m", age * 1000.0)
+            } else {
+                format!("\x1b[31m{:>6.1}s\x1b[0m", age)
+            };
+            line(format!(
+                "{:<5} 0x{:02x} {:>13} {:>7} {:>7} {:>5} {:>7} {}  {}",
+                device_type::name(device_type).unwrap_or("?"),
+                address,
+                angle,
+                speed,
+                current,
+                switches,
+                state.frames,
+                seen,
+                sparkline(&state.history)
+            ));
+        }
+        line(String::new());
+        line(format!("unknown frames: {}", self.unknown_frames));
+        line(self.message.clone());
+        out.push_str("\x1b[J");
+        let mut stdout = io::stdout();
+        stdout.write_all(out.as_bytes())?;
+        stdout.flush()
+    }
+}
+
+/// Requests the status of a device. Device types without a status request are skipped.
+fn poll(handle: &impl HandleTrait, device_type: u8, address: u8) -> Result<usize, Error> {
+    match device_type {
+        device_type::MD => md::request_status(handle, address),
+        device_type::SD => sd::request_status(handle, address),
+        device_type::SMD => smd::request_status(handle, address),
+        device_type::BLMD => blmd::request_status(handle, device_type::BLMD, address),
+        device_type::SR => sr::request_status(handle),
+        _ => Ok(0),
+    }
+}
+
+/// Parses a `--poll` argument into the device type and address of the device to poll.
+fn parse_poll(spec: &str) -> Option<(u8, u8)> {
+    if device_type::from_name(spec) == Some(device_type::SR) {
+        // SR statuses carry the device type in place of an address.
+        return Some((device_type::SR, device_type::SR));
+    }
+    let (name, address) = spec.split_once(':')?;
+    let device_type = device_type::from_name(name)?;
+    [
+        device_type::MD,
+        device_type::SD,
+        device_type::SMD,
+        device_type::BLMD,
+    ]
+    .contains(&device_type)
+    .then_some((device_type, parse_number(address)?))
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("{}\n{}", message, USAGE);
+    process::exit(2);
+}
+
+fn main() {
+    let mut transport = cli::default_transport();
+    let mut polled: Vec<(u8, u8)> = Vec::new();
+    let mut interval = time::Duration::from_millis(100);
+    let mut args = env::args().skip(1).peekable();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--usb" | "--grpc" => {
+                transport =
+                    cli::parse_transport(&arg, &mut args).unwrap_or_else(|message| fail(&message))
+            }
+            "--poll" => {
+                let spec = args
+                    .next()
+                    .unwrap_or_else(|| fail("--poll needs TYPE:ADDR or SR"));
+                polled.push(
+                    parse_poll(&spec).unwrap_or_else(|| fail(&format!("invalid --poll: {}", spec))),
+                );
+            }
+            "--interval" => {
+                let millis = args.next().and_then(|millis| millis.parse().ok());
+                interval = time::Duration::from_millis(
+                    millis.unwrap_or_else(|| fail("--interval needs MILLISECONDS")),
+                );
+            }
+            "--help" | "-h" => {
+                println!("{}", USAGE);
+                return;
+            }
+            _ => fail(&format!("unknown argument: {}", arg)),
+        }
+    }
+    let handle = transport.open().unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(1);
+    });
+
+    let terminal = match terminal::RawTerminal::enter() {
+        Ok(terminal) => terminal,
+        Err(e) => {
+            eprintln!("Cannot set up the terminal: {}", e);
+            process::exit(1);
+        }
+    };
+    let mut dashboard = Dashboard {
+        devices: BTreeMap::new(),
+        unknown_frames: 0,
+        message: String::new(),
+        emergency: None,
+        started: time::Instant::now(),
+    };
+    let mut last_poll = time::Instant::now() - interval;
+    let mut last_draw = time::Instant::now() - REDRAW_INTERVAL;
+    let mut buf = [0; 8];
+    loop {
+        match terminal.key() {
+            Some(b'q') => break,
+            Some(b' ') | Some(b'e') | Some(b'E') => match send_emergency(&handle) {
+                Ok(_) => dashboard.emergency = Some(time::Instant::now()),
+                Err(e) => dashboard.message = format!("Emergency stop failed: {}", e),
+            },
+            _ => {}
+        }
+        if !polled.is_empty() && last_poll.elapsed() >= interval {
+            last_poll = time::Instant::now();
+            for &(device_type, address) in &polled {
+                if let Err(e) = poll(&handle, device_type, address) {
+                    dashboard.message = format!("Status request failed: {}", e);
+                }
+            }
+        }
+        match handle.read_bulk(&mut buf, time::Duration::from_millis(20)) {
+            Ok(_) => dashboard.update(&buf),
+            Err(e) if e.is_timeout() => {}
+            Err(e) => dashboard.message = format!("Read failed: {}", e),
+        }
+        if last_draw.elapsed() >= REDRAW_INTERVAL {
+            last_draw = time::Instant::now();
+            if dashboard.draw().is_err() {
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use motor_lib::recording::{Outcome, Record};
+    use motor_lib::ReplayHandle;
+
+    fn record(direction: Direction, data: [u8; 8]) -> Record {
+        Record {
+            direction,
+            outcome: Outcome::Ok,
+            timestamp: time::Duration::ZERO,
+            data: data.to_vec(),
+        }
+    }
+
+    #[test]
+    fn polls_sr_and_skips_unknown_device_types() {
+        let handle = ReplayHandle::new(vec![
+            record(
+                Direction::Write,
+                [device_type::SR, 0x60, sr::mode::STATUS, 0, 0, 0, 0, 0],
+            ),
+            record(Direction::Read, [device_type::SR, 0, 0, 1, 0, 255, 0, 8]),
+        ]);
+        let sr = parse_poll("sr").unwrap();
+        poll(&handle, sr.0, sr.1).unwrap();
+        assert_eq!(poll(&handle, device_type::SM, 0x50).unwrap(), 0);
+
+        let mut dashboard = Dashboard {
+            devices: BTreeMap::new(),
+            unknown_frames: 0,
+            message: String::new(),
+            emergency: None,
+            started: time::Instant::now(),
+        };
+        let mut buf = [0; 8];
+        handle.read_bulk(&mut buf, time::Duration::ZERO).unwrap();
+        dashboard.update(&buf);
+        assert!(matches!(
+            dashboard.devices[&sr].frame,
+            Frame::Sr(status) if status.voltage && status.color.red == 255
+        ));
+        assert!(handle.divergences().is_empty());
+        assert!(handle.is_finished());
+    }
+
+    #[test]
+    fn parses_poll_arguments() {
+        assert_eq!(parse_poll("md:0x01"), Some((device_type::MD, 0x01)));
+        assert_eq!(parse_poll("BLMD:3"), Some((device_type::BLMD, 3)));
+        assert_eq!(parse_poll("SR"), Some((device_type::SR, device_type::SR)));
+        assert_eq!(parse_poll("sm:0x50"), None);
+        assert_eq!(parse_poll("md"), None);
+    }
+}
@@ -0,0 +1,310 @@
+//! Prints every frame on the bus in human-readable form.
+//!
+//! Over gRPC the frames come from the broadcast of the server, which carries every frame it
+//! reads, including the replies to its other clients. Only the frames read from the bus are
+//! seen this way, not the commands of the other clients.
+//!
+//! Usage: motor_sniff [--usb [VID:PID]] [--grpc URL] [--file PATH]
+//!                    [--address ADDR]... [--type TYPE]... [--stats SECONDS] [--no-color]
+
+use motor_lib::cli::{self, parse_number};
+use motor_lib::frame::{self, Frame};
+use motor_lib::recording::{self, Direction, Outcome};
+use motor_lib::robot::TransportConfig;
+use motor_lib::{device_type, GrpcClient, HandleTrait};
+use std::collections::BTreeMap;
+use std::io::{IsTerminal, Write};
+use std::{env, process, time};
+
+const USAGE: &str = "Usage: motor_sniff [--usb [VID:PID]] [--grpc URL] [--file PATH] \
+[--address ADDR]... [--type TYPE]... [--stats SECONDS] [--no-color]";
+
+enum Source {
+    Bus(TransportConfig),
+    /// The frames broadcast by a server.
+    Server {
+        url: String,
+        bus: Option<String>,
+    },
+    File(String),
+}
+
+struct Options {
+    addresses: Vec<u8>,
+    types: Vec<u8>,
+    stats: Option<time::Duration>,
+    color: bool,
+}
+
+fn parse_args() -> Result<(Source, Options), String> {
+    let mut source = Source::Bus(cli::default_transport());
+    let mut options = Options {
+        addresses: Vec::new(),
+        types: Vec::new(),
+        stats: None,
+        color: std::io::stdout().is_terminal(),
+    };
+    let mut args = env::args().skip(1).peekable();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().ok_or(format!("{} needs a value", name));
+        match arg.as_str() {
+            "--usb" | "--grpc" => {
+                source = match cli::parse_transport(&arg, &mut args)? {
+                    TransportConfig::Grpc { url, bus } => Source::Server { url, bus },
+                    transport => Source::Bus(transport),
+                }
+            }
+            "--file" => source = Source::File(value("--file")?),
+            "--address" => {
+                let address = value("--address")?;
+                options
+                    .addresses
+                    .push(parse_number(&address).ok_or(format!("invalid address: {}", address))?);
+            }
+            "--type" => {
+                let name = value("--type")?;
+                options.types.push(
+                    device_type::from_name(&name)
+                        .ok_or(format!("unknown device type: {}", name))?,
+                );
+            }
+            "--stats" => {
+                let seconds = value("--stats")?;
+                let seconds: f64 = seconds
+                    .parse()
+                    .map_err(|_| format!("invalid interval: {}", seconds))?;
+                options.stats = Some(time::Duration::from_secs_f64(seconds.max(0.1)));
+            }
+            "--no-color" => options.color = false,
+            "--help" | "-h" => {
+                println!("{}", USAGE);
+                process::exit(0);
+            }
+            _ => return Err(format!("unknown argument: {}", arg)),
+        }
+    }
+    Ok((source, options))
+}
+
+/// Per-device frame counts used for rate statistics.
+struct Stats {
+    counts: BTreeMap<(u8, u8), u64>,
+    since: time::Duration,
+}
+
+impl Stats {
+    fn new(since: time::Duration) -> Self {
+        Self {
+            counts: BTreeMap::new(),
+            since,
+        }
+    }
+
+    fn count(&mut self, device: (u8, u8)) {
+        *self.counts.entry(device).or_default() += 1;
+    }
+
+    fn print(&mut self, now: time::Duration, color: bool) {
+        let seconds = (now - self.since).as_secs_f64().max(f64::EPSILON);
+        eprintln!("--- rates over {:.1} s ---", seconds);
+        for (&(device_type, address), &count) in &self.counts {
+            let name = device_type::name(device_type).unwrap_or("?");
+            eprintln!(
+                "{}",
+                paint(
+                    device_type,
+                    &format!(
+                        "{:<10} 0x{:02x} {:>8.1} frames/s",
+                        name,
+                        address,
+                        count as f64 / seconds
+                    ),
+                    color
+                )
+            );
+        }
+        self.counts.clear();
+        self.since = now;
+    }
+}
+
+fn paint(device_type: u8, text: &str, color: bool) -> String {
+    if !color {
+        return text.to_string();
+    }
+    let code = match device_type {
+        device_type::MD => "36",
+        device_type::SD => "33",
+        device_type::SMD => "35",
+        device_type::BLMD => "34",
+        device_type::SR => "32",
+        device_type::EMMERGENCY => "1;31",
+        _ => "2",
+    };
+    format!("\x1b[{}m{}\x1b[0m", code, text)
+}
+
+struct Sniffer {
+    options: Options,
+    stats: Stats,
+}
+
+impl Sniffer {
+    fn show(&mut self, timestamp: time::Duration, direction: Direction, frame: Frame) {
+        let device = frame.device();
+        if !self.options.types.is_empty()
+            && !device.is_some_and(|(device_type, _)| self.options.types.contains(&device_type))
+        {
+            return;
+        }
+        if !self.options.addresses.is_empty()
+            && !device.is_some_and(|(_, address)| self.options.addresses.contains(&address))
+        {
+            return;
+        }
+        if let Some(device) = device {
+            self.stats.count(device);
+        }
+        let arrow = match direction {
+            Direction::Write => "TX",
+            Direction::Read => "RX",
+        };
+        let device_type = device.map_or(0xff, |(device_type, _)| device_type);
+        let line = writeln!(
+            std::io::stdout(),
+            "[{:>12.6}] {} {}",
+            timestamp.as_secs_f64(),
+            arrow,
+            paint(device_type, &frame.to_string(), self.options.color)
+        );
+        // Stop quietly when the output is closed, e.g. when piped into `head`.
+        if line.is_err() {
+            process::exit(0);
+        }
+    }
+
+    fn tick(&mut self, now: time::Duration) {
+        if let Some(interval) = self.options.stats {
+            if now - self.stats.since >= interval {
+                self.stats.print(now, self.options.color);
+            }
+        }
+    }
+
+    fn listen(&mut self, handle: &impl HandleTrait) {
+        let started = time::Instant::now();
+        let mut buf = [0; 8];
+        loop {
+            match handle.read_bulk(&mut buf, time::Duration::from_millis(100)) {
+                Ok(_) => self.show(
+                    started.elapsed(),
+                    Direction::Read,
+                    frame::decode(Direction::Read, &buf),
+                ),
+                Err(e) if e.is_timeout() => {}
+                Err(e) => {
+                    eprintln!("Read failed: {}", e);
+                    process::exit(1);
+                }
+            }
+            self.tick(started.elapsed());
+        }
+    }
+
+    fn subscribe(&mut self, client: &GrpcClient) {
+        let started = time::Instant::now();
+        let updates = client
+            .subscribe_status(&self.options.types, &self.options.addresses)
+            .unwrap_or_else(|e| {
+                eprintln!("Cannot subscribe: {}", e);
+                process::exit(1);
+            });
+        let mut dropped = 0;
+        for update in updates {
+            let update = update.unwrap_or_else(|e| {
+                eprintln!("Read failed: {}", e);
+                process::exit(1);
+            });
+            if update.dropped > dropped {
+                eprintln!("--- missed {} frames ---", update.dropped - dropped);
+                dropped = update.dropped;
+            }
+            self.show(started.elapsed(), Direction::Read, update.frame);
+            self.tick(started.elapsed());
+        }
+        eprintln!("The server closed the stream");
+        process::exit(1);
+    }
+
+    fn replay(&mut self, path: &str) {
+        let records = match recording::read_session(path) {
+            Ok(records) => records,
+            Err(e) => {
+                eprintln!("Cannot read {}: {}", path, e);
+                process::exit(1);
+            }
+        };
+        if let Some(first) = records.first() {
+            self.stats.since = first.timestamp;
+        }
+        let mut last = self.stats.since;
+        for record in &records {
+            last = record.timestamp;
+            match record.outcome {
+                Outcome::Ok if record.data.len() == 8 => {
+                    let mut buf = [0; 8];
+                    buf.copy_from_slice(&record.data);
+                    let frame = frame::decode(record.direction, &buf);
+                    self.show(record.timestamp, record.direction, frame);
+                }
+                Outcome::Ok => println!(
+                    "[{:>12.6}] short frame {:02x?}",
+                    record.timestamp.as_secs_f64(),
+                    record.data
+                ),
+                Outcome::Timeout | Outcome::Error => continue,
+            }
+            self.tick(record.timestamp);
+        }
+        if !self.stats.counts.is_empty() {
+            self.stats.print(last, self.options.color);
+        }
+    }
+}
+
+fn main() {
+    let (source, options) = match parse_args() {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            eprintln!("{}\n{}", e, USAGE);
+            process::exit(2);
+        }
+    };
+    let mut sniffer = Sniffer {
+        options,
+        stats: Stats::new(time::Duration::ZERO),
+    };
+    match source {
+        Source::Bus(transport) => match transport.open() {
+            Ok(handle) => sniffer.listen(&handle),
+            Err(e) => {
+                eprintln!("{}", e);
+                process::exit(1);
+            }
+        },
+        Source::Server { url, bus } => {
+            let client = match bus {
+                Some(bus) => GrpcClient::with_bus(&url, &bus),
+                None => GrpcClient::new(&url),
+            };
+            match client {
+                Ok(client) => sniffer.subscribe(&client),
+                Err(e) => {
+                    eprintln!("{}", e);
+                    process::exit(1);
+                }
+            }
+        }
+        Source::File(path) => sniffer.replay(&path),
+    }
+}
@@ -0,0 +1,439 @@
+//! Drives a single device by hand, e.g. during pit checks.
+//!
+//! Usage: motorctl [--usb [VID:PID] | --grpc URL] [--json] <COMMAND>
+
+use motor_lib::cli::{self, parse_number};
+use motor_lib::{blmd, device_type, md, sd, send_emergency, smd, sr, Error, HandleTrait};
+use serde_json::json;
+use std::{env, process, thread, time};
+
+const USAGE: &str = "Usage: motorctl [--usb [VID:PID] | --grpc URL] [--json] <COMMAND>
+
+Commands:
+  md pwm <ADDR> <POWER>
+  md speed <ADDR> <VELOCITY>
+  md angle <ADDR> <ANGLE>
+  md limsw <ADDR> <PORT> <POWER> <AFTER_POWER>
+  sd power <ADDR> <PORT> <POWER>
+  sd powers <ADDR> <POWER_0> <POWER_1>
+  smd angle <ADDR> <PORT> <ANGLE>
+  smd angles <ADDR> <ANGLE_0> <ANGLE_1>
+  blmd current <ADDR> <CONTROLLER_ID> <CURRENT>
+  blmd velocity <ADDR> <CONTROLLER_ID> <VELOCITY>
+  sr color <RED> <GREEN> <BLUE> [FREQ]
+  sr start
+  sr stop
+  estop
+  status <md|sd|smd> <ADDR> [--watch [MILLISECONDS]]
+  status blmd <ADDR> <CONTROLLER_ID> [--watch [MILLISECONDS]]
+  status sr [--watch [MILLISECONDS]]";
+
+/// The result of a command, printed as text or JSON.
+enum Output {
+    Md(md::MdStatus),
+    Sd(sd::SdStatus),
+    Smd(smd::SmdStatus),
+    BlMd(blmd::BlMdStatus),
+    Sr(sr::SrStatus),
+    Sent(usize),
+    Done,
+}
+
+impl Output {
+    fn json(&self) -> serde_json::Value {
+        match self {
+            Output::Md(status) => json!({
+                "type": "md",
+                "address": status.address,
+                "semi_id": status.semi_id,
+                "angle": status.angle,
+                "speed": status.speed,
+                "limsw_0": status.limsw.limsw_0,
+                "limsw_1": status.limsw.limsw_1,
+            }),
+            Output::Sd(status) => json!({
+                "type": "sd",
+                "address": status.address,
+                "semi_id": status.semi_id,
+                "port_0": status.port_0,
+                "port_1": status.port_1,
+                "limsw_0": status.limsw.limsw_0,
+                "limsw_1": status.limsw.limsw_1,
+            }),
+            Output::Smd(status) => json!({
+                "type": "smd",
+                "address": status.address,
+                "semi_id": status.semi_id,
+                "angle_0": status.angle_0,
+                "angle_1": status.angle_1,
+            }),
+            Output::BlMd(status) => json!({
+                "type": "blmd",
+                "std_id": status.std_id,
+                "angle": status.angle,
+                "speed": status.speed,
+                "current": status.current,
+            }),
+            Output::Sr(status) => json!({
+                "type": "sr",
+                "voltage": status.voltage,
+                "red": status.color.red,
+                "green": status.color.green,
+                "blue": status.color.blue,
+                "freq": status.freq,
+            }),
+            Output::Sent(size) => json!({ "sent": size }),
+            Output::Done => json!({ "ok": true }),
+        }
+    }
+
+    fn text(&self) -> String {
+        match self {
+            Output::Md(status) => format!("{:?}", status),
+            Output::Sd(status) => format!("{:?}", status),
+            Output::Smd(status) => format!("{:?}", status),
+            Output::BlMd(status) => format!("{:?}", status),
+            Output::Sr(status) => format!("{:?}", status),
+            Output::Sent(size) => format!("Sent {} bytes", size),
+            Output::Done => "OK".to_string(),
+        }
+    }
+}
+
+/// Positional arguments of a subcommand.
+struct Args {
+    values: Vec<String>,
+    position: usize,
+}
+
+impl Args {
+    fn next(&mut self, name: &str) -> Result<String, String> {
+        let value = self
+            .values
+            .get(self.position)
+            .cloned()
+            .ok_or(format!("missing <{}>", name))?;
+        self.position += 1;
+        Ok(value)
+    }
+
+    fn optional(&mut self) -> Option<String> {
+        let value = self.values.get(self.position).cloned();
+        self.position += 1;
+        value
+    }
+
+    fn u8(&mut self, name: &str) -> Result<u8, String> {
+        let value = self.next(name)?;
+        parse_number(&value).ok_or(format!("invalid <{}>: {}", name, value))
+    }
+
+    fn i16(&mut self, name: &str) -> Result<i16, String> {
+        let value = self.next(name)?;
+        value
+            .parse()
+            .map_err(|_| format!("invalid <{}>: {}", name, value))
+    }
+
+    fn finish(&self) -> Result<(), String> {
+        match self.values.get(self.position) {
+            Some(extra) => Err(format!("unexpected argument: {}", extra)),
+            None => Ok(()),
+        }
+    }
+}
+
+enum Command {
+    Estop,
+    MdPwm(u8, i16),
+    MdSpeed(u8, i16),
+    MdAngle(u8, i16),
+    MdLimSw(u8, u8, i16, i16),
+    SdPower(u8, u8, i16),
+    SdPowers(u8, i16, i16),
+    SmdAngle(u8, u8, i16),
+    SmdAngles(u8, i16, i16),
+    BlMdCurrent(u8, u8, i16),
+    BlMdVelocity(u8, u8, i16),
+    SrColor(u8, u8, u8, f32),
+    SrStart,
+    SrStop,
+    MdStatus(u8),
+    SdStatus(u8),
+    SmdStatus(u8),
+    BlMdStatus(u8, u8),
+    SrStatus,
+}
+
+fn parse_command(args: &mut Args) -> Result<Command, String> {
+    let command = match args.next("COMMAND")?.as_str() {
+        "estop" => Command::Estop,
+        "md" => match args.next("md command")?.as_str() {
+            "pwm" => Command::MdPwm(args.u8("ADDR")?, args.i16("POWER")?),
+            "speed" => Command::MdSpeed(args.u8("ADDR")?, args.i16("VELOCITY")?),
+            "angle" => Command::MdAngle(args.u8("ADDR")?, args.i16("ANGLE")?),
+            "limsw" => Command::MdLimSw(
+                args.u8("ADDR")?,
+                args.u8("PORT")?,
+                args.i16("POWER")?,
+                args.i16("AFTER_POWER")?,
+            ),
+            other => return Err(format!("unknown md command: {}", other)),
+        },
+        "sd" => match args.next("sd command")?.as_str() {
+            "power" => Command::SdPower(args.u8("ADDR")?, args.u8("PORT")?, args.i16("POWER")?),
+            "powers" => {
+                Command::SdPowers(args.u8("ADDR")?, args.i16("POWER_0")?, args.i16("POWER_1")?)
+            }
+            other => return Err(format!("unknown sd command: {}", other)),
+        },
+        "smd" => match args.next("smd command")?.as_str() {
+            "angle" => Command::SmdAngle(args.u8("ADDR")?, args.u8("PORT")?, args.i16("ANGLE")?),
+            "angles" => {
+                Command::SmdAngles(args.u8("ADDR")?, args.i16("ANGLE_0")?, args.i16("ANGLE_1")?)
+            }
+            other => return Err(format!("unknown smd command: {}", other)),
+        },
+        "blmd" => match args.next("blmd command")?.as_str() {
+            "current" => Command::BlMdCurrent(
+                args.u8("ADDR")?,
+                args.u8("CONTROLLER_ID")?,
+                args.i16("CURRENT")?,
+            ),
+            "velocity" => Command::BlMdVelocity(
+                args.u8("ADDR")?,
+                args.u8("CONTROLLER_ID")?,
+                args.i16("VELOCITY")?,
+            ),
+            other => return Err(format!("unknown blmd command: {}", other)),
+        },
+        "sr" => match args.next("sr command")?.as_str() {
+            "color" => {
+                let (red, green, blue) = (args.u8("RED")?, args.u8("GREEN")?, args.u8("BLUE")?);
+                let freq = match args.optional() {
+                    Some(freq) => freq
+                        .parse()
+                        .map_err(|_| format!("invalid <FREQ>: {}", freq))?,
+                    None => 0.0,
+                };
+                Command::SrColor(red, green, blue, freq)
+            }
+            "start" => Command::SrStart,
+            "stop" => Command::SrStop,
+            other => return Err(format!("unknown sr command: {}", other)),
+        },
+        "status" => match args.next("md|sd|smd|blmd|sr")?.as_str() {
+            "md" => Command::MdStatus(args.u8("ADDR")?),
+            "sd" => Command::SdStatus(args.u8("ADDR")?),
+            "smd" => Command::SmdStatus(args.u8("ADDR")?),
+            "blmd" => Command::BlMdStatus(args.u8("ADDR")?, args.u8("CONTROLLER_ID")?),
+            "sr" => Command::SrStatus,
+            other => return Err(format!("unknown device type: {}", other)),
+        },
+        other => return Err(format!("unknown command: {}", other)),
+    };
+    args.finish()?;
+    Ok(command)
+}
+
+fn execute(handle: &impl HandleTrait, command: &Command) -> Result<Output, Error> {
+    match *command {
+        Command::Estop => send_emergency(handle).map(Output::Sent),
+        Command::MdPwm(address, power) => md::send_pwm(handle, address, power).map(Output::Md),
+        Command::MdSpeed(address, velocity) => {
+            md::send_speed(handle, address, velocity).map(Output::Md)
+        }
+        Command::MdAngle(address, angle) => md::send_angle(handle, address, angle).map(Output::Md),
+        Command::MdLimSw(address, port, power, after_power) => {
+            md::send_limsw(handle, address, port, power, after_power).map(Output::Md)
+        }
+        Command::SdPower(address, port, power) => {
+            sd::send_power(handle, address, port, power).map(Output::Sd)
+        }
+        Command::SdPowers(address, power_0, power_1) => {
+            sd::send_powers(handle, address, power_0, power_1).map(Output::Sd)
+        }
+        Command::SmdAngle(address, port, angle) => {
+            smd::send_angle(handle, address, port, angle).map(Output::Smd)
+        }
+        Command::SmdAngles(address, angle_0, angle_1) => {
+            smd::send_angles(handle, address, angle_0, angle_1).map(Output::Smd)
+        }
+        Command::BlMdCurrent(address, controller_id, current) => {
+            blmd::send_current(handle, address, controller_id, current).map(Output::BlMd)
+        }
+        Command::BlMdVelocity(address, controller_id, velocity) => {
+            blmd::send_velocity(handle, address, controller_id, velocity).map(Output::BlMd)
+        }
+        Command::SrColor(red, green, blue, freq) => {
+            sr::send_colors(handle, red, green, blue, freq, 5000).map(|_| Output::Done)
+        }
+        Command::SrStart => sr::send_start(handle, 5000).map(|_| Output::Done),
+        Command::SrStop => sr::send_stop(handle).map(|_| Output::Done),
+        Command::MdStatus(address) => {
+            md::request_status(handle, address)?;
+            md::receive_status(handle, address).map(Output::Md)
+        }
+        Command::SdStatus(address) => {
+            sd::request_status(handle, address)?;
+            sd::receive_status(handle, address).map(Output::Sd)
+        }
+        Command::SmdStatus(address) => {
+            smd::request_status(handle, address)?;
+            smd::receive_status(handle, address).map(Output::Smd)
+        }
+        Command::BlMdStatus(address, controller_id) => {
+            blmd::request_status(handle, address, controller_id)?;
+            blmd::receive_status(handle, controller_id).map(Output::BlMd)
+        }
+        Command::SrStatus => {
+            sr::request_status(handle)?;
+            receive_sr_status(handle).map(Output::Sr)
+        }
+    }
+}
+
+/// Reads frames until the SR device replies.
+fn receive_sr_status(handle: &impl HandleTrait) -> Result<sr::SrStatus, Error> {
+    let mut receive_buf = [0; 8];
+    loop {
+        handle.read_bulk(&mut receive_buf, time::Duration::from_millis(5000))?;
+        if receive_buf[0] == device_type::SR {
+            return Ok(sr::decode_status(&receive_buf));
+        }
+    }
+}
+
+fn main() {
+    let mut transport = cli::default_transport();
+    let mut json = false;
+    let mut args = env::args().skip(1).peekable();
+    while let Some(arg) = args.next_if(|arg| arg.starts_with("--")) {
+        match arg.as_str() {
+            "--usb" | "--grpc" => {
+                transport =
+                    cli::parse_transport(&arg, &mut args).unwrap_or_else(|message| fail(&message))
+            }
+            "--json" => json = true,
+            "--help" => {
+                println!("{}", USAGE);
+                return;
+            }
+            _ => fail(&format!("unknown option: {}", arg)),
+        }
+    }
+
+    let mut values: Vec<String> = args.collect();
+    let watch = match values.iter().position(|value| value == "--watch") {
+        Some(index) => {
+            values.remove(index);
+            let interval = match values.get(index).and_then(|value| value.parse().ok()) {
+                Some(interval) => {
+                    values.remove(index);
+                    interval
+                }
+                None => 100,
+            };
+            if values.first().map(String::as_str) != Some("status") {
+                fail("--watch is only supported by the status command");
+            }
+            Some(time::Duration::from_millis(interval))
+        }
+        None => None,
+    };
+    let command = parse_command(&mut Args {
+        values,
+        position: 0,
+    })
+    .unwrap_or_else(|message| fail(&message));
+
+    let handle = transport.open().unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(1);
+    });
+
+    loop {
+        match execute(&handle, &command) {
+            Ok(output) if json => println!("{}", output.json()),
+            Ok(output) => println!("{}", output.text()),
+            Err(e) => {
+                if json {
+                    println!("{}", json!({ "error": e.to_string() }));
+                } else {
+                    eprintln!("{}", e);
+                }
+                if watch.is_none() {
+                    process::exit(1);
+                }
+            }
+        }
+        match watch {
+            Some(interval) => thread::sleep(interval),
+            None => break,
+        }
+    }
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("{}\n\n{}", message, USAGE);
+    process::exit(2);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use motor_lib::recording::{Direction, Outcome, Record};
+    use motor_lib::ReplayHandle;
+
+    fn record(direction: Direction, data: [u8; 8]) -> Record {
+        Record {
+            direction,
+            outcome: Outcome::Ok,
+            timestamp: time::Duration::ZERO,
+            data: data.to_vec(),
+        }
+    }
+
+    #[test]
+    fn status_requests_before_reading() {
+        let handle = ReplayHandle::new(vec![
+            record(
+                Direction::Write,
+                [0x01, 0x60, md::mode::STATUS, 0, 0, 0, 0, 0],
+            ),
+            record(Direction::Read, [0x01, 0, 0, 90, 0, 100, 0, 0]),
+            record(
+                Direction::Write,
+                [device_type::SR, 0x60, sr::mode::STATUS, 0, 0, 0, 0, 0],
+            ),
+            record(Direction::Read, [device_type::SR, 0, 0, 1, 0, 255, 0, 8]),
+        ]);
+        let Output::Md(status) = execute(&handle, &Command::MdStatus(0x01)).unwrap() else {
+            panic!("not an MD status");
+        };
+        assert_eq!(status.angle, 90);
+        let Output::Sr(status) = execute(&handle, &Command::SrStatus).unwrap() else {
+            panic!("not an SR status");
+        };
+        assert_eq!((status.color.red, status.freq), (255, 2.0));
+        assert!(handle.divergences().is_empty());
+        assert!(handle.is_finished());
+    }
+
+    #[test]
+    fn parses_status_commands() {
+        let parse = |values: &[&str]| {
+            parse_command(&mut Args {
+                values: values.iter().map(|value| value.to_string()).collect(),
+                position: 0,
+            })
+        };
+        assert!(matches!(
+            parse(&["status", "blmd", "0x20", "3"]),
+            Ok(Command::BlMdStatus(0x20, 3))
+        ));
+        assert!(matches!(parse(&["status", "sr"]), Ok(Command::SrStatus)));
+        assert!(parse(&["status", "sr", "1"]).is_err());
+        assert!(parse(&["status", "blmd", "3"]).is_err());
+    }
+}
@@ -0,0 +1,278 @@
+//! The USB adapters of the server, told apart by their serial numbers.
+//!
+//! Each bus is carried by one adapter. An adapter that arrives is opened and given to the
+//! bus with its serial number, or else to the bus that takes any adapter. An adapter that
+//! leaves only closes the handle of its own bus.
+
+use rusb::{Context, Device, HotplugBuilder, UsbContext};
+use std::sync::Arc;
+use std::time;
+
+use crate::bus::SharedHandle;
+use crate::config::Config;
+
+/// How often the adapters are looked for when libusb cannot report their arrival.
+const RECONNECT_INTERVAL: time::Duration = time::Duration::from_millis(1000);
+
+/// The adapter of one bus.
+struct Slot {
+    name: String,
+    serial: Option<String>,
+    handle: SharedHandle,
+}
+
+pub struct Adapters {
+    context: Context,
+    config: Arc<Config>,
+    slots: Vec<Slot>,
+}
+
+/// Returns whether `handle` is open on `device`.
+fn is_open_on(handle: &SharedHandle, device: &Device<Context>) -> bool {
+    handle.read().unwrap().as_ref().is_some_and(|handle| {
+        let open = handle.device();
+        open.bus_number() == device.bus_number() && open.address() == device.address()
+    })
+}
+
+/// Returns the slot of an adapter with `serial`: the bus with that serial, or else the bus
+/// that takes any adapter.
+fn slot_for<'a>(slots: &'a [Slot], serial: Option<&str>) -> Option<&'a Slot> {
+    slots
+        .iter()
+        .find(|slot| serial.is_some() && slot.serial.as_deref() == serial)
+        .or_else(|| slots.iter().find(|slot| slot.serial.is_none()))
+}
+
+impl Adapters {
+    pub fn new(context: Context, config: Arc<Config>) -> Adapters {
+        Adapters {
+            context,
+            config,
+            slots: Vec::new(),
+        }
+    }
+
+    /// Adds the bus `name`, whose handle is opened on the adapter with `serial`, or on any
+    /// adapter if `None`.
+    pub fn add(&mut self, name: &str, serial: Option<String>, handle: SharedHandle) {
+        self.slots.push(Slot {
+            name: name.to_string(),
+            serial,
+            handle,
+        });
+    }
+
+    /// Opens `device` and gives it to its bus, if it is an adapter that is not open yet.
+    fn attach(&self, device: &Device<Context>) -> Result<(), String> {
+        let config = &self.config;
+        let descriptor = device
+            .device_descriptor()
+            .map_err(|e| format!("cannot read a device descriptor: {}", e))?;
+        if descriptor.vendor_id() != config.vendor_id
+            || descriptor.product_id() != config.product_id
+            || self
+                .slots
+                .iter()
+                .any(|slot| is_open_on(&slot.handle, device))
+        {
+            return Ok(());
+        }
+        let name = format!(
+            "{:04x}:{:04x} at {:03}:{:03}",
+            config.vendor_id,
+            config.product_id,
+            device.bus_number(),
+            device.address()
+        );
+        let handle = device.open().map_err(|e| match e {
+            rusb::Error::Access => format!(
+                "cannot open {}: permission denied, check the udev rules of the device",
+                name
+            ),
+            e => format!("cannot open {}: {}", name, e),
+        })?;
+        let serial = handle.read_serial_number_string_ascii(&descriptor).ok();
+        let slot = slot_for(&self.slots, serial.as_deref()).ok_or_else(|| {
+            format!(
+                "no bus has the serial {} of {}",
+                serial.as_deref().unwrap_or("(none)"),
+                name
+            )
+        })?;
+        let mut locked_handle = slot.handle.write().unwrap();
+        if locked_handle.is_some() {
+            return Err(format!(
+                "bus {} already has an adapter, ignoring {}",
+                slot.name, name
+            ));
+        }
+        handle.set_auto_detach_kernel_driver(true).unwrap_or(());
+        handle
+            .claim_interface(config.interface)
+            .map_err(|e| match e {
+                rusb::Error::Busy => format!(
+                    "cannot claim interface {} of {}: another program is using it",
+                    config.interface, name
+                ),
+                e => format!(
+                    "cannot claim interface {} of {}: {}",
+                    config.interface, name, e
+                ),
+            })?;
+        *locked_handle = Some(handle);
+        info!(
+            "Adapter {} ({}) arrived on bus {}",
+            serial.as_deref().unwrap_or("without serial"),
+            name,
+            slot.name
+        );
+        Ok(())
+    }
+
+    /// Closes the handle open on `device`, if any.
+    fn detach(&self, device: &Device<Context>) {
+        if let Some(slot) = self
+            .slots
+            .iter()
+            .find(|slot| is_open_on(&slot.handle, device))
+        {
+            *slot.handle.write().unwrap() = None;
+            info!("Adapter of bus {} left", slot.name);
+        }
+    }
+
+    /// Opens every adapter that is connected and not open yet. Errors are only logged at
+    /// the debug level if `quiet`, so polling does not repeat them.
+    pub fn scan(&self, quiet: bool) {
+        let devices = match self.context.devices() {
+            Ok(devices) => devices,
+            Err(e) => {
+                error!("cannot list the USB devices: {}", e);
+                return;
+            }
+        };
+        for device in devices.iter() {
+            if let Err(e) = self.attach(&device) {
+                if quiet {
+                    debug!("{}", e);
+                } else {
+                    warn!("{}", e);
+                }
+            }
+        }
+    }
+
+    /// Logs the buses that have no adapter.
+    pub fn report_missing(&self) {
+        for slot in &self.slots {
+            if slot.handle.read().unwrap().is_none() {
+                match &slot.serial {
+                    Some(serial) => warn!(
+                        "the adapter {} of bus {} is not connected, waiting for it",
+                        serial, slot.name
+                    ),
+                    None => warn!("bus {} has no adapter, waiting for one", slot.name),
+                }
+            }
+        }
+    }
+
+    /// Watches for the arrival and removal of the adapters.
+    pub fn watch(self: Arc<Self>) -> Result<(), rusb::Error> {
+        if rusb::has_hotplug() {
+            let context = self.context.clone();
+            let reg: Option<rusb::Registration<Context>> = Some(
+                HotplugBuilder::new()
+                    .enumerate(false)
+                    .vendor_id(self.config.vendor_id)
+                    .product_id(self.config.product_id)
+                    .register(&context, Box::new(HotPlugHandler::new(self)))?,
+            );
+            tokio::task::spawn_blocking(move || {
+                // regの所有権をthreadに移している．
+                let _reg = reg;
+                loop {
+                    if let Err(e) = context.handle_events(None) {
+                        error!("USB event error: {}", e);
+                        std::thread::sleep(RECONNECT_INTERVAL);
+                    }
+                }
+            });
+        } else {
+            // Only the arrivals are polled: the reader of a bus closes a removed adapter.
+            warn!("libusb cannot report hotplug events, polling for the adapters");
+            std::thread::spawn(move || loop {
+                std::thread::sleep(RECONNECT_INTERVAL);
+                self.scan(true);
+            });
+        }
+        Ok(())
+    }
+}
+
+struct HotPlugHandler {
+    adapters: Arc<Adapters>,
+}
+
+impl HotPlugHandler {
+    fn new(adapters: Arc<Adapters>) -> HotPlugHandler {
+        HotPlugHandler { adapters }
+    }
+}
+
+impl rusb::Hotplug<Context> for HotPlugHandler {
+    fn device_arrived(&mut self, device: Device<Context>) {
+        if let Err(e) = self.adapters.attach(&device) {
+            error!("{}", e);
+        }
+    }
+
+    fn device_left(&mut self, device: Device<Context>) {
+        self.adapters.detach(&device);
+    }
+}
+
+impl Drop for HotPlugHandler {
+    fn drop(&mut self) {
+        debug!("HotPlugHandler dropped");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::RwLock;
+
+    fn slot(name: &str, serial: Option<&str>) -> Slot {
+        Slot {
+            name: name.to_string(),
+            serial: serial.map(str::to_string),
+            handle: Arc::new(RwLock::new(None)),
+        }
+    }
+
+    fn name(slot: Option<&Slot>) -> Option<&str> {
+        slot.map(|slot| slot.name.as_str())
+    }
+
+    #[test]
+    fn an_exact_serial_wins_over_any_adapter() {
+        let slots = [
+            slot("spare", None),
+            slot("front", Some("205F32A4594B")),
+            slot("rear", Some("2061367D4E4B")),
+        ];
+        assert_eq!(name(slot_for(&slots, Some("205F32A4594B"))), Some("front"));
+        assert_eq!(name(slot_for(&slots, Some("2061367D4E4B"))), Some("rear"));
+        assert_eq!(name(slot_for(&slots, Some("0000"))), Some("spare"));
+        assert_eq!(name(slot_for(&slots, None)), Some("spare"));
+    }
+
+    #[test]
+    fn an_unknown_serial_has_no_bus_without_one_that_takes_any() {
+        let slots = [slot("front", Some("205F32A4594B"))];
+        assert_eq!(name(slot_for(&slots, Some("0000"))), None);
+        assert_eq!(name(slot_for(&slots, None)), None);
+    }
+}
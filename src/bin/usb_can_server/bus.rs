@@ -0,0 +1,317 @@
+//! The USB adapter shared by every service of the server.
+//!
+//! A single reader thread reads every frame from the adapter and publishes it to the
+//! subscribers of the bus, and routes it to the clients of the raw `Read` RPC, which used
+//! to read the adapter directly.
+
+use motor_lib::frame;
+use motor_lib::recording::Direction;
+use motor_lib::HandleTrait;
+use rusb::constants::{LIBUSB_ENDPOINT_IN, LIBUSB_ENDPOINT_OUT};
+use std::cell::RefCell;
+use std::sync::{Arc, RwLock};
+use std::time::{self, Instant, SystemTime};
+use tokio::sync::{broadcast, Mutex, OwnedMutexGuard};
+
+use crate::lease::Lease;
+use crate::router::{ClientId, Router};
+
+pub type SharedHandle = Arc<RwLock<Option<rusb::DeviceHandle<rusb::Context>>>>;
+
+/// How long the reader waits for a frame before it checks the device again.
+const READ_POLL: time::Duration = time::Duration::from_millis(100);
+/// How long the reader waits after an error or while no device is connected.
+const RETRY_DELAY: time::Duration = time::Duration::from_millis(100);
+/// The number of frames a slow subscriber may fall behind before it misses frames.
+const SUBSCRIBER_CAPACITY: usize = 1024;
+
+/// A frame read from the adapter.
+#[derive(Debug, Clone, Copy)]
+pub struct Received {
+    pub frame: [u8; 8],
+    pub time: SystemTime,
+}
+
+#[derive(Debug)]
+pub struct Bus {
+    name: String,
+    handle: SharedHandle,
+    frames: broadcast::Sender<Received>,
+    router: Router,
+    lease: Lease,
+    /// The bulk endpoint of the adapter, without its direction bit.
+    endpoint: u8,
+    /// The timeout of the transfers the server makes on its own.
+    timeout: time::Duration,
+    // Held for a whole command and its reply, so a reply is matched to one request.
+    transaction: Arc<Mutex<()>>,
+}
+
+impl Bus {
+    /// Creates the bus named `name` on `endpoint` of its adapter and starts its reader
+    /// thread.
+    pub fn start(
+        name: String,
+        handle: SharedHandle,
+        endpoint: u8,
+        timeout: time::Duration,
+    ) -> Arc<Bus> {
+        let bus = Arc::new(Bus {
+            name,
+            handle,
+            frames: broadcast::channel(SUBSCRIBER_CAPACITY).0,
+            router: Router::default(),
+            lease: Lease::default(),
+            endpoint,
+            timeout,
+            transaction: Arc::new(Mutex::new(())),
+        });
+        let reader = Arc::clone(&bus);
+        std::thread::spawn(move || reader.read_loop());
+        bus
+    }
+
+    fn read_loop(&self) {
+        let mut frame = [0; 8];
+        loop {
+            let result = match *self.handle.read().unwrap() {
+                Some(ref handle) => {
+                    handle.read_bulk(LIBUSB_ENDPOINT_IN | self.endpoint, &mut frame, READ_POLL)
+                }
+                None => Err(rusb::Error::NoDevice),
+            };
+            match result {
+                Ok(size) if size == frame.len() => self.publish(frame),
+                Ok(_) | Err(rusb::Error::Timeout) => {}
+                Err(rusb::Error::NoDevice) => {
+                    let mut handle = self.handle.write().unwrap();
+                    if handle.take().is_some() {
+                        info!("Adapter of bus {} left", self.name);
+                    }
+                    drop(handle);
+                    std::thread::sleep(RETRY_DELAY);
+                }
+                Err(e) => {
+                    error!("Read error on bus {}: {}", self.name, e);
+                    std::thread::sleep(RETRY_DELAY);
+                }
+            }
+        }
+    }
+
+    fn publish(&self, frame: [u8; 8]) {
+        debug!(
+            "{}: read {}",
+            self.name,
+            frame::decode(Direction::Read, &frame)
+        );
+        let received = Received {
+            frame,
+            time: SystemTime::now(),
+        };
+        // Sending only fails when nobody is subscribed.
+        let _ = self.frames.send(received);
+        self.router.route(frame);
+    }
+
+    /// Returns a receiver of every frame read from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<Received> {
+        self.frames.subscribe()
+    }
+
+    /// Returns whether the adapter is connected.
+    pub fn is_connected(&self) -> bool {
+        self.handle.read().unwrap().is_some()
+    }
+
+    /// Takes the oldest frame routed to `client`, waiting up to `timeout` for one.
+    pub fn read_as(&self, client: &str, timeout: time::Duration) -> Option<[u8; 8]> {
+        self.router.read(client, timeout)
+    }
+
+    /// Returns the timeout of the transfers the server makes on its own, and of the
+    /// requests that set no deadline.
+    pub fn timeout(&self) -> time::Duration {
+        self.timeout
+    }
+
+    pub fn lease(&self) -> &Lease {
+        &self.lease
+    }
+
+    /// Writes a frame for `client`, unless another client holds the lease.
+    pub fn write_as(
+        &self,
+        client: &str,
+        data: &[u8],
+        timeout: time::Duration,
+    ) -> Result<usize, motor_lib::Error> {
+        self.lease.check(client, data)?;
+        self.write(data, timeout)
+    }
+
+    /// Same as `write_as`, and routes the reply to the `Read` requests of `client`.
+    pub fn write_routed(
+        &self,
+        client: &str,
+        data: &[u8],
+        timeout: time::Duration,
+    ) -> Result<usize, motor_lib::Error> {
+        self.lease.check(client, data)?;
+        self.router.expect(client, data);
+        self.write(data, timeout)
+    }
+
+    pub fn write(&self, data: &[u8], timeout: time::Duration) -> Result<usize, motor_lib::Error> {
+        match *self.handle.read().unwrap() {
+            Some(ref handle) => {
+                if let Ok(buf) = <[u8; 8]>::try_from(data) {
+                    debug!(
+                        "{}: write {}",
+                        self.name,
+                        frame::decode(Direction::Write, &buf)
+                    );
+                }
+                Ok(handle.write_bulk(LIBUSB_ENDPOINT_OUT | self.endpoint, data, timeout)?)
+            }
+            None => Err(motor_lib::Error::RUsbError(rusb::Error::NoDevice)),
+        }
+    }
+
+    /// Waits for the other transactions until `deadline`, and returns a handle whose reads
+    /// only see the frames received from now on, and whose writes are made for `client`.
+    /// The reads of the transaction time out at `deadline`.
+    pub async fn transaction(
+        self: &Arc<Self>,
+        client: ClientId,
+        deadline: Instant,
+    ) -> Result<Transaction, motor_lib::Error> {
+        let guard =
+            tokio::time::timeout_at(deadline.into(), Arc::clone(&self.transaction).lock_owned())
+                .await
+                .map_err(|_| motor_lib::Error::RUsbError(rusb::Error::Timeout))?;
+        Ok(Transaction {
+            bus: Arc::clone(self),
+            client,
+            frames: RefCell::new(self.subscribe()),
+            written: RefCell::new(Vec::new()),
+            deadline,
+            _guard: guard,
+        })
+    }
+}
+
+/// Only for commands without a reply, such as the emergency stop. Replies are read through
+/// a `Transaction`.
+impl HandleTrait for Bus {
+    fn read_bulk(
+        &self,
+        _data: &mut [u8],
+        _timeout: time::Duration,
+    ) -> Result<usize, motor_lib::Error> {
+        Err(motor_lib::Error::Unsupported(
+            "reading the bus outside of a transaction",
+        ))
+    }
+
+    fn write_bulk(&self, data: &[u8], timeout: time::Duration) -> Result<usize, motor_lib::Error> {
+        self.write(data, timeout)
+    }
+}
+
+/// A command and its reply, as a HandleTrait for the device modules. Must be used from a
+/// blocking thread of the runtime.
+pub struct Transaction {
+    bus: Arc<Bus>,
+    client: ClientId,
+    frames: RefCell<broadcast::Receiver<Received>>,
+    written: RefCell<Vec<Vec<u8>>>,
+    // The device modules read until the reply of their device arrives, so a busy bus
+    // would keep them reading forever without it.
+    deadline: Instant,
+    _guard: OwnedMutexGuard<()>,
+}
+
+impl HandleTrait for Transaction {
+    fn read_bulk(
+        &self,
+        data: &mut [u8],
+        timeout: time::Duration,
+    ) -> Result<usize, motor_lib::Error> {
+        let remaining = self.deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(motor_lib::Error::RUsbError(rusb::Error::Timeout));
+        }
+        let mut frames = self.frames.borrow_mut();
+        let received = tokio::runtime::Handle::current().block_on(async {
+            tokio::time::timeout(timeout.min(remaining), async {
+                loop {
+                    match frames.recv().await {
+                        Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                        result => return result.ok(),
+                    }
+                }
+            })
+            .await
+        });
+        match received {
+            Ok(Some(received)) => {
+                let len = data.len().min(received.frame.len());
+                data[..len].copy_from_slice(&received.frame[..len]);
+                Ok(len)
+            }
+            Ok(None) => Err(motor_lib::Error::RUsbError(rusb::Error::Io)),
+            Err(_) => Err(motor_lib::Error::RUsbError(rusb::Error::Timeout)),
+        }
+    }
+
+    fn write_bulk(&self, data: &[u8], timeout: time::Duration) -> Result<usize, motor_lib::Error> {
+        let size = self.bus.write_as(&self.client, data, timeout)?;
+        self.written.borrow_mut().push(data.to_vec());
+        Ok(size)
+    }
+}
+
+impl Transaction {
+    /// Returns the frames written during the transaction.
+    pub fn into_written(self) -> Vec<Vec<u8>> {
+        self.written.into_inner()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn transaction_times_out_on_a_busy_bus() {
+        let timeout = time::Duration::from_secs(5);
+        let bus = Bus::start("test".to_string(), Arc::new(RwLock::new(None)), 1, timeout);
+        let done = Arc::new(AtomicBool::new(false));
+        let publisher = {
+            let (bus, done) = (Arc::clone(&bus), Arc::clone(&done));
+            std::thread::spawn(move || {
+                while !done.load(Ordering::Relaxed) {
+                    bus.publish([0x10, 0, 0, 0, 0, 0, 0, 0]);
+                    std::thread::sleep(time::Duration::from_millis(1));
+                }
+            })
+        };
+        let start = Instant::now();
+        let deadline = start + time::Duration::from_millis(200);
+        let transaction = bus.transaction("a".to_string(), deadline).await.unwrap();
+        // The MD at 0x00 is absent, only the frames of 0x10 arrive.
+        let result =
+            tokio::task::spawn_blocking(move || motor_lib::md::receive_status(&transaction, 0x00))
+                .await
+                .unwrap();
+        assert!(result.unwrap_err().is_timeout());
+        assert!(start.elapsed() < timeout);
+        // The device is released for the next request.
+        let deadline = Instant::now() + time::Duration::from_millis(10);
+        assert!(bus.transaction("b".to_string(), deadline).await.is_ok());
+        done.store(true, Ordering::Relaxed);
+        publisher.join().unwrap();
+    }
+}
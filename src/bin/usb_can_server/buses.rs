@@ -0,0 +1,153 @@
+//! The named buses of the server, and the `Buses` service that lists them.
+//!
+//! Every request is made on the bus named by its `bus` metadata. A server with a single bus
+//! also takes requests without it, so the clients written for one adapter keep working.
+
+use motor_lib::grpc::{pb, BUS_KEY};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use crate::bus::Bus;
+use crate::watchdog::Watchdog;
+
+/// A bus and the watchdog of its clients.
+#[derive(Debug)]
+pub struct Line {
+    pub bus: Arc<Bus>,
+    pub watchdog: Arc<Watchdog>,
+    /// The serial number of the adapter of the bus, or `None` if it takes any adapter.
+    pub serial: Option<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct Buses {
+    lines: BTreeMap<String, Arc<Line>>,
+}
+
+impl Buses {
+    pub fn insert(&mut self, name: String, line: Line) {
+        self.lines.insert(name, Arc::new(line));
+    }
+
+    fn names(&self) -> String {
+        self.lines.keys().cloned().collect::<Vec<_>>().join(", ")
+    }
+
+    /// Returns the bus a request is made on.
+    pub fn select<T>(&self, request: &tonic::Request<T>) -> Result<Arc<Line>, tonic::Status> {
+        let Some(name) = request.metadata().get(BUS_KEY) else {
+            return match self.lines.values().next() {
+                Some(line) if self.lines.len() == 1 => Ok(Arc::clone(line)),
+                _ => Err(tonic::Status::invalid_argument(format!(
+                    "the server has several buses, select one of {} with the `{}` metadata",
+                    self.names(),
+                    BUS_KEY
+                ))),
+            };
+        };
+        let name = String::from_utf8_lossy(name.as_bytes());
+        self.lines.get(name.as_ref()).cloned().ok_or_else(|| {
+            tonic::Status::not_found(format!(
+                "no bus is named {}, the buses are {}",
+                name,
+                self.names()
+            ))
+        })
+    }
+}
+
+#[cfg(test)]
+impl Line {
+    /// A bus without an adapter, whose writes fail with `NoDevice`.
+    pub fn detached(watchdog: Option<std::time::Duration>) -> Line {
+        let timeout = std::time::Duration::from_millis(100);
+        let handle = Arc::new(std::sync::RwLock::new(None));
+        let bus = Bus::start("test".to_string(), handle, 1, timeout);
+        let watchdog = Watchdog::start(Arc::clone(&bus), watchdog, crate::watchdog::Failsafe::Zero);
+        Line {
+            bus,
+            watchdog,
+            serial: None,
+        }
+    }
+}
+
+/// The Buses service.
+#[derive(Debug)]
+pub struct BusesServer {
+    buses: Arc<Buses>,
+}
+
+impl BusesServer {
+    pub fn new(buses: Arc<Buses>) -> BusesServer {
+        BusesServer { buses }
+    }
+}
+
+#[tonic::async_trait]
+impl pb::buses_server::Buses for BusesServer {
+    async fn list_buses(
+        &self,
+        _request: tonic::Request<pb::Empty>,
+    ) -> Result<tonic::Response<pb::BusList>, tonic::Status> {
+        let buses = self
+            .buses
+            .lines
+            .iter()
+            .map(|(name, line)| pb::BusInfo {
+                name: name.clone(),
+                serial: line.serial.clone().unwrap_or_default(),
+                connected: line.bus.is_connected(),
+            })
+            .collect();
+        Ok(tonic::Response::new(pb::BusList { buses }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tonic::Code;
+
+    fn buses(names: &[&str]) -> Buses {
+        let mut buses = Buses::default();
+        for name in names {
+            buses.insert(name.to_string(), Line::detached(None));
+        }
+        buses
+    }
+
+    fn request(bus: Option<&str>) -> tonic::Request<()> {
+        let mut request = tonic::Request::new(());
+        if let Some(bus) = bus {
+            request.metadata_mut().insert(BUS_KEY, bus.parse().unwrap());
+        }
+        request
+    }
+
+    #[test]
+    fn a_single_bus_takes_requests_without_a_name() {
+        let buses = buses(&["default"]);
+        let line = buses.select(&request(None)).unwrap();
+        assert!(Arc::ptr_eq(&line, &buses.lines["default"]));
+        assert!(buses.select(&request(Some("default"))).is_ok());
+    }
+
+    #[test]
+    fn several_buses_need_a_name() {
+        let buses = buses(&["front", "rear"]);
+        let status = buses.select(&request(None)).unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+        assert!(status.message().contains("front, rear"));
+        let line = buses.select(&request(Some("rear"))).unwrap();
+        assert!(Arc::ptr_eq(&line, &buses.lines["rear"]));
+    }
+
+    #[test]
+    fn an_unknown_name_is_not_found() {
+        for names in [&["default"][..], &["front", "rear"]] {
+            let status = buses(names).select(&request(Some("side"))).unwrap_err();
+            assert_eq!(status.code(), Code::NotFound);
+        }
+    }
+}
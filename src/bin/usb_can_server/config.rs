@@ -0,0 +1,336 @@
+//! The settings of the server, read from a TOML file given with `--config` and overridden
+//! by the other flags. Every key is optional.
+//!
+//! Each adapter carries a named bus, and is told apart from the others by its serial
+//! number. Without a `buses` table the server has a single bus named `default`, which takes
+//! the first adapter to arrive.
+//!
+//! ```toml
+//! bind = "127.0.0.1:50051"   # or "unix:///run/usb_can_server.sock"
+//! socket_mode = 0o660         # the permissions of the Unix domain socket
+//! vendor_id = 0x483
+//! product_id = 0x5740
+//! interface = 1
+//! endpoint = 1
+//! timeout_ms = 5000
+//! watchdog_ms = 1000    # 0 disables the watchdog
+//! failsafe = "zero"     # or "emergency"
+//! log = "info"          # "error", "warn", "info" or "debug"
+//!
+//! [buses.front]
+//! serial = "205F32A4594B"
+//!
+//! [buses.rear]
+//! serial = "2061367D4E4B"
+//! ```
+
+use motor_lib::cli;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::{process, time};
+
+use crate::log::Level;
+use crate::watchdog::Failsafe;
+
+pub const USAGE: &str = "Usage: usb_can_server [--config PATH] [--bind ADDRESS] [--socket-mode MODE] \
+[--usb VID:PID] [--bus NAME[=SERIAL]]... [--interface N] [--endpoint N] [--timeout MS] [--watchdog MS] \
+[--failsafe zero|emergency] [-v]... [-q]... [ADDRESS]";
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// The address the server listens on, or `unix://PATH` for a Unix domain socket.
+    pub bind: String,
+    /// The permissions of the Unix domain socket, such as `0o660` to let only the owner and
+    /// group of the server connect. The umask applies if unset.
+    pub socket_mode: Option<u32>,
+    pub vendor_id: u16,
+    pub product_id: u16,
+    /// The interface of the adapter that carries the bus.
+    pub interface: u8,
+    /// The bulk endpoint of the interface, without its direction bit.
+    pub endpoint: u8,
+    /// The default timeout of the USB transfers, for requests that do not set a deadline.
+    pub timeout_ms: u64,
+    /// How long a client that has sent output commands may stay silent before its devices
+    /// are stopped, or 0 to disable the watchdog.
+    pub watchdog_ms: u64,
+    pub failsafe: Failsafe,
+    pub log: Level,
+    pub buses: BTreeMap<String, BusConfig>,
+}
+
+/// The name of the bus of a server without a `buses` table.
+pub const DEFAULT_BUS: &str = "default";
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BusConfig {
+    /// The serial number of the adapter of the bus. A bus without one takes any adapter
+    /// that no other bus claims.
+    pub serial: Option<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            bind: "127.0.0.1:50051".to_string(),
+            socket_mode: None,
+            vendor_id: cli::VENDOR_ID,
+            product_id: cli::PRODUCT_ID,
+            interface: cli::INTERFACE,
+            endpoint: 1,
+            timeout_ms: 5000,
+            watchdog_ms: 1000,
+            failsafe: Failsafe::Zero,
+            log: Level::Info,
+            buses: BTreeMap::new(),
+        }
+    }
+}
+
+fn parse_number<T: TryFrom<u64>>(name: &str, value: &str) -> Result<T, String> {
+    cli::parse_number(value).ok_or(format!("invalid value for {}: {}", name, value))
+}
+
+impl Config {
+    /// Reads a configuration file.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+        toml::from_str(&contents).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    /// Reads the configuration file given with `--config`, if any, then applies the other
+    /// flags of the command line. Prints the usage and exits on `--help`.
+    pub fn from_args(args: Vec<String>) -> Result<Self, String> {
+        let mut config = match args.iter().position(|arg| arg == "--config") {
+            Some(index) => Config::from_file(
+                args.get(index + 1)
+                    .ok_or("--config needs a value".to_string())?,
+            )?,
+            None => Config::default(),
+        };
+        let mut verbosity = 0;
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| args.next().ok_or(format!("{} needs a value", name));
+            match arg.as_str() {
+                "--config" => {
+                    value("--config")?;
+                }
+                "--bind" => config.bind = value("--bind")?,
+                "--socket-mode" => {
+                    let mode = value("--socket-mode")?;
+                    let octal = mode.strip_prefix("0o").unwrap_or(&mode);
+                    config.socket_mode = Some(
+                        u32::from_str_radix(octal, 8)
+                            .map_err(|_| format!("invalid socket mode: {}", mode))?,
+                    );
+                }
+                "--usb" => {
+                    let device = value("--usb")?;
+                    (config.vendor_id, config.product_id) =
+                        cli::parse_usb_id(&device).ok_or(format!("invalid VID:PID: {}", device))?;
+                }
+                "--bus" => {
+                    let bus = value("--bus")?;
+                    let (name, serial) = match bus.split_once('=') {
+                        Some((name, serial)) => (name, Some(serial.to_string())),
+                        None => (bus.as_str(), None),
+                    };
+                    config.buses.insert(name.to_string(), BusConfig { serial });
+                }
+                "--interface" => config.interface = parse_number(&arg, &value(&arg)?)?,
+                "--endpoint" => config.endpoint = parse_number(&arg, &value(&arg)?)?,
+                "--timeout" => config.timeout_ms = parse_number(&arg, &value(&arg)?)?,
+                "--watchdog" => config.watchdog_ms = parse_number(&arg, &value(&arg)?)?,
+                "--failsafe" => {
+                    config.failsafe = match value("--failsafe")?.as_str() {
+                        "zero" => Failsafe::Zero,
+                        "emergency" => Failsafe::Emergency,
+                        other => return Err(format!("unknown failsafe: {}", other)),
+                    }
+                }
+                "--verbose" | "-v" => verbosity += 1,
+                "--quiet" | "-q" => verbosity -= 1,
+                "--help" | "-h" => {
+                    println!("{}", USAGE);
+                    process::exit(0);
+                }
+                _ if arg.starts_with('-') => return Err(format!("unknown argument: {}", arg)),
+                _ => config.bind = arg,
+            }
+        }
+        config.log = config.log.shift(verbosity);
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if !(1..=15).contains(&self.endpoint) {
+            return Err(format!("endpoint {} is not in 1..=15", self.endpoint));
+        }
+        if self.socket_mode.is_some_and(|mode| mode > 0o777) {
+            return Err(format!(
+                "socket mode {:o} is not a permission mode",
+                self.socket_mode.unwrap_or_default()
+            ));
+        }
+        if self.timeout_ms == 0 {
+            return Err("the timeout must not be 0".to_string());
+        }
+        let mut serials = BTreeMap::new();
+        for (name, bus) in &self.buses {
+            if name.is_empty() || name.parse::<tonic::metadata::AsciiMetadataValue>().is_err() {
+                return Err(format!("invalid bus name: {:?}", name));
+            }
+            if let Some(other) = serials.insert(&bus.serial, name) {
+                return Err(match &bus.serial {
+                    Some(serial) => format!(
+                        "buses {} and {} have the same serial {}",
+                        other, name, serial
+                    ),
+                    None => format!(
+                        "buses {} and {} both take any adapter, give them a serial",
+                        other, name
+                    ),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the buses to serve, by name.
+    pub fn buses(&self) -> BTreeMap<String, BusConfig> {
+        if self.buses.is_empty() {
+            return BTreeMap::from([(DEFAULT_BUS.to_string(), BusConfig::default())]);
+        }
+        self.buses.clone()
+    }
+
+    pub fn timeout(&self) -> time::Duration {
+        time::Duration::from_millis(self.timeout_ms)
+    }
+
+    /// Returns the watchdog deadline, or `None` if the watchdog is disabled.
+    pub fn watchdog(&self) -> Option<time::Duration> {
+        (self.watchdog_ms > 0).then(|| time::Duration::from_millis(self.watchdog_ms))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs};
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn reads_every_key_of_a_file() {
+        let config: Config = toml::from_str(
+            r#"
+            bind = "unix:///run/usb_can_server.sock"
+            socket_mode = 0o660
+            vendor_id = 0x1234
+            product_id = 0x5678
+            interface = 2
+            endpoint = 3
+            timeout_ms = 100
+            watchdog_ms = 0
+            failsafe = "emergency"
+            log = "debug"
+
+            [buses.front]
+            serial = "205F32A4594B"
+
+            [buses.rear]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.bind, "unix:///run/usb_can_server.sock");
+        assert_eq!(config.socket_mode, Some(0o660));
+        assert_eq!((config.vendor_id, config.product_id), (0x1234, 0x5678));
+        assert_eq!((config.interface, config.endpoint), (2, 3));
+        assert_eq!(config.timeout(), time::Duration::from_millis(100));
+        assert_eq!(config.watchdog(), None);
+        assert_eq!(config.failsafe, Failsafe::Emergency);
+        assert_eq!(config.log, Level::Debug);
+        assert_eq!(
+            config.buses["front"].serial.as_deref(),
+            Some("205F32A4594B")
+        );
+        assert_eq!(config.buses["rear"].serial, None);
+        config.validate().unwrap();
+    }
+
+    #[test]
+    fn rejects_unknown_keys() {
+        assert!(toml::from_str::<Config>("bnd = \"127.0.0.1:1\"").is_err());
+        assert!(toml::from_str::<Config>("[buses.front]\nserial_number = \"1\"").is_err());
+    }
+
+    #[test]
+    fn flags_override_the_file() {
+        let path = env::temp_dir().join(format!("usb_can_server_config.{}", process::id()));
+        fs::write(
+            &path,
+            "bind = \"127.0.0.1:1\"\ntimeout_ms = 100\nlog = \"warn\"\n",
+        )
+        .unwrap();
+        let config = Config::from_args(args(&[
+            "--timeout",
+            "200",
+            "--config",
+            path.to_str().unwrap(),
+            "--socket-mode",
+            "0o600",
+            "--bus",
+            "front=1234",
+            "--bus",
+            "rear",
+            "-v",
+            "-v",
+        ]));
+        fs::remove_file(&path).unwrap();
+        let config = config.unwrap();
+        assert_eq!(config.bind, "127.0.0.1:1");
+        assert_eq!(config.timeout_ms, 200);
+        assert_eq!(config.socket_mode, Some(0o600));
+        assert_eq!(config.log, Level::Debug);
+        assert_eq!(config.buses["front"].serial.as_deref(), Some("1234"));
+        assert_eq!(config.buses["rear"].serial, None);
+    }
+
+    #[test]
+    fn a_single_default_bus_without_a_table() {
+        let config = Config::from_args(args(&["unix:///tmp/server.sock"])).unwrap();
+        assert_eq!(config.bind, "unix:///tmp/server.sock");
+        assert_eq!(
+            config.buses().into_keys().collect::<Vec<_>>(),
+            [DEFAULT_BUS]
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_settings() {
+        for flags in [
+            &["--endpoint", "16"][..],
+            &["--timeout", "0"],
+            &["--socket-mode", "1777"],
+            &["--socket-mode", "0o9"],
+            &["--failsafe", "halt"],
+            &["--bus", "a", "--bus", "b"],
+            &["--bus", "a=1", "--bus", "b=1"],
+            &["--bus", "bad\nname"],
+            &["--timeout"],
+            &["--frobnicate"],
+        ] {
+            assert!(Config::from_args(args(flags)).is_err(), "{:?}", flags);
+        }
+    }
+}
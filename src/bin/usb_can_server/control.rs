@@ -0,0 +1,250 @@
+//! The `Control` RPC: command frames and their replies over one bidirectional stream.
+//!
+//! Commands are written as soon as they arrive, without waiting for the reply of the
+//! previous one. A frame read from the bus is matched to the last command sent to the
+//! same device, and returned with its sequence number.
+
+use motor_lib::frame;
+use motor_lib::grpc::pb;
+use motor_lib::recording::Direction;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::{broadcast, mpsc};
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::{Stream, StreamExt};
+
+use crate::bus::Bus;
+use crate::router::ClientId;
+use crate::watchdog::Watchdog;
+
+/// The number of replies buffered for a client before it starts falling behind the bus.
+const REPLY_BUFFER: usize = 64;
+
+pub type ControlStream = ReceiverStream<Result<pb::ControlReply, tonic::Status>>;
+
+/// The sequence number of the last command sent to each device awaiting a reply.
+#[derive(Debug, Default)]
+struct Outstanding(HashMap<(u8, u8), u64>);
+
+impl Outstanding {
+    /// Records that the command `sequence` has been written.
+    fn sent(&mut self, command: &[u8; 8], sequence: u64) {
+        if let Some(device) = frame::decode(Direction::Write, command).device() {
+            self.0.insert(device, sequence);
+        }
+    }
+
+    /// Returns the sequence number of the command that `received` replies to, if any.
+    fn reply(&mut self, received: &[u8; 8]) -> Option<u64> {
+        frame::senders(received)
+            .into_iter()
+            .find_map(|device| self.0.remove(&device))
+    }
+}
+
+/// Starts serving the commands of `client`. The watchdog ends its session when the stream
+/// closes.
+pub fn serve(
+    bus: Arc<Bus>,
+    watchdog: Arc<Watchdog>,
+    client: ClientId,
+    mut commands: impl Stream<Item = Result<pb::ControlFrame, tonic::Status>> + Unpin + Send + 'static,
+) -> ControlStream {
+    let mut frames = bus.subscribe();
+    let (tx, rx) = mpsc::channel(REPLY_BUFFER);
+    tokio::spawn(async move {
+        let mut outstanding = Outstanding::default();
+        loop {
+            tokio::select! {
+                command = commands.next() => {
+                    let command = match command {
+                        Some(Ok(command)) => command,
+                        // The client has closed its side of the stream or disconnected.
+                        None | Some(Err(_)) => break,
+                    };
+                    let Ok(buf) = <[u8; 8]>::try_from(command.frame.as_slice()) else {
+                        let status = tonic::Status::invalid_argument(format!(
+                            "frame {} is not 8 bytes",
+                            command.sequence
+                        ));
+                        let _ = tx.send(Err(status)).await;
+                        break;
+                    };
+                    let bus = Arc::clone(&bus);
+                    let writer = client.clone();
+                    let timeout = bus.timeout();
+                    let result = tokio::task::spawn_blocking(move || bus.write_as(&writer, &buf, timeout))
+                        .await
+                        .map_err(|e| tonic::Status::internal(e.to_string()))
+                        .and_then(|result| result.map_err(tonic::Status::from));
+                    match result {
+                        Ok(_) => {
+                            watchdog.command(&client, &buf);
+                            outstanding.sent(&buf, command.sequence);
+                        }
+                        Err(status) => {
+                            let reply = pb::ControlReply {
+                                sequence: command.sequence,
+                                frame: Vec::new(),
+                                error: status.message().to_string(),
+                                code: status.code().into(),
+                            };
+                            if tx.send(Ok(reply)).await.is_err() {
+                                break;
+                            }
+                        }
+                    }
+                }
+                received = frames.recv() => {
+                    let received = match received {
+                        Ok(received) => received,
+                        Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                        Err(broadcast::error::RecvError::Closed) => break,
+                    };
+                    let Some(sequence) = outstanding.reply(&received.frame) else {
+                        continue;
+                    };
+                    let reply = pb::ControlReply {
+                        sequence,
+                        frame: received.frame.to_vec(),
+                        error: String::new(),
+                        code: tonic::Code::Ok.into(),
+                    };
+                    if tx.send(Ok(reply)).await.is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+        watchdog.close(&client);
+    });
+    ReceiverStream::new(rx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buses::{Buses, Line};
+    use motor_lib::device_type;
+    use std::time::Duration;
+    use tonic::Code;
+
+    const PWM: [u8; 8] = [0x00, device_type::MASTER, 0x02, 0, 0, 100, 0, 0];
+    const STATUS: [u8; 8] = [0x00, device_type::MASTER, 0x01, 0, 0, 0, 0, 0];
+
+    type Commands = mpsc::Sender<Result<pb::ControlFrame, tonic::Status>>;
+
+    fn open(line: &Line, client: &str) -> (Commands, ControlStream) {
+        let (commands, receiver) = mpsc::channel(8);
+        let replies = serve(
+            Arc::clone(&line.bus),
+            Arc::clone(&line.watchdog),
+            client.to_string(),
+            ReceiverStream::new(receiver),
+        );
+        (commands, replies)
+    }
+
+    async fn send(commands: &Commands, sequence: u64, frame: &[u8]) {
+        let command = pb::ControlFrame {
+            sequence,
+            frame: frame.to_vec(),
+        };
+        commands.send(Ok(command)).await.unwrap();
+    }
+
+    #[test]
+    fn replies_carry_the_sequence_of_the_last_command_to_the_device() {
+        let mut outstanding = Outstanding::default();
+        outstanding.sent(&PWM, 1);
+        outstanding.sent(&STATUS, 2);
+        outstanding.sent(&[0x10, device_type::MASTER, 0x02, 0, 0, 0, 0, 0], 3);
+        assert_eq!(outstanding.reply(&[0x10, 0, 0, 0, 0, 0, 0, 0]), Some(3));
+        assert_eq!(outstanding.reply(&[0x00, 0, 0, 90, 0, 0, 0, 0]), Some(2));
+        assert_eq!(outstanding.reply(&[0x00, 0, 0, 90, 0, 0, 0, 0]), None);
+        assert_eq!(outstanding.reply(&[0x05, 0, 0, 0, 0, 0, 0, 0]), None);
+    }
+
+    #[tokio::test]
+    async fn a_frame_that_is_not_8_bytes_ends_the_stream() {
+        let line = Line::detached(None);
+        let (commands, mut replies) = open(&line, "a");
+        send(&commands, 1, &PWM[..4]).await;
+        let status = replies.next().await.unwrap().unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+        assert!(replies.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn a_rejected_write_is_a_reply() {
+        let line = Line::detached(None);
+        line.bus
+            .lease()
+            .acquire("console", "operator".to_string(), 5, None)
+            .unwrap();
+        let (commands, mut replies) = open(&line, "a");
+        send(&commands, 1, &PWM).await;
+        let reply = replies.next().await.unwrap().unwrap();
+        assert_eq!(reply.sequence, 1);
+        assert_eq!(reply.code, Code::PermissionDenied as i32);
+        assert!(reply.error.contains("operator"));
+        assert!(reply.frame.is_empty());
+
+        // The stream stays open; the bus has no adapter to write the next command to.
+        send(&commands, 2, &STATUS).await;
+        let reply = replies.next().await.unwrap().unwrap();
+        assert_eq!(reply.sequence, 2);
+        assert_eq!(reply.code, Code::Unavailable as i32);
+    }
+
+    #[tokio::test]
+    async fn closing_the_stream_ends_the_watchdog_session() {
+        let line = Line::detached(Some(Duration::from_secs(60)));
+        let (commands, mut replies) = open(&line, "a");
+        line.watchdog.command("a", &PWM);
+        assert!(line.watchdog.has_session("a"));
+        drop(commands);
+        assert!(replies.next().await.is_none());
+        assert!(!line.watchdog.has_session("a"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn a_stream_handle_reads_a_rejected_write_as_an_error() {
+        let path =
+            std::env::temp_dir().join(format!("usb_can_server_control.{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let line = Line::detached(None);
+        line.bus
+            .lease()
+            .acquire("console", "operator".to_string(), 5, None)
+            .unwrap();
+        let mut buses = Buses::default();
+        buses.insert("default".to_string(), line);
+        let server = crate::UsbCanServer::new(Arc::new(buses));
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let listener = runtime
+            .block_on(async { tokio::net::UnixListener::bind(&path) })
+            .unwrap();
+        runtime.spawn(
+            tonic::transport::Server::builder()
+                .add_service(pb::usb_can_server::UsbCanServer::new(server))
+                .serve_with_incoming(tokio_stream::wrappers::UnixListenerStream::new(listener)),
+        );
+
+        let url = format!("unix://{}", path.display());
+        let handle = motor_lib::GrpcStreamHandle::new(&url).unwrap();
+        let timeout = Duration::from_secs(1);
+        motor_lib::HandleTrait::write_bulk(&handle, &PWM, timeout).unwrap();
+        assert_eq!(handle.last_sequence(), 1);
+        match handle.read_reply(timeout).unwrap_err() {
+            motor_lib::Error::GrpcError(status) => {
+                assert_eq!(status.code(), Code::PermissionDenied)
+            }
+            other => panic!("not a gRPC error: {}", other),
+        }
+        drop(handle);
+        drop(runtime);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,279 @@
+//! The controlling lease of the bus.
+//!
+//! While a client holds the lease, the output commands of every other client are rejected,
+//! so observers such as dashboards cannot move the devices. A client with a higher
+//! priority, such as an operator console, takes the lease from the current holder.
+
+use motor_lib::frame::{self, Frame};
+use motor_lib::grpc::pb;
+use motor_lib::recording::Direction;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::buses::Buses;
+use crate::client_id;
+use crate::router::ClientId;
+
+/// How long the holder may write nothing before the lease expires, if it does not say.
+const DEFAULT_TTL: Duration = Duration::from_millis(3000);
+
+#[derive(Debug, Clone)]
+struct Holder {
+    client: ClientId,
+    name: String,
+    priority: u32,
+    acquired: SystemTime,
+    ttl: Duration,
+    last_seen: Instant,
+}
+
+impl Holder {
+    fn info(&self) -> pb::LeaseInfo {
+        pb::LeaseInfo {
+            client: self.client.clone(),
+            name: self.name.clone(),
+            priority: self.priority,
+            acquired_us: self
+                .acquired
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_micros() as u64,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Lease {
+    holder: Mutex<Option<Holder>>,
+}
+
+/// Returns the holder, after dropping an expired lease.
+fn current(holder: &mut Option<Holder>) -> &mut Option<Holder> {
+    if holder
+        .as_ref()
+        .is_some_and(|holder| holder.last_seen.elapsed() >= holder.ttl)
+    {
+        if let Some(holder) = holder.take() {
+            info!("Lease of {} ({}) expired", holder.name, holder.client);
+        }
+    }
+    holder
+}
+
+impl Lease {
+    pub fn acquire(
+        &self,
+        client: &str,
+        name: String,
+        priority: u32,
+        ttl: Option<Duration>,
+    ) -> Result<pb::LeaseInfo, tonic::Status> {
+        let mut guard = self.holder.lock().unwrap();
+        let holder = current(&mut guard);
+        if let Some(current) = holder.as_mut() {
+            if current.client == client {
+                current.name = name;
+                current.priority = priority;
+                current.ttl = ttl.unwrap_or(DEFAULT_TTL);
+                current.last_seen = Instant::now();
+                return Ok(current.info());
+            }
+            if current.priority >= priority {
+                return Err(tonic::Status::failed_precondition(format!(
+                    "the lease is held by {} ({}) with priority {}",
+                    current.name, current.client, current.priority
+                )));
+            }
+            info!(
+                "Lease of {} ({}) preempted by {} ({})",
+                current.name, current.client, name, client
+            );
+        }
+        let new = Holder {
+            client: client.to_string(),
+            name,
+            priority,
+            acquired: SystemTime::now(),
+            ttl: ttl.unwrap_or(DEFAULT_TTL),
+            last_seen: Instant::now(),
+        };
+        let info = new.info();
+        *holder = Some(new);
+        Ok(info)
+    }
+
+    pub fn release(&self, client: &str) -> Result<(), tonic::Status> {
+        let mut guard = self.holder.lock().unwrap();
+        let holder = current(&mut guard);
+        match holder {
+            Some(current) if current.client == client => {
+                *holder = None;
+                Ok(())
+            }
+            _ => Err(tonic::Status::failed_precondition(
+                "the lease is not held by this client",
+            )),
+        }
+    }
+
+    pub fn holder(&self) -> Option<pb::LeaseInfo> {
+        current(&mut self.holder.lock().unwrap())
+            .as_ref()
+            .map(Holder::info)
+    }
+
+    /// Returns an error if `client` may not write `frame` because another client holds the
+    /// lease. Writes of the holder renew the lease.
+    pub fn check(&self, client: &str, frame: &[u8]) -> Result<(), tonic::Status> {
+        let mut guard = self.holder.lock().unwrap();
+        let Some(holder) = current(&mut guard) else {
+            return Ok(());
+        };
+        if holder.client == client {
+            holder.last_seen = Instant::now();
+            return Ok(());
+        }
+        let output = match <[u8; 8]>::try_from(frame) {
+            Ok(buf) => match frame::decode(Direction::Write, &buf) {
+                Frame::Command(command) => command.is_output(),
+                _ => false,
+            },
+            Err(_) => true,
+        };
+        if output {
+            return Err(tonic::Status::permission_denied(format!(
+                "the lease is held by {} ({})",
+                holder.name, holder.client
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// The Lease service. Each bus has its own lease.
+#[derive(Debug)]
+pub struct LeaseServer {
+    buses: Arc<Buses>,
+}
+
+impl LeaseServer {
+    pub fn new(buses: Arc<Buses>) -> LeaseServer {
+        LeaseServer { buses }
+    }
+}
+
+#[tonic::async_trait]
+impl pb::lease_server::Lease for LeaseServer {
+    async fn acquire_lease(
+        &self,
+        request: tonic::Request<pb::AcquireLeaseRequest>,
+    ) -> Result<tonic::Response<pb::LeaseInfo>, tonic::Status> {
+        let line = self.buses.select(&request)?;
+        let client = client_id(&request);
+        let request = request.into_inner();
+        let ttl = (request.ttl_ms > 0).then(|| Duration::from_millis(request.ttl_ms.into()));
+        let info = line
+            .bus
+            .lease()
+            .acquire(&client, request.name, request.priority, ttl)?;
+        Ok(tonic::Response::new(info))
+    }
+
+    async fn release_lease(
+        &self,
+        request: tonic::Request<pb::Empty>,
+    ) -> Result<tonic::Response<pb::Empty>, tonic::Status> {
+        let line = self.buses.select(&request)?;
+        line.bus.lease().release(&client_id(&request))?;
+        Ok(tonic::Response::new(pb::Empty {}))
+    }
+
+    async fn get_lease(
+        &self,
+        request: tonic::Request<pb::Empty>,
+    ) -> Result<tonic::Response<pb::LeaseStatus>, tonic::Status> {
+        let line = self.buses.select(&request)?;
+        Ok(tonic::Response::new(pb::LeaseStatus {
+            holder: line.bus.lease().holder(),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PWM: [u8; 8] = [0x01, 0x60, 0x02, 0, 0x03, 0xe8, 0, 0];
+    const STATUS: [u8; 8] = [0x01, 0x60, 0x01, 0, 0, 0, 0, 0];
+
+    #[test]
+    fn a_higher_priority_preempts_the_holder() {
+        let lease = Lease::default();
+        lease.acquire("dash", "dashboard".into(), 1, None).unwrap();
+        assert_eq!(
+            lease
+                .acquire("other", "other".into(), 1, None)
+                .unwrap_err()
+                .code(),
+            tonic::Code::FailedPrecondition
+        );
+        let info = lease
+            .acquire("console", "operator".into(), 5, None)
+            .unwrap();
+        assert_eq!(info.client, "console");
+        assert_eq!(lease.holder().unwrap().name, "operator");
+        assert!(lease.acquire("dash", "dashboard".into(), 1, None).is_err());
+    }
+
+    #[test]
+    fn the_holder_renews_without_competing() {
+        let lease = Lease::default();
+        lease
+            .acquire("console", "operator".into(), 5, None)
+            .unwrap();
+        let info = lease
+            .acquire("console", "operator".into(), 0, None)
+            .unwrap();
+        assert_eq!(info.priority, 0);
+        assert_eq!(lease.holder().unwrap().client, "console");
+    }
+
+    #[test]
+    fn only_the_holder_writes_outputs() {
+        let lease = Lease::default();
+        assert!(lease.check("dash", &PWM).is_ok());
+        lease
+            .acquire("console", "operator".into(), 5, None)
+            .unwrap();
+        assert!(lease.check("console", &PWM).is_ok());
+        assert_eq!(
+            lease.check("dash", &PWM).unwrap_err().code(),
+            tonic::Code::PermissionDenied
+        );
+        assert!(lease.check("dash", &STATUS).is_ok());
+        assert!(lease.check("dash", &PWM[..4]).is_err());
+    }
+
+    #[test]
+    fn only_the_holder_releases() {
+        let lease = Lease::default();
+        lease
+            .acquire("console", "operator".into(), 5, None)
+            .unwrap();
+        assert!(lease.release("dash").is_err());
+        lease.release("console").unwrap();
+        assert!(lease.holder().is_none());
+        assert!(lease.release("console").is_err());
+    }
+
+    #[test]
+    fn an_idle_lease_expires() {
+        let lease = Lease::default();
+        lease
+            .acquire("console", "operator".into(), 5, Some(Duration::ZERO))
+            .unwrap();
+        assert!(lease.holder().is_none());
+        assert!(lease.check("dash", &PWM).is_ok());
+        lease.acquire("dash", "dashboard".into(), 1, None).unwrap();
+    }
+}
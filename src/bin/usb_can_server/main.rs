@@ -0,0 +1,346 @@
+//! Shares the USB-CAN adapters of the robot with the clients of the gRPC services.
+//!
+//! Usage: usb_can_server [--config PATH] [--bind ADDRESS] [--socket-mode MODE]
+//!                       [--usb VID:PID] [--bus NAME[=SERIAL]]... [--interface N]
+//!                       [--endpoint N] [--timeout MS] [--watchdog MS]
+//!                       [--failsafe zero|emergency] [-v]... [-q]... [ADDRESS]
+//!
+//! Each USB adapter carries a named bus. The server starts even if the adapters are not
+//! connected, and opens each one when it arrives.
+//!
+//! With `--bind unix:///run/usb_can_server.sock` the server listens on a Unix domain socket
+//! instead of TCP, so only the local users that may open the socket can reach the devices.
+
+#[macro_use]
+mod log;
+mod adapters;
+mod bus;
+mod buses;
+mod config;
+mod control;
+mod lease;
+mod motor_control;
+mod router;
+mod watchdog;
+
+use adapters::Adapters;
+use bus::Bus;
+use buses::{Buses, BusesServer, Line};
+use config::{Config, USAGE};
+use lease::LeaseServer;
+use motor_control::MotorControlServer;
+use motor_lib::grpc::{pb, CLIENT_ID_KEY};
+use router::ClientId;
+use rusb::Context;
+use std::{
+    env,
+    net::SocketAddr,
+    process,
+    sync::{Arc, RwLock},
+    time,
+};
+use tonic::transport::Server;
+use watchdog::Watchdog;
+
+/// Identifies the client of a raw request, to route the replies of the bus to it.
+fn client_id<T>(request: &tonic::Request<T>) -> ClientId {
+    match request.metadata().get(CLIENT_ID_KEY) {
+        Some(id) => String::from_utf8_lossy(id.as_bytes()).into_owned(),
+        None => request
+            .remote_addr()
+            .map(|address| address.to_string())
+            .or_else(|| peer_pid(request))
+            .unwrap_or_default(),
+    }
+}
+
+/// Identifies a client connected to the Unix domain socket by its process.
+#[cfg(unix)]
+fn peer_pid<T>(request: &tonic::Request<T>) -> Option<String> {
+    let info = request
+        .extensions()
+        .get::<tonic::transport::server::UdsConnectInfo>()?;
+    Some(format!("pid {}", info.peer_cred?.pid()?))
+}
+
+#[cfg(not(unix))]
+fn peer_pid<T>(_request: &tonic::Request<T>) -> Option<String> {
+    None
+}
+
+/// Returns the deadline the client sent in the `grpc-timeout` header, or `default`.
+fn request_timeout<T>(request: &tonic::Request<T>, default: time::Duration) -> time::Duration {
+    let parse = |value: &str| {
+        let (amount, unit) = value.split_at(value.len().checked_sub(1)?);
+        let amount: u64 = amount.parse().ok()?;
+        match unit {
+            "H" => Some(time::Duration::from_secs(amount.saturating_mul(3600))),
+            "M" => Some(time::Duration::from_secs(amount.saturating_mul(60))),
+            "S" => Some(time::Duration::from_secs(amount)),
+            "m" => Some(time::Duration::from_millis(amount)),
+            "u" => Some(time::Duration::from_micros(amount)),
+            "n" => Some(time::Duration::from_nanos(amount)),
+            _ => None,
+        }
+    };
+    request
+        .metadata()
+        .get("grpc-timeout")
+        .and_then(|value| value.to_str().ok())
+        .and_then(parse)
+        .unwrap_or(default)
+}
+
+#[derive(Debug)]
+pub struct UsbCanServer {
+    buses: Arc<Buses>,
+}
+
+impl UsbCanServer {
+    fn new(buses: Arc<Buses>) -> UsbCanServer {
+        UsbCanServer { buses }
+    }
+}
+
+#[tonic::async_trait]
+impl pb::usb_can_server::UsbCan for UsbCanServer {
+    type ControlStream = control::ControlStream;
+
+    async fn read(
+        &self,
+        _request: tonic::Request<pb::ReadRequest>,
+    ) -> Result<tonic::Response<pb::ReadResponse>, tonic::Status> {
+        let line = self.buses.select(&_request)?;
+        let client = client_id(&_request);
+        let timeout = request_timeout(&_request, line.bus.timeout());
+        let size = _request.into_inner().size;
+        let size: usize = size
+            .try_into()
+            .map_err(|_| tonic::Status::invalid_argument(format!("size {} is negative", size)))?;
+        let mut recv_buf = vec![0; size];
+        if !line.bus.is_connected() {
+            return Err(tonic::Status::unavailable("no device connected"));
+        }
+        line.watchdog.seen(&client);
+        let bus = Arc::clone(&line.bus);
+        let frame = tokio::task::spawn_blocking(move || bus.read_as(&client, timeout))
+            .await
+            .map_err(|e| tonic::Status::internal(e.to_string()))?
+            .ok_or_else(|| {
+                tonic::Status::deadline_exceeded(format!("no frame received in {:?}", timeout))
+            })?;
+        let len = recv_buf.len().min(frame.len());
+        recv_buf[..len].copy_from_slice(&frame[..len]);
+        Ok(tonic::Response::new(pb::ReadResponse { recv_buf }))
+    }
+    async fn write(
+        &self,
+        _request: tonic::Request<pb::WriteRequest>,
+    ) -> Result<tonic::Response<pb::WriteResponse>, tonic::Status> {
+        let line = self.buses.select(&_request)?;
+        let client = client_id(&_request);
+        let timeout = request_timeout(&_request, line.bus.timeout());
+        let send_buf = _request.into_inner().send_buf;
+        let bus = Arc::clone(&line.bus);
+        let watchdog = Arc::clone(&line.watchdog);
+        let size = tokio::task::spawn_blocking(move || {
+            let size = bus.write_routed(&client, &send_buf, timeout)?;
+            watchdog.command(&client, &send_buf);
+            Ok::<_, motor_lib::Error>(size)
+        })
+        .await
+        .map_err(|e| tonic::Status::internal(e.to_string()))??;
+        Ok(tonic::Response::new(pb::WriteResponse {
+            size: size.try_into().unwrap_or(i32::MAX),
+        }))
+    }
+    async fn control(
+        &self,
+        request: tonic::Request<tonic::Streaming<pb::ControlFrame>>,
+    ) -> Result<tonic::Response<Self::ControlStream>, tonic::Status> {
+        let line = self.buses.select(&request)?;
+        let replies = control::serve(
+            Arc::clone(&line.bus),
+            Arc::clone(&line.watchdog),
+            client_id(&request),
+            request.into_inner(),
+        );
+        Ok(tonic::Response::new(replies))
+    }
+}
+
+/// Where the server listens.
+enum Listen {
+    Tcp(SocketAddr),
+    #[cfg(unix)]
+    Unix(std::path::PathBuf),
+}
+
+fn parse_bind(bind: &str) -> Result<Listen, String> {
+    match bind.strip_prefix("unix://") {
+        #[cfg(unix)]
+        Some(path) => Ok(Listen::Unix(path.into())),
+        #[cfg(not(unix))]
+        Some(_) => Err("Unix domain sockets are not supported on this platform".to_string()),
+        None => bind
+            .parse()
+            .map(Listen::Tcp)
+            .map_err(|e| format!("invalid bind address {}: {}", bind, e)),
+    }
+}
+
+/// Listens on the Unix domain socket at `path`, replacing the socket left behind by a
+/// server that has exited.
+///
+/// With a `mode`, the socket is bound in a directory that only this user may enter, given
+/// its mode, and then moved to `path`, so nobody connects before its mode is set.
+#[cfg(unix)]
+fn bind_unix(
+    path: &std::path::Path,
+    mode: Option<u32>,
+) -> Result<tokio_stream::wrappers::UnixListenerStream, String> {
+    use std::os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt};
+    let display = path.display();
+    if std::fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+        if std::os::unix::net::UnixStream::connect(path).is_ok() {
+            return Err(format!("another server is listening on {}", display));
+        }
+        std::fs::remove_file(path)
+            .map_err(|e| format!("cannot remove the old socket {}: {}", display, e))?;
+    }
+    let listener = match mode {
+        None => std::os::unix::net::UnixListener::bind(path)
+            .map_err(|e| format!("cannot listen on {}: {}", display, e))?,
+        Some(mode) => {
+            let parent = match path.parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => parent,
+                _ => std::path::Path::new("."),
+            };
+            let staging = parent.join(format!(".usb_can_server.{}", process::id()));
+            std::fs::DirBuilder::new()
+                .mode(0o700)
+                .create(&staging)
+                .map_err(|e| format!("cannot create {}: {}", staging.display(), e))?;
+            let staged = staging.join("socket");
+            let listener = std::os::unix::net::UnixListener::bind(&staged)
+                .map_err(|e| format!("cannot listen on {}: {}", display, e))
+                .and_then(|listener| {
+                    std::fs::set_permissions(&staged, std::fs::Permissions::from_mode(mode))
+                        .map_err(|e| format!("cannot set the mode of {}: {}", display, e))?;
+                    std::fs::rename(&staged, path)
+                        .map_err(|e| format!("cannot move the socket to {}: {}", display, e))?;
+                    Ok(listener)
+                });
+            let _ = std::fs::remove_file(&staged);
+            let _ = std::fs::remove_dir(&staging);
+            listener?
+        }
+    };
+    let listener = listener
+        .set_nonblocking(true)
+        .and_then(|_| tokio::net::UnixListener::from_std(listener))
+        .map_err(|e| format!("cannot listen on {}: {}", display, e))?;
+    Ok(tokio_stream::wrappers::UnixListenerStream::new(listener))
+}
+
+async fn run(config: Config) -> Result<(), String> {
+    let listen = parse_bind(&config.bind)?;
+
+    let context = Context::new().map_err(|e| format!("cannot initialise libusb: {}", e))?;
+    let config = Arc::new(config);
+    let mut adapters = Adapters::new(context, Arc::clone(&config));
+    let mut buses = Buses::default();
+    for (name, bus_config) in config.buses() {
+        let handle = Arc::new(RwLock::new(None));
+        adapters.add(&name, bus_config.serial.clone(), Arc::clone(&handle));
+        let bus = Bus::start(name.clone(), handle, config.endpoint, config.timeout());
+        let watchdog = Watchdog::start(Arc::clone(&bus), config.watchdog(), config.failsafe);
+        let serial = bus_config.serial;
+        buses.insert(
+            name,
+            Line {
+                bus,
+                watchdog,
+                serial,
+            },
+        );
+    }
+    let adapters = Arc::new(adapters);
+    adapters.scan(false);
+    adapters.report_missing();
+    adapters
+        .watch()
+        .map_err(|e| format!("cannot watch the USB devices: {}", e))?;
+
+    let buses = Arc::new(buses);
+    let server = UsbCanServer::new(Arc::clone(&buses));
+    let lease = LeaseServer::new(Arc::clone(&buses));
+    let motor_control = MotorControlServer::new(Arc::clone(&buses));
+    let buses = BusesServer::new(buses);
+
+    let router = Server::builder()
+        .add_service(pb::usb_can_server::UsbCanServer::new(server))
+        .add_service(pb::buses_server::BusesServer::new(buses))
+        .add_service(pb::lease_server::LeaseServer::new(lease))
+        .add_service(pb::motor_control_server::MotorControlServer::new(
+            motor_control,
+        ));
+    let served = match listen {
+        Listen::Tcp(socket_address) => {
+            info!("Server listening on {}", socket_address);
+            router.serve(socket_address).await
+        }
+        #[cfg(unix)]
+        Listen::Unix(path) => {
+            let incoming = bind_unix(&path, config.socket_mode)?;
+            info!("Server listening on unix://{}", path.display());
+            router.serve_with_incoming(incoming).await
+        }
+    };
+    served.map_err(|e| {
+        // The transport error only says "transport error"; its source says why.
+        let reason = std::error::Error::source(&e).map_or(e.to_string(), |e| e.to_string());
+        format!("cannot serve on {}: {}", config.bind, reason)
+    })
+}
+
+#[tokio::main]
+async fn main() {
+    let config = match Config::from_args(env::args().skip(1).collect()) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}\n{}", e, USAGE);
+            process::exit(2);
+        }
+    };
+    log::set_level(config.log);
+    if let Err(e) = run(config).await {
+        error!("{}", e);
+        process::exit(1);
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[tokio::test]
+    async fn unix_socket_is_moved_in_place_with_its_mode() {
+        let dir = env::temp_dir().join(format!("usb_can_server_test.{}", process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("server.sock");
+
+        let listener = bind_unix(&path, Some(0o600)).unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert!(std::os::unix::net::UnixStream::connect(&path).is_ok());
+        // Only the socket is left in the directory.
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+        assert!(bind_unix(&path, Some(0o600)).is_err());
+
+        drop(listener);
+        assert!(bind_unix(&path, None).is_ok());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,475 @@
+//! The typed MotorControl service, built on the device modules of motor_lib.
+
+use motor_lib::device::{BlMd, Md, Sd, Smd};
+use motor_lib::frame::{self, Frame};
+use motor_lib::grpc::pb;
+use motor_lib::recording::Direction;
+use motor_lib::{device_type, sr};
+use std::sync::Arc;
+use std::time::{Duration, Instant, UNIX_EPOCH};
+use tokio::sync::{broadcast, mpsc};
+use tokio_stream::wrappers::ReceiverStream;
+
+use crate::bus::{Received, Transaction};
+use crate::buses::{Buses, Line};
+use crate::router::ClientId;
+use crate::{client_id, request_timeout};
+
+/// The number of frames buffered for a subscriber before it starts falling behind the bus.
+const SUBSCRIBER_BUFFER: usize = 64;
+
+fn to_u8(value: u32, name: &str) -> Result<u8, tonic::Status> {
+    value.try_into().map_err(|_| {
+        tonic::Status::invalid_argument(format!("{} {} does not fit in a byte", name, value))
+    })
+}
+
+fn to_i16(value: i32, name: &str) -> Result<i16, tonic::Status> {
+    value.try_into().map_err(|_| {
+        tonic::Status::invalid_argument(format!("{} {} does not fit in an int16", name, value))
+    })
+}
+
+fn to_port(value: u32) -> Result<u8, tonic::Status> {
+    match value {
+        0 | 1 => Ok(value as u8),
+        _ => Err(tonic::Status::invalid_argument(format!(
+            "port {} is not 0 or 1",
+            value
+        ))),
+    }
+}
+
+/// The typed MotorControl service, built on the device modules of motor_lib.
+#[derive(Debug)]
+pub struct MotorControlServer {
+    buses: Arc<Buses>,
+}
+
+impl MotorControlServer {
+    pub fn new(buses: Arc<Buses>) -> MotorControlServer {
+        MotorControlServer { buses }
+    }
+
+    /// Runs `command` for `client` on a blocking thread, holding the device on `line` for
+    /// the command and its reply. Fails with `DEADLINE_EXCEEDED` if the reply has not
+    /// arrived within `timeout`, after releasing the device.
+    async fn transact<T: Send + 'static>(
+        line: Arc<Line>,
+        client: ClientId,
+        timeout: Duration,
+        command: impl FnOnce(&Transaction) -> Result<T, motor_lib::Error> + Send + 'static,
+    ) -> Result<tonic::Response<T>, tonic::Status> {
+        line.watchdog.seen(&client);
+        let deadline = Instant::now() + timeout;
+        let transaction = line.bus.transaction(client.clone(), deadline).await?;
+        let watchdog = Arc::clone(&line.watchdog);
+        tokio::task::spawn_blocking(move || {
+            let result = command(&transaction);
+            for frame in transaction.into_written() {
+                watchdog.command(&client, &frame);
+            }
+            result
+        })
+        .await
+        .map_err(|e| tonic::Status::internal(e.to_string()))?
+        .map(tonic::Response::new)
+        .map_err(tonic::Status::from)
+    }
+
+    async fn send_md(
+        &self,
+        request: tonic::Request<pb::MdRequest>,
+        send: fn(
+            &mut Md<'_, Transaction>,
+            i16,
+        ) -> Result<motor_lib::md::MdStatus, motor_lib::Error>,
+    ) -> Result<tonic::Response<pb::MdStatus>, tonic::Status> {
+        let line = self.buses.select(&request)?;
+        let client = client_id(&request);
+        let timeout = request_timeout(&request, line.bus.timeout());
+        let request = request.into_inner();
+        let address = to_u8(request.address, "address")?;
+        let value = to_i16(request.value, "value")?;
+        Self::transact(line, client, timeout, move |device| {
+            Ok(send(&mut Md::new(device, address)?, value)?.into())
+        })
+        .await
+    }
+}
+
+/// Converts a frame read from the bus for `SubscribeStatus`.
+fn to_status_frame(received: &Received, dropped: u64) -> pb::StatusFrame {
+    use pb::device_status::Status;
+    let frame = frame::decode(Direction::Read, &received.frame);
+    let device = frame.device();
+    let status = match frame {
+        Frame::Md(status) => Some(Status::Md(status.into())),
+        Frame::Sd(status) => Some(Status::Sd(status.into())),
+        Frame::Smd(status) => Some(Status::Smd(status.into())),
+        Frame::BlMd(status) => Some(Status::Blmd(status.into())),
+        _ => None,
+    };
+    pb::StatusFrame {
+        frame: received.frame.to_vec(),
+        device_type: device.map(|(device_type, _)| device_type.into()),
+        address: device.map(|(_, address)| address.into()),
+        timestamp_us: received
+            .time
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_micros() as u64,
+        status: status.map(|status| pb::DeviceStatus {
+            status: Some(status),
+        }),
+        dropped,
+    }
+}
+
+/// Returns whether a frame passes the filters of a `SubscribeRequest`.
+fn matches(request: &pb::SubscribeRequest, frame: &pb::StatusFrame) -> bool {
+    let matches = |filter: &[u32], value: Option<u32>| {
+        filter.is_empty() || value.is_some_and(|value| filter.contains(&value))
+    };
+    matches(&request.device_types, frame.device_type) && matches(&request.addresses, frame.address)
+}
+
+#[tonic::async_trait]
+impl pb::motor_control_server::MotorControl for MotorControlServer {
+    type SubscribeStatusStream = ReceiverStream<Result<pb::StatusFrame, tonic::Status>>;
+
+    async fn send_pwm(
+        &self,
+        request: tonic::Request<pb::MdRequest>,
+    ) -> Result<tonic::Response<pb::MdStatus>, tonic::Status> {
+        self.send_md(request, |md, power| md.send_pwm(power)).await
+    }
+
+    async fn send_speed(
+        &self,
+        request: tonic::Request<pb::MdRequest>,
+    ) -> Result<tonic::Response<pb::MdStatus>, tonic::Status> {
+        self.send_md(request, |md, velocity| md.send_speed(velocity))
+            .await
+    }
+
+    async fn send_angle(
+        &self,
+        request: tonic::Request<pb::MdRequest>,
+    ) -> Result<tonic::Response<pb::MdStatus>, tonic::Status> {
+        self.send_md(request, |md, angle| md.send_angle(angle))
+            .await
+    }
+
+    async fn send_lim_sw(
+        &self,
+        request: tonic::Request<pb::LimSwRequest>,
+    ) -> Result<tonic::Response<pb::MdStatus>, tonic::Status> {
+        let line = self.buses.select(&request)?;
+        let client = client_id(&request);
+        let timeout = request_timeout(&request, line.bus.timeout());
+        let request = request.into_inner();
+        let address = to_u8(request.address, "address")?;
+        let port = to_port(request.port)?;
+        let power = to_i16(request.power, "power")?;
+        let after_power = to_i16(request.after_power, "after_power")?;
+        Self::transact(line, client, timeout, move |device| {
+            Ok(Md::new(device, address)?
+                .send_limsw(port, power, after_power)?
+                .into())
+        })
+        .await
+    }
+
+    async fn sd_set_power(
+        &self,
+        request: tonic::Request<pb::SdPowerRequest>,
+    ) -> Result<tonic::Response<pb::SdStatus>, tonic::Status> {
+        let line = self.buses.select(&request)?;
+        let client = client_id(&request);
+        let timeout = request_timeout(&request, line.bus.timeout());
+        let request = request.into_inner();
+        let address = to_u8(request.address, "address")?;
+        let port = to_port(request.port)?;
+        let power = to_i16(request.power, "power")?;
+        Self::transact(line, client, timeout, move |device| {
+            Ok(Sd::new(device, address)?.send_power(port, power)?.into())
+        })
+        .await
+    }
+
+    async fn smd_set_angles(
+        &self,
+        request: tonic::Request<pb::SmdAnglesRequest>,
+    ) -> Result<tonic::Response<pb::SmdStatus>, tonic::Status> {
+        let line = self.buses.select(&request)?;
+        let client = client_id(&request);
+        let timeout = request_timeout(&request, line.bus.timeout());
+        let request = request.into_inner();
+        let address = to_u8(request.address, "address")?;
+        let angle_0 = to_i16(request.angle_0, "angle_0")?;
+        let angle_1 = to_i16(request.angle_1, "angle_1")?;
+        Self::transact(line, client, timeout, move |device| {
+            Ok(Smd::new(device, address)?
+                .send_angles(angle_0, angle_1)?
+                .into())
+        })
+        .await
+    }
+
+    async fn blmd_set_current(
+        &self,
+        request: tonic::Request<pb::BlmdCurrentRequest>,
+    ) -> Result<tonic::Response<pb::BlmdStatus>, tonic::Status> {
+        let line = self.buses.select(&request)?;
+        let client = client_id(&request);
+        let timeout = request_timeout(&request, line.bus.timeout());
+        let request = request.into_inner();
+        let address = to_u8(request.address, "address")?;
+        let controller_id = to_u8(request.controller_id, "controller_id")?;
+        let current = to_i16(request.current, "current")?;
+        Self::transact(line, client, timeout, move |device| {
+            Ok(BlMd::new(device, address, controller_id)?
+                .send_current(current)?
+                .into())
+        })
+        .await
+    }
+
+    async fn sr_set_color(
+        &self,
+        request: tonic::Request<pb::SrColorRequest>,
+    ) -> Result<tonic::Response<pb::Empty>, tonic::Status> {
+        let line = self.buses.select(&request)?;
+        let client = client_id(&request);
+        let timeout = request_timeout(&request, line.bus.timeout());
+        let request = request.into_inner();
+        let red = to_u8(request.red, "red")?;
+        let green = to_u8(request.green, "green")?;
+        let blue = to_u8(request.blue, "blue")?;
+        let freq = request.freq;
+        if !(0.0..=63.75).contains(&freq) {
+            return Err(tonic::Status::invalid_argument(format!(
+                "freq {} is not in 0..=63.75",
+                freq
+            )));
+        }
+        let timeout_ms = timeout.as_millis().try_into().unwrap_or(u16::MAX);
+        Self::transact(line, client, timeout, move |device| {
+            sr::send_colors(device, red, green, blue, freq, timeout_ms)?;
+            Ok(pb::Empty {})
+        })
+        .await
+    }
+
+    async fn emergency(
+        &self,
+        request: tonic::Request<pb::Empty>,
+    ) -> Result<tonic::Response<pb::Empty>, tonic::Status> {
+        // Not queued behind a transaction: an emergency stop must not wait for a reply.
+        let bus = Arc::clone(&self.buses.select(&request)?.bus);
+        tokio::task::spawn_blocking(move || motor_lib::send_emergency(&*bus))
+            .await
+            .map_err(|e| tonic::Status::internal(e.to_string()))?
+            .map_err(tonic::Status::from)?;
+        Ok(tonic::Response::new(pb::Empty {}))
+    }
+
+    async fn get_status(
+        &self,
+        request: tonic::Request<pb::StatusRequest>,
+    ) -> Result<tonic::Response<pb::DeviceStatus>, tonic::Status> {
+        use pb::device_status::Status;
+        let line = self.buses.select(&request)?;
+        let client = client_id(&request);
+        let timeout = request_timeout(&request, line.bus.timeout());
+        let request = request.into_inner();
+        let address = to_u8(request.address, "address")?;
+        let controller_id = to_u8(request.controller_id, "controller_id")?;
+        let status = match device_type::of(address) {
+            device_type::MD => {
+                Self::transact(line, client, timeout, move |device| {
+                    let mut md = Md::new(device, address)?;
+                    md.request_status()?;
+                    Ok(Status::Md(md.receive_status()?.into()))
+                })
+                .await?
+            }
+            device_type::SD => {
+                Self::transact(line, client, timeout, move |device| {
+                    let mut sd = Sd::new(device, address)?;
+                    sd.request_status()?;
+                    Ok(Status::Sd(sd.receive_status()?.into()))
+                })
+                .await?
+            }
+            device_type::SMD => {
+                Self::transact(line, client, timeout, move |device| {
+                    let mut smd = Smd::new(device, address)?;
+                    smd.request_status()?;
+                    Ok(Status::Smd(smd.receive_status()?.into()))
+                })
+                .await?
+            }
+            device_type::BLMD => {
+                Self::transact(line, client, timeout, move |device| {
+                    let mut blmd = BlMd::new(device, address, controller_id)?;
+                    blmd.request_status()?;
+                    Ok(Status::Blmd(blmd.receive_status()?.into()))
+                })
+                .await?
+            }
+            _ => {
+                return Err(tonic::Status::unimplemented(format!(
+                    "no status decoder for the device at 0x{:02x}",
+                    address
+                )))
+            }
+        };
+        Ok(tonic::Response::new(pb::DeviceStatus {
+            status: Some(status.into_inner()),
+        }))
+    }
+
+    async fn subscribe_status(
+        &self,
+        request: tonic::Request<pb::SubscribeRequest>,
+    ) -> Result<tonic::Response<Self::SubscribeStatusStream>, tonic::Status> {
+        let mut frames = self.buses.select(&request)?.bus.subscribe();
+        let request = request.into_inner();
+        let (tx, rx) = mpsc::channel(SUBSCRIBER_BUFFER);
+        tokio::spawn(async move {
+            let mut dropped = 0;
+            loop {
+                let received = tokio::select! {
+                    received = frames.recv() => received,
+                    // The client has disconnected.
+                    _ = tx.closed() => break,
+                };
+                let received = match received {
+                    Ok(received) => received,
+                    Err(broadcast::error::RecvError::Lagged(missed)) => {
+                        dropped += missed;
+                        continue;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+                let frame = to_status_frame(&received, dropped);
+                if matches(&request, &frame) && tx.send(Ok(frame)).await.is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(tonic::Response::new(ReceiverStream::new(rx)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pb::motor_control_server::MotorControl;
+    use tonic::Code;
+
+    fn server() -> MotorControlServer {
+        let mut buses = Buses::default();
+        buses.insert("default".to_string(), Line::detached(None));
+        MotorControlServer::new(Arc::new(buses))
+    }
+
+    #[test]
+    fn values_out_of_range_are_invalid_arguments() {
+        assert_eq!(to_u8(255, "address").unwrap(), 255);
+        assert_eq!(
+            to_u8(256, "address").unwrap_err().code(),
+            Code::InvalidArgument
+        );
+        assert_eq!(to_i16(-32768, "value").unwrap(), -32768);
+        assert_eq!(to_i16(32767, "value").unwrap(), 32767);
+        assert_eq!(
+            to_i16(32768, "value").unwrap_err().code(),
+            Code::InvalidArgument
+        );
+        assert_eq!(
+            to_i16(-32769, "value").unwrap_err().code(),
+            Code::InvalidArgument
+        );
+        assert_eq!(to_port(0).unwrap(), 0);
+        assert_eq!(to_port(1).unwrap(), 1);
+        let error = to_port(2).unwrap_err();
+        assert_eq!(error.code(), Code::InvalidArgument);
+        assert_eq!(error.message(), "port 2 is not 0 or 1");
+    }
+
+    #[tokio::test]
+    async fn sr_freq_must_fit_the_frame() {
+        let server = server();
+        for freq in [-0.25, 64.0, f32::NAN] {
+            let request = pb::SrColorRequest {
+                red: 255,
+                green: 0,
+                blue: 0,
+                freq,
+            };
+            let error = server
+                .sr_set_color(tonic::Request::new(request))
+                .await
+                .unwrap_err();
+            assert_eq!(error.code(), Code::InvalidArgument, "{}", freq);
+        }
+    }
+
+    #[tokio::test]
+    async fn no_status_for_devices_without_a_decoder() {
+        let request = pb::StatusRequest {
+            address: device_type::SR.into(),
+            controller_id: 0,
+        };
+        let error = server()
+            .get_status(tonic::Request::new(request))
+            .await
+            .unwrap_err();
+        assert_eq!(error.code(), Code::Unimplemented);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn a_client_receives_the_status_code() {
+        let path = std::env::temp_dir().join(format!(
+            "usb_can_server_motor_control.{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let listener = runtime
+            .block_on(async { tokio::net::UnixListener::bind(&path) })
+            .unwrap();
+        runtime.spawn(
+            tonic::transport::Server::builder()
+                .add_service(pb::motor_control_server::MotorControlServer::new(server()))
+                .serve_with_incoming(tokio_stream::wrappers::UnixListenerStream::new(listener)),
+        );
+
+        let code = |error: motor_lib::Error| match error {
+            motor_lib::Error::GrpcError(status) => status.code(),
+            other => panic!("not a gRPC error: {}", other),
+        };
+        let url = format!("unix://{}", path.display());
+        let client = motor_lib::GrpcClient::new(&url).unwrap();
+        // The bus has no adapter.
+        assert_eq!(
+            code(client.send_pwm(0x00, 100).unwrap_err()),
+            Code::Unavailable
+        );
+        assert_eq!(
+            code(client.sr_set_color(255, 0, 0, 64.0).unwrap_err()),
+            Code::InvalidArgument
+        );
+        assert_eq!(
+            code(client.get_status(device_type::SR, 0).unwrap_err()),
+            Code::Unimplemented
+        );
+        drop(client);
+        drop(runtime);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,180 @@
+//! Delivery of the frames read from the bus to the clients of the raw `Read` RPC.
+//!
+//! Each frame written through the raw `Write` RPC is remembered as an outstanding request
+//! of its client, keyed by the device it was sent to. A frame read from that device is a
+//! reply, and is delivered only to the client that has been waiting for it the longest.
+//! Frames that answer no outstanding request are unsolicited, and are queued for every
+//! client.
+
+use motor_lib::frame;
+use motor_lib::recording::Direction;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// The number of frames kept per client when it does not read them.
+const QUEUE_CAPACITY: usize = 4096;
+/// How long a device has to reply before its reply is treated as unsolicited.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(1);
+/// How long a client may stay idle before it is forgotten.
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// The key of a client: the `client-id` metadata sent by `GrpcHandle`, or its address.
+pub type ClientId = String;
+
+#[derive(Debug)]
+struct Client {
+    /// The time of the oldest request awaiting a reply from each device.
+    outstanding: HashMap<(u8, u8), Instant>,
+    queue: VecDeque<[u8; 8]>,
+    last_seen: Instant,
+}
+
+impl Client {
+    fn new(now: Instant) -> Client {
+        Client {
+            outstanding: HashMap::new(),
+            queue: VecDeque::new(),
+            last_seen: now,
+        }
+    }
+
+    fn push(&mut self, frame: [u8; 8]) {
+        if self.queue.len() == QUEUE_CAPACITY {
+            self.queue.pop_front();
+        }
+        self.queue.push_back(frame);
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Router {
+    clients: Mutex<HashMap<ClientId, Client>>,
+    delivered: Condvar,
+}
+
+impl Router {
+    /// Records that `client` is about to write `frame`, so the reply can be routed back to it.
+    pub fn expect(&self, client: &str, frame: &[u8]) {
+        let now = Instant::now();
+        let mut clients = self.clients.lock().unwrap();
+        let entry = clients
+            .entry(client.to_string())
+            .or_insert_with(|| Client::new(now));
+        entry.last_seen = now;
+        let Ok(buf) = <[u8; 8]>::try_from(frame) else {
+            return;
+        };
+        if let Some(device) = frame::decode(Direction::Write, &buf).device() {
+            entry.outstanding.entry(device).or_insert(now);
+        }
+    }
+
+    /// Delivers a frame read from the bus.
+    pub fn route(&self, frame: [u8; 8]) {
+        let now = Instant::now();
+        let mut clients = self.clients.lock().unwrap();
+        clients.retain(|_, client| now.duration_since(client.last_seen) < CLIENT_TIMEOUT);
+        for client in clients.values_mut() {
+            client
+                .outstanding
+                .retain(|_, &mut since| now.duration_since(since) < REPLY_TIMEOUT);
+        }
+
+        let waiting = frame::senders(&frame).into_iter().find_map(|device| {
+            clients
+                .iter()
+                .filter_map(|(id, client)| Some((client.outstanding.get(&device).copied()?, id)))
+                .min_by_key(|(since, _)| *since)
+                .map(|(_, id)| (device, id.clone()))
+        });
+        match waiting.and_then(|(device, id)| Some((device, clients.get_mut(&id)?))) {
+            Some((device, client)) => {
+                client.outstanding.remove(&device);
+                client.push(frame);
+            }
+            None => clients.values_mut().for_each(|client| client.push(frame)),
+        }
+        self.delivered.notify_all();
+    }
+
+    /// Takes the oldest frame delivered to `client`, waiting up to `timeout` for one.
+    pub fn read(&self, client: &str, timeout: Duration) -> Option<[u8; 8]> {
+        let now = Instant::now();
+        let mut clients = self.clients.lock().unwrap();
+        clients
+            .entry(client.to_string())
+            .or_insert_with(|| Client::new(now))
+            .last_seen = now;
+        let (mut clients, _) = self
+            .delivered
+            .wait_timeout_while(clients, timeout, |clients| {
+                clients
+                    .get(client)
+                    .is_none_or(|client| client.queue.is_empty())
+            })
+            .unwrap();
+        let client = clients.get_mut(client)?;
+        client.last_seen = Instant::now();
+        client.queue.pop_front()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STATUS_REQUEST: [u8; 8] = [0x01, 0x60, 0x01, 0, 0, 0, 0, 0];
+    const REPLY: [u8; 8] = [0x01, 0, 0, 90, 0, 100, 0, 0];
+    const TIMEOUT: Duration = Duration::from_millis(10);
+
+    #[test]
+    fn replies_go_to_the_longest_waiting_client() {
+        let router = Router::default();
+        router.expect("a", &STATUS_REQUEST);
+        router.expect("b", &STATUS_REQUEST);
+        router.route(REPLY);
+        assert_eq!(router.read("a", TIMEOUT), Some(REPLY));
+        assert_eq!(router.read("b", TIMEOUT), None);
+        router.route(REPLY);
+        assert_eq!(router.read("b", TIMEOUT), Some(REPLY));
+        assert_eq!(router.read("a", TIMEOUT), None);
+    }
+
+    #[test]
+    fn unsolicited_frames_go_to_every_client() {
+        let router = Router::default();
+        router.expect("a", &STATUS_REQUEST);
+        assert_eq!(router.read("b", TIMEOUT), None);
+        let other = [0x05, 0, 0, 0, 0, 0, 0, 0];
+        router.route(other);
+        assert_eq!(router.read("a", TIMEOUT), Some(other));
+        assert_eq!(router.read("b", TIMEOUT), Some(other));
+        // The request of `a` is still outstanding.
+        router.route(REPLY);
+        assert_eq!(router.read("a", TIMEOUT), Some(REPLY));
+        assert_eq!(router.read("b", TIMEOUT), None);
+    }
+
+    #[test]
+    fn frames_of_other_lengths_expect_no_reply() {
+        let router = Router::default();
+        router.expect("a", &STATUS_REQUEST[..4]);
+        router.expect("b", &[]);
+        router.route(REPLY);
+        assert_eq!(router.read("a", TIMEOUT), Some(REPLY));
+        assert_eq!(router.read("b", TIMEOUT), Some(REPLY));
+    }
+
+    #[test]
+    fn a_full_queue_drops_the_oldest_frame() {
+        let router = Router::default();
+        assert_eq!(router.read("a", TIMEOUT), None);
+        for index in 0..=QUEUE_CAPACITY {
+            let mut frame = [0x05, 0, 0, 0, 0, 0, 0, 0];
+            frame[6..].copy_from_slice(&(index as u16).to_be_bytes());
+            router.route(frame);
+        }
+        assert_eq!(router.read("a", TIMEOUT), Some([0x05, 0, 0, 0, 0, 0, 0, 1]));
+    }
+}
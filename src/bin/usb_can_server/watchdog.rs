@@ -0,0 +1,242 @@
+//! Stops the devices of a client that disconnects or goes silent.
+//!
+//! A client becomes a controlling session when it sends an output command, such as a PWM
+//! duty cycle or a BLMD current. If the session sends no request for `deadline`, or its
+//! `Control` stream closes, the watchdog zeros the outputs of every device the session
+//! commanded, or sends the emergency frame.
+
+use motor_lib::frame::{self, Command};
+use motor_lib::recording::Direction;
+use motor_lib::{blmd, device_type, md, sd};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::bus::Bus;
+use crate::router::ClientId;
+
+/// What the watchdog sends when a session ends.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Failsafe {
+    /// Zero the output of every device the session commanded.
+    Zero,
+    /// Send the emergency frame, which stops every device on the bus.
+    Emergency,
+}
+
+#[derive(Debug)]
+struct Session {
+    /// When the client last made a request. A request in progress does not keep the
+    /// session alive, so a client stuck waiting for a reply is silent.
+    last_seen: Instant,
+    /// The last output command sent to each device.
+    devices: HashMap<(u8, u8), Command>,
+}
+
+#[derive(Debug)]
+pub struct Watchdog {
+    bus: Arc<Bus>,
+    sessions: Mutex<HashMap<ClientId, Session>>,
+    /// `None` disables the watchdog.
+    deadline: Option<Duration>,
+    failsafe: Failsafe,
+}
+
+/// Returns the frames that zero the output set by `command`. Servos keep their angle.
+fn zero_frames(command: &Command) -> Vec<[u8; 8]> {
+    match command.device_type {
+        device_type::MD => vec![[
+            command.address,
+            device_type::MASTER,
+            md::mode::PWM,
+            0,
+            0,
+            0,
+            0,
+            0,
+        ]],
+        device_type::SD => (0..2)
+            .map(|port| {
+                [
+                    command.address,
+                    device_type::MASTER,
+                    sd::mode::SINGLE_POWER,
+                    port,
+                    0,
+                    0,
+                    0,
+                    0,
+                ]
+            })
+            .collect(),
+        device_type::BLMD => vec![[
+            command.address,
+            command.semi_id,
+            blmd::mode::CURRENT,
+            0,
+            0,
+            0,
+            0,
+            0,
+        ]],
+        _ => Vec::new(),
+    }
+}
+
+impl Watchdog {
+    /// Creates the watchdog of the sessions on `bus` and starts the thread that checks them.
+    /// A `deadline` of `None` disables the watchdog.
+    pub fn start(bus: Arc<Bus>, deadline: Option<Duration>, failsafe: Failsafe) -> Arc<Watchdog> {
+        let watchdog = Arc::new(Watchdog {
+            bus,
+            sessions: Mutex::new(HashMap::new()),
+            deadline,
+            failsafe,
+        });
+        if let Some(deadline) = deadline {
+            let checker = Arc::clone(&watchdog);
+            std::thread::spawn(move || loop {
+                std::thread::sleep((deadline / 4).max(Duration::from_millis(10)));
+                checker.check(deadline);
+            });
+        }
+        watchdog
+    }
+
+    /// Records a frame that `client` has written to the bus.
+    pub fn command(&self, client: &str, frame: &[u8]) {
+        if self.deadline.is_none() {
+            return;
+        }
+        let Ok(buf) = <[u8; 8]>::try_from(frame) else {
+            return;
+        };
+        let frame = frame::decode(Direction::Write, &buf);
+        let (Some(device), frame::Frame::Command(command)) = (frame.device(), frame) else {
+            return;
+        };
+        let mut sessions = self.sessions.lock().unwrap();
+        if !command.is_output() {
+            if let Some(session) = sessions.get_mut(client) {
+                session.last_seen = Instant::now();
+            }
+            return;
+        }
+        let session = sessions
+            .entry(client.to_string())
+            .or_insert_with(|| Session {
+                last_seen: Instant::now(),
+                devices: HashMap::new(),
+            });
+        session.last_seen = Instant::now();
+        session.devices.insert(device, command);
+    }
+
+    /// Records a request of `client`, which keeps its session alive.
+    pub fn seen(&self, client: &str) {
+        if let Some(session) = self.sessions.lock().unwrap().get_mut(client) {
+            session.last_seen = Instant::now();
+        }
+    }
+
+    /// Returns whether `client` has a session that the watchdog would end.
+    #[cfg(test)]
+    pub fn has_session(&self, client: &str) -> bool {
+        self.sessions.lock().unwrap().contains_key(client)
+    }
+
+    /// Ends the session of `client` now, for example when its stream closes.
+    pub fn close(&self, client: &str) {
+        let session = self.sessions.lock().unwrap().remove(client);
+        if let Some(session) = session {
+            self.stop(client, &session);
+        }
+    }
+
+    fn check(&self, deadline: Duration) {
+        let now = Instant::now();
+        let expired: Vec<(ClientId, Session)> = {
+            let mut sessions = self.sessions.lock().unwrap();
+            let clients: Vec<ClientId> = sessions
+                .iter()
+                .filter(|(_, session)| now.duration_since(session.last_seen) >= deadline)
+                .map(|(client, _)| client.clone())
+                .collect();
+            clients
+                .iter()
+                .filter_map(|client| sessions.remove_entry(client))
+                .collect()
+        };
+        for (client, session) in expired {
+            self.stop(&client, &session);
+        }
+    }
+
+    fn stop(&self, client: &str, session: &Session) {
+        if session.devices.is_empty() {
+            return;
+        }
+        warn!(
+            "Client {} is gone, sending the failsafe for {} devices",
+            client,
+            session.devices.len()
+        );
+        let result = match self.failsafe {
+            Failsafe::Emergency => motor_lib::send_emergency(&*self.bus).map(|_| ()),
+            Failsafe::Zero => session
+                .devices
+                .values()
+                .flat_map(zero_frames)
+                .try_for_each(|frame| self.bus.write(&frame, self.bus.timeout()).map(|_| ())),
+        };
+        if let Err(e) = result {
+            error!("Failsafe error: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::RwLock;
+
+    const PWM: [u8; 8] = [0x00, device_type::MASTER, md::mode::PWM, 0, 100, 0, 0, 0];
+
+    fn watchdog() -> Arc<Watchdog> {
+        let timeout = Duration::from_millis(10);
+        let bus = Bus::start("test".to_string(), Arc::new(RwLock::new(None)), 1, timeout);
+        Watchdog::start(bus, Some(Duration::from_millis(50)), Failsafe::Zero)
+    }
+
+    #[test]
+    fn silent_client_is_stopped() {
+        let watchdog = watchdog();
+        watchdog.command("a", &PWM);
+        assert!(watchdog.has_session("a"));
+        std::thread::sleep(Duration::from_millis(150));
+        assert!(!watchdog.has_session("a"));
+    }
+
+    #[test]
+    fn requests_keep_the_session_alive() {
+        let watchdog = watchdog();
+        watchdog.command("a", &PWM);
+        for _ in 0..10 {
+            std::thread::sleep(Duration::from_millis(15));
+            watchdog.seen("a");
+        }
+        assert!(watchdog.has_session("a"));
+    }
+
+    #[test]
+    fn non_output_commands_open_no_session() {
+        let watchdog = watchdog();
+        watchdog.command(
+            "a",
+            &[0x00, device_type::MASTER, md::mode::STATUS, 0, 0, 0, 0, 0],
+        );
+        assert!(!watchdog.has_session("a"));
+    }
+}
@@ -9,7 +9,7 @@ pub mod mode {
     pub const ANGLE: u8 = 4;
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub struct BlMdStatus {
     pub std_id: u16,
     pub angle: i16,
@@ -69,6 +69,16 @@ pub fn send_current(
     return receive_status(handle, controller_id);
 }
 
+/// Requests the status of the specified BLMD controller without changing its output.
+pub fn request_status(
+    handle: &impl HandleTrait,
+    address: u8,
+    controller_id: u8,
+) -> Result<usize, crate::Error> {
+    let send_buf: [u8; 8] = [address, controller_id, mode::STATUS, 0, 0, 0, 0, 0];
+    handle.write_bulk(&send_buf, Duration::from_millis(5000))
+}
+
 pub fn receive_status(
     handle: &impl HandleTrait,
     controller_id: u8,
@@ -81,12 +91,17 @@ pub fn receive_status(
         }
         let received_stdid = (receive_buf[0] as u16) << 8 | (receive_buf[1] as u16);
         if received_stdid == (0x200 + (controller_id as u16)) {
-            return Ok(BlMdStatus {
-                std_id: received_stdid,
-                angle: ((receive_buf[2] as i16) << 8 | (receive_buf[3] as i16)),
-                speed: ((receive_buf[4] as i16) << 8 | (receive_buf[5] as i16)),
-                current: ((receive_buf[6] as i16) << 8 | (receive_buf[7] as i16)),
-            });
+            return Ok(decode_status(&receive_buf));
         }
     }
 }
+
+/// Decodes a status frame received from a BLMD controller.
+pub fn decode_status(receive_buf: &[u8; 8]) -> BlMdStatus {
+    BlMdStatus {
+        std_id: (receive_buf[0] as u16) << 8 | (receive_buf[1] as u16),
+        angle: ((receive_buf[2] as i16) << 8 | (receive_buf[3] as i16)),
+        speed: ((receive_buf[4] as i16) << 8 | (receive_buf[5] as i16)),
+        current: ((receive_buf[6] as i16) << 8 | (receive_buf[7] as i16)),
+    }
+}
@@ -0,0 +1,404 @@
+//! Conversion between the raw values of the devices and physical units.
+//!
+//! The device modules send and return raw `i16` values: degrees and rpm for MD, encoder
+//! counts for BLMD angles, degrees for SMD. A `Calibration` adds the direction, gear ratio
+//! and zero offset of one device, so that application code works in radians, rad/s (or
+//! m/s for wheels) and amperes at the output of the gearbox.
+//!
+//! ```rust,no_run
+//! use motor_lib::calibration::{Calibrated, Calibration, RawUnits};
+//! use motor_lib::device::Md;
+//! use motor_lib::{Error, USBHandle};
+//! fn main() -> Result<(), Error> {
+//!     let handle = USBHandle::new(0x483, 0x5740, 1);
+//!     let calibration = Calibration {
+//!         inverted: true,
+//!         gear_ratio: 19.2,
+//!         wheel_radius: Some(0.05),
+//!         ..Calibration::new(RawUnits::MD)
+//!     };
+//!     let mut wheel = Calibrated::new(Md::new(&handle, 0x00)?, calibration);
+//!     let status = wheel.set_velocity(0.5)?;
+//!     println!("{:.3} m/s", status.velocity);
+//!     Ok(())
+//! }
+//! ```
+
+use std::f64::consts::PI;
+
+use serde::Deserialize;
+
+use crate::actuator::{Motor, MotorStatus, Servo};
+use crate::device_type;
+use crate::md::LimSwStatus;
+use crate::safety::{Action, SafetyLimits};
+
+/// The physical value of one raw count of a device.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RawUnits {
+    /// Radians per angle count.
+    pub angle: f64,
+    /// Radians per second per speed count.
+    pub speed: f64,
+    /// Amperes per current count.
+    pub current: f64,
+}
+
+impl RawUnits {
+    /// MD angles are in degrees and speeds in rpm.
+    pub const MD: Self = Self {
+        angle: PI / 180.0,
+        speed: 2.0 * PI / 60.0,
+        current: 0.0,
+    };
+
+    /// BLMD angles are encoder counts (8192 per turn), speeds are in rpm and currents
+    /// span -20 A to 20 A over -16384 to 16384.
+    pub const BLMD: Self = Self {
+        angle: 2.0 * PI / 8192.0,
+        speed: 2.0 * PI / 60.0,
+        current: 20.0 / 16384.0,
+    };
+
+    /// SMD angles are in degrees.
+    pub const SMD: Self = Self {
+        angle: PI / 180.0,
+        speed: 0.0,
+        current: 0.0,
+    };
+
+    /// Returns the raw units of `device_type`, or `None` if it has no calibrated values.
+    pub fn of(device_type: u8) -> Option<Self> {
+        match device_type {
+            device_type::MD => Some(Self::MD),
+            device_type::BLMD => Some(Self::BLMD),
+            device_type::SMD => Some(Self::SMD),
+            _ => None,
+        }
+    }
+}
+
+/// The calibration of one device.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Calibration {
+    /// Reverses the direction of every value.
+    pub inverted: bool,
+    /// The number of motor turns per output turn.
+    pub gear_ratio: f64,
+    /// The output position, in radians, at which the device reports a raw angle of zero.
+    pub offset: f64,
+    /// The radius of the wheel on the output. When set, velocities are in m/s instead of rad/s.
+    pub wheel_radius: Option<f64>,
+    pub raw: RawUnits,
+}
+
+impl Calibration {
+    /// A calibration without inversion, gearing or offset.
+    pub fn new(raw: RawUnits) -> Self {
+        Self {
+            inverted: false,
+            gear_ratio: 1.0,
+            offset: 0.0,
+            wheel_radius: None,
+            raw,
+        }
+    }
+
+    fn sign(&self) -> f64 {
+        if self.inverted {
+            -1.0
+        } else {
+            1.0
+        }
+    }
+
+    /// Returns true if a positive output value is a negative raw value.
+    fn reverses(&self) -> bool {
+        self.sign() * self.gear_ratio < 0.0
+    }
+
+    fn velocity_scale(&self) -> f64 {
+        self.sign() * self.raw.speed / self.gear_ratio * self.wheel_radius.unwrap_or(1.0)
+    }
+
+    /// Converts a raw angle to the output position in radians.
+    pub fn position(&self, raw: i16) -> f64 {
+        self.sign() * raw as f64 * self.raw.angle / self.gear_ratio + self.offset
+    }
+
+    /// Converts an output position in radians to a raw angle.
+    pub fn raw_position(&self, position: f64) -> i16 {
+        to_raw((position - self.offset) * self.gear_ratio / (self.sign() * self.raw.angle))
+    }
+
+    /// Converts a raw speed to the output velocity in rad/s, or m/s with a wheel radius.
+    pub fn velocity(&self, raw: i16) -> f64 {
+        raw as f64 * self.velocity_scale()
+    }
+
+    /// Converts an output velocity in rad/s, or m/s with a wheel radius, to a raw speed.
+    pub fn raw_velocity(&self, velocity: f64) -> i16 {
+        to_raw(velocity / self.velocity_scale())
+    }
+
+    /// Converts a raw current to amperes.
+    pub fn current(&self, raw: i16) -> f64 {
+        self.sign() * raw as f64 * self.raw.current
+    }
+
+    /// Converts a current in amperes to a raw current.
+    pub fn raw_current(&self, current: f64) -> i16 {
+        to_raw(current / (self.sign() * self.raw.current))
+    }
+
+    /// Applies the direction to a raw duty cycle.
+    pub fn raw_duty(&self, duty: i16) -> i16 {
+        if self.inverted {
+            duty.saturating_neg()
+        } else {
+            duty
+        }
+    }
+
+    /// Converts `limits` to the raw units and directions of the device. When the device
+    /// reverses the output, the position range is mirrored and the limit switches at the
+    /// positive and negative ends of travel are swapped.
+    pub fn raw_limits(&self, limits: &PhysicalLimits) -> SafetyLimits {
+        let mut min_position = limits.min_position.map(|p| self.raw_position(p));
+        let mut max_position = limits.max_position.map(|p| self.raw_position(p));
+        let mut positive_limsw = limits.positive_limsw;
+        let mut negative_limsw = limits.negative_limsw;
+        if self.reverses() {
+            std::mem::swap(&mut min_position, &mut max_position);
+            std::mem::swap(&mut positive_limsw, &mut negative_limsw);
+        }
+        SafetyLimits {
+            min_position,
+            max_position,
+            max_duty: limits.max_duty,
+            max_speed: limits
+                .max_velocity
+                .map(|v| self.raw_velocity(v).saturating_abs()),
+            max_current: limits
+                .max_current
+                .map(|c| self.raw_current(c).saturating_abs()),
+            action: limits.action,
+            positive_limsw,
+            negative_limsw,
+        }
+    }
+
+    /// Converts the raw status of a motor to physical units.
+    pub fn status(&self, status: &MotorStatus) -> PhysicalStatus {
+        PhysicalStatus {
+            position: self.position(status.angle),
+            velocity: self.velocity(status.speed),
+            current: status.current.map(|c| self.current(c)),
+            limsw: status.limsw,
+        }
+    }
+}
+
+/// Rounds `value` to the nearest raw value, saturating at the limits of `i16`.
+///
+/// A value divided by a zero scale, which the device cannot represent, becomes 0.
+fn to_raw(value: f64) -> i16 {
+    if value.is_finite() {
+        value.round() as i16
+    } else {
+        0
+    }
+}
+
+/// The safety limits of a device in the physical units of its calibration, with the
+/// directions of the output. `None` means unlimited.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PhysicalLimits {
+    /// The lowest output position, in radians.
+    pub min_position: Option<f64>,
+    /// The highest output position, in radians.
+    pub max_position: Option<f64>,
+    /// The largest absolute PWM duty cycle, in raw units.
+    pub max_duty: Option<i16>,
+    /// The largest absolute output velocity, in rad/s, or m/s with a wheel radius.
+    pub max_velocity: Option<f64>,
+    /// The largest absolute motor current, in amperes.
+    pub max_current: Option<f64>,
+    /// `"clamp"` or `"reject"`.
+    #[serde(default)]
+    pub action: Action,
+    /// The port of the limit switch pressed at the positive end of output travel.
+    pub positive_limsw: Option<u8>,
+    /// The port of the limit switch pressed at the negative end of output travel.
+    pub negative_limsw: Option<u8>,
+}
+
+/// The status of a motor in physical units.
+#[derive(Debug, Clone, Copy)]
+pub struct PhysicalStatus {
+    /// The output position in radians.
+    pub position: f64,
+    /// The output velocity in rad/s, or m/s with a wheel radius.
+    pub velocity: f64,
+    /// The motor current in amperes, if the driver measures it.
+    pub current: Option<f64>,
+    pub limsw: Option<LimSwStatus>,
+}
+
+/// A motor or servo commanded in physical units.
+pub struct Calibrated<D> {
+    device: D,
+    calibration: Calibration,
+}
+
+impl<D> Calibrated<D> {
+    pub fn new(device: D, calibration: Calibration) -> Self {
+        Self {
+            device,
+            calibration,
+        }
+    }
+
+    pub fn calibration(&self) -> &Calibration {
+        &self.calibration
+    }
+
+    /// Returns the wrapped device, for commands in raw units.
+    pub fn inner(&mut self) -> &mut D {
+        &mut self.device
+    }
+
+    pub fn into_inner(self) -> D {
+        self.device
+    }
+}
+
+impl<M: Motor> Calibrated<M> {
+    /// Sets the raw PWM duty cycle, in the calibrated direction.
+    pub fn set_duty(&mut self, duty: i16) -> Result<PhysicalStatus, crate::Error> {
+        let status = self.device.set_duty(self.calibration.raw_duty(duty))?;
+        Ok(self.calibration.status(&status))
+    }
+
+    /// Sets the output velocity in rad/s, or m/s with a wheel radius.
+    pub fn set_velocity(&mut self, velocity: f64) -> Result<PhysicalStatus, crate::Error> {
+        let status = self
+            .device
+            .set_velocity(self.calibration.raw_velocity(velocity))?;
+        Ok(self.calibration.status(&status))
+    }
+
+    /// Sets the output position in radians.
+    pub fn set_position(&mut self, position: f64) -> Result<PhysicalStatus, crate::Error> {
+        let status = self
+            .device
+            .set_position(self.calibration.raw_position(position))?;
+        Ok(self.calibration.status(&status))
+    }
+
+    /// Sets the motor current in amperes.
+    pub fn set_current(&mut self, current: f64) -> Result<PhysicalStatus, crate::Error> {
+        let status = self
+            .device
+            .set_current(self.calibration.raw_current(current))?;
+        Ok(self.calibration.status(&status))
+    }
+
+    /// Returns the last status received from the driver, in physical units.
+    pub fn status(&self) -> Option<PhysicalStatus> {
+        self.device
+            .status()
+            .map(|status| self.calibration.status(&status))
+    }
+
+    /// Requests the status from the driver and waits for it, in physical units.
+    pub fn read_status(&mut self) -> Result<PhysicalStatus, crate::Error> {
+        let status = self.device.read_status()?;
+        Ok(self.calibration.status(&status))
+    }
+}
+
+impl<S: Servo> Calibrated<S> {
+    /// Sets the output angle in radians and returns the angle reported by the driver.
+    pub fn set_angle(&mut self, angle: f64) -> Result<f64, crate::Error> {
+        let angle = self
+            .device
+            .set_angle(self.calibration.raw_position(angle))?;
+        Ok(self.calibration.position(angle))
+    }
+
+    /// Returns the last angle received from the driver, in radians.
+    pub fn angle(&self) -> Option<f64> {
+        self.device
+            .angle()
+            .map(|angle| self.calibration.position(angle))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9
+    }
+
+    #[test]
+    fn raw_values_round_trip() {
+        let calibration = Calibration {
+            gear_ratio: 2.0,
+            offset: 0.5,
+            ..Calibration::new(RawUnits::MD)
+        };
+        assert!(close(calibration.position(180), PI / 2.0 + 0.5));
+        assert_eq!(calibration.raw_position(PI / 2.0 + 0.5), 180);
+        assert!(close(calibration.velocity(60), PI));
+        assert_eq!(calibration.raw_velocity(PI), 60);
+    }
+
+    #[test]
+    fn inversion_reverses_every_value() {
+        let calibration = Calibration {
+            inverted: true,
+            ..Calibration::new(RawUnits::BLMD)
+        };
+        assert_eq!(calibration.raw_position(PI), -4096);
+        assert!(close(calibration.velocity(60), -2.0 * PI));
+        assert_eq!(calibration.raw_current(10.0), -8192);
+        assert_eq!(calibration.raw_duty(i16::MIN), i16::MAX);
+    }
+
+    #[test]
+    fn raw_values_saturate() {
+        let calibration = Calibration::new(RawUnits::MD);
+        assert_eq!(calibration.raw_position(1e9), i16::MAX);
+        assert_eq!(calibration.raw_position(-1e9), i16::MIN);
+        // MD has no current unit, so no current can be represented.
+        assert_eq!(calibration.raw_current(1.0), 0);
+    }
+
+    #[test]
+    fn inverted_limits_swap_ends_and_switches() {
+        let limits = PhysicalLimits {
+            min_position: Some(-PI / 2.0),
+            max_position: Some(PI),
+            max_velocity: Some(2.0 * PI),
+            positive_limsw: Some(1),
+            negative_limsw: Some(0),
+            ..PhysicalLimits::default()
+        };
+        let raw = Calibration::new(RawUnits::MD).raw_limits(&limits);
+        assert_eq!((raw.min_position, raw.max_position), (Some(-90), Some(180)));
+        assert_eq!((raw.positive_limsw, raw.negative_limsw), (Some(1), Some(0)));
+
+        let inverted = Calibration {
+            inverted: true,
+            ..Calibration::new(RawUnits::MD)
+        };
+        let raw = inverted.raw_limits(&limits);
+        assert_eq!((raw.min_position, raw.max_position), (Some(-180), Some(90)));
+        assert_eq!((raw.positive_limsw, raw.negative_limsw), (Some(0), Some(1)));
+        assert_eq!(raw.max_speed, Some(60));
+    }
+}
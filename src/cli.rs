@@ -0,0 +1,165 @@
+//! Argument parsing shared by the command-line tools of the crate.
+
+use std::iter::Peekable;
+
+use crate::robot::TransportConfig;
+
+/// The vendor ID of the USB-CAN adapter.
+pub const VENDOR_ID: u16 = 0x483;
+/// The product ID of the USB-CAN adapter.
+pub const PRODUCT_ID: u16 = 0x5740;
+/// The interface of the USB-CAN adapter that carries the bus.
+pub const INTERFACE: u8 = 1;
+
+/// Parses an unsigned number, in hexadecimal with a `0x` prefix and in decimal otherwise.
+///
+/// # Example
+///
+/// ```rust
+/// use motor_lib::cli::parse_number;
+/// assert_eq!(parse_number::<u8>("0x10"), Some(16));
+/// assert_eq!(parse_number::<u8>("10"), Some(10));
+/// assert_eq!(parse_number::<u8>("0x100"), None);
+/// ```
+pub fn parse_number<T: TryFrom<u64>>(str: &str) -> Option<T> {
+    let number = match str.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok()?,
+        None => str.parse().ok()?,
+    };
+    number.try_into().ok()
+}
+
+/// Parses the `VID:PID` of a USB device. Both IDs are in hexadecimal, with or without a
+/// `0x` prefix, as `lsusb` prints them.
+///
+/// # Example
+///
+/// ```rust
+/// use motor_lib::cli::parse_usb_id;
+/// assert_eq!(parse_usb_id("0483:5740"), Some((0x483, 0x5740)));
+/// assert_eq!(parse_usb_id("0x483:0x5740"), Some((0x483, 0x5740)));
+/// ```
+pub fn parse_usb_id(str: &str) -> Option<(u16, u16)> {
+    let hex = |id: &str| u16::from_str_radix(id.strip_prefix("0x").unwrap_or(id), 16).ok();
+    let (vendor_id, product_id) = str.split_once(':')?;
+    Some((hex(vendor_id)?, hex(product_id)?))
+}
+
+/// Parses the `--usb [VID:PID]` and `--grpc URL` flags with which a tool selects its
+/// transport. `args` are the arguments after `flag`; the value of the flag is taken from
+/// them.
+///
+/// # Example
+///
+/// ```rust
+/// use motor_lib::cli::parse_transport;
+/// use motor_lib::robot::TransportConfig;
+/// let mut args = vec!["483:5740".to_string(), "status".to_string()].into_iter().peekable();
+/// let transport = parse_transport("--usb", &mut args).unwrap();
+/// assert!(matches!(transport, TransportConfig::Usb { vendor_id: 0x483, .. }));
+/// assert_eq!(args.next().as_deref(), Some("status"));
+/// ```
+pub fn parse_transport<I: Iterator<Item = String>>(
+    flag: &str,
+    args: &mut Peekable<I>,
+) -> Result<TransportConfig, String> {
+    match flag {
+        "--usb" => {
+            let (vendor_id, product_id) = match args.peek().and_then(|next| parse_usb_id(next)) {
+                Some(id) => {
+                    args.next();
+                    id
+                }
+                None => (VENDOR_ID, PRODUCT_ID),
+            };
+            Ok(TransportConfig::Usb {
+                vendor_id,
+                product_id,
+                interface: INTERFACE,
+            })
+        }
+        "--grpc" => match args.next() {
+            Some(url) => Ok(TransportConfig::Grpc { url, bus: None }),
+            None => Err("--grpc needs a URL".to_string()),
+        },
+        _ => Err(format!("{} does not select a transport", flag)),
+    }
+}
+
+/// Returns the transport of a tool without `--usb` or `--grpc`: the adapter on USB.
+pub fn default_transport() -> TransportConfig {
+    TransportConfig::Usb {
+        vendor_id: VENDOR_ID,
+        product_id: PRODUCT_ID,
+        interface: INTERFACE,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn numbers_without_prefix_are_decimal() {
+        assert_eq!(parse_number::<u8>("16"), Some(16));
+        assert_eq!(parse_number::<u16>("5740"), Some(5740));
+        assert_eq!(parse_number::<u8>("1f"), None);
+    }
+
+    #[test]
+    fn numbers_with_prefix_are_hexadecimal() {
+        assert_eq!(parse_number::<u8>("0x1f"), Some(0x1f));
+        assert_eq!(parse_number::<u16>("0x5740"), Some(0x5740));
+        assert_eq!(parse_number::<u8>("0x"), None);
+    }
+
+    #[test]
+    fn numbers_out_of_range_are_rejected() {
+        assert_eq!(parse_number::<u8>("256"), None);
+        assert_eq!(parse_number::<u8>("-1"), None);
+        assert_eq!(parse_number::<u64>("18446744073709551615"), Some(u64::MAX));
+    }
+
+    fn transport(args: &[&str]) -> Result<TransportConfig, String> {
+        let mut args = args.iter().map(|arg| arg.to_string()).peekable();
+        let flag = args.next().unwrap();
+        parse_transport(&flag, &mut args)
+    }
+
+    #[test]
+    fn usb_transport_takes_an_optional_id() {
+        assert!(matches!(
+            transport(&["--usb", "1234:abcd"]),
+            Ok(TransportConfig::Usb {
+                vendor_id: 0x1234,
+                product_id: 0xabcd,
+                interface: INTERFACE
+            })
+        ));
+        assert!(matches!(
+            transport(&["--usb", "md"]),
+            Ok(TransportConfig::Usb {
+                vendor_id: VENDOR_ID,
+                product_id: PRODUCT_ID,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn grpc_transport_needs_a_url() {
+        assert!(matches!(
+            transport(&["--grpc", "http://[::1]:50051"]),
+            Ok(TransportConfig::Grpc { ref url, bus: None }) if url == "http://[::1]:50051"
+        ));
+        assert!(transport(&["--grpc"]).is_err());
+    }
+
+    #[test]
+    fn usb_ids_are_hexadecimal() {
+        assert_eq!(parse_usb_id("483:5740"), Some((0x483, 0x5740)));
+        assert_eq!(parse_usb_id("0x0483:5740"), Some((0x483, 0x5740)));
+        assert_eq!(parse_usb_id("483"), None);
+        assert_eq!(parse_usb_id("483:57400"), None);
+    }
+}
@@ -0,0 +1,424 @@
+//! Device objects that bind a handle, a device type and an address.
+//!
+//! Each object checks on construction that its address belongs to its device type,
+//! so an SD address can no longer be passed to an MD function by mistake. The methods
+//! mirror the functions of the corresponding module and keep the last status received.
+//!
+//! ```rust
+//! use motor_lib::device::{Md, Sd};
+//! use motor_lib::ReplayHandle;
+//! let handle = ReplayHandle::new(Vec::new());
+//! assert!(Md::new(&handle, 0x10).is_err());
+//! assert!(Sd::new(&handle, 0x10).is_ok());
+//! ```
+
+use crate::blmd::BlMdStatus;
+use crate::md::MdStatus;
+use crate::sd::SdStatus;
+use crate::smd::SmdStatus;
+use crate::{blmd, device_type, md, sd, smd, sr, HandleTrait};
+
+fn check_address(address: u8, expected_type: u8) -> Result<(), crate::Error> {
+    if device_type::of(address) == expected_type {
+        Ok(())
+    } else {
+        Err(crate::Error::InvalidAddress(address))
+    }
+}
+
+fn check_port(port: u8) -> Result<(), crate::Error> {
+    if port <= 1 {
+        Ok(())
+    } else {
+        Err(crate::Error::InvalidPort(port))
+    }
+}
+
+/// An MD device at an address in `0x00..=0x0F`.
+pub struct Md<'a, H: HandleTrait> {
+    handle: &'a H,
+    address: u8,
+    last_status: Option<MdStatus>,
+}
+
+impl<'a, H: HandleTrait> Md<'a, H> {
+    /// Binds the MD at `address` to `handle`.
+    ///
+    /// # Example
+    ///
+    /// Sample code to rotate a motor connected to the MD at address 0x00 at a PWM duty cycle of 1000.
+    /// ```rust,no_run
+    /// use motor_lib::{device::Md, Error, USBHandle};
+    /// fn main() -> Result<(), Error> {
+    ///     let handle = USBHandle::new(0x483, 0x5740, 1);
+    ///     let mut md = Md::new(&handle, 0x00)?;
+    ///     md.send_pwm(1000)?;
+    ///     println!("{:?}", md.last_status());
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn new(handle: &'a H, address: u8) -> Result<Self, crate::Error> {
+        check_address(address, device_type::MD)?;
+        Ok(Self {
+            handle,
+            address,
+            last_status: None,
+        })
+    }
+
+    pub fn address(&self) -> u8 {
+        self.address
+    }
+
+    /// Returns the last status received from the device.
+    pub fn last_status(&self) -> Option<&MdStatus> {
+        self.last_status.as_ref()
+    }
+
+    fn keep(&mut self, status: MdStatus) -> MdStatus {
+        self.last_status = Some(status);
+        status
+    }
+
+    /// See `md::send_pwm`.
+    pub fn send_pwm(&mut self, power: i16) -> Result<MdStatus, crate::Error> {
+        let status = md::send_pwm(self.handle, self.address, power)?;
+        Ok(self.keep(status))
+    }
+
+    /// See `md::send_speed`.
+    pub fn send_speed(&mut self, velocity: i16) -> Result<MdStatus, crate::Error> {
+        let status = md::send_speed(self.handle, self.address, velocity)?;
+        Ok(self.keep(status))
+    }
+
+    /// See `md::send_angle`.
+    pub fn send_angle(&mut self, angle: i16) -> Result<MdStatus, crate::Error> {
+        let status = md::send_angle(self.handle, self.address, angle)?;
+        Ok(self.keep(status))
+    }
+
+    /// See `md::send_limsw`.
+    pub fn send_limsw(
+        &mut self,
+        port: u8,
+        power: i16,
+        after_power: i16,
+    ) -> Result<MdStatus, crate::Error> {
+        let status = md::send_limsw(self.handle, self.address, port, power, after_power)?;
+        Ok(self.keep(status))
+    }
+
+    /// See `md::request_status`.
+    pub fn request_status(&self) -> Result<usize, crate::Error> {
+        md::request_status(self.handle, self.address)
+    }
+
+    /// See `md::receive_status`.
+    pub fn receive_status(&mut self) -> Result<MdStatus, crate::Error> {
+        let status = md::receive_status(self.handle, self.address)?;
+        Ok(self.keep(status))
+    }
+}
+
+/// An SD device at an address in `0x10..=0x1F`.
+pub struct Sd<'a, H: HandleTrait> {
+    handle: &'a H,
+    address: u8,
+    last_status: Option<SdStatus>,
+}
+
+impl<'a, H: HandleTrait> Sd<'a, H> {
+    /// Binds the SD at `address` to `handle`.
+    pub fn new(handle: &'a H, address: u8) -> Result<Self, crate::Error> {
+        check_address(address, device_type::SD)?;
+        Ok(Self {
+            handle,
+            address,
+            last_status: None,
+        })
+    }
+
+    pub fn address(&self) -> u8 {
+        self.address
+    }
+
+    /// Returns the last status received from the device.
+    pub fn last_status(&self) -> Option<&SdStatus> {
+        self.last_status.as_ref()
+    }
+
+    fn keep(&mut self, status: SdStatus) -> SdStatus {
+        self.last_status = Some(status);
+        status
+    }
+
+    /// See `sd::send_power`.
+    pub fn send_power(&mut self, port: u8, power: i16) -> Result<SdStatus, crate::Error> {
+        let status = sd::send_power(self.handle, self.address, port, power)?;
+        Ok(self.keep(status))
+    }
+
+    /// See `sd::send_powers`.
+    pub fn send_powers(&mut self, power_0: i16, power_1: i16) -> Result<SdStatus, crate::Error> {
+        let status = sd::send_powers(self.handle, self.address, power_0, power_1)?;
+        Ok(self.keep(status))
+    }
+
+    /// Returns the solenoid connected to `port` (0 or 1), for use through the `Solenoid` trait.
+    /// The port sends through this SD and shares its last status.
+    pub fn port(&mut self, port: u8) -> Result<SdPort<'_, 'a, H>, crate::Error> {
+        check_port(port)?;
+        Ok(SdPort { sd: self, port })
+    }
+
+    /// See `sd::request_status`.
+    pub fn request_status(&self) -> Result<usize, crate::Error> {
+        sd::request_status(self.handle, self.address)
+    }
+
+    /// See `sd::receive_status`.
+    pub fn receive_status(&mut self) -> Result<SdStatus, crate::Error> {
+        let status = sd::receive_status(self.handle, self.address)?;
+        Ok(self.keep(status))
+    }
+}
+
+/// One port of an SD device.
+pub struct SdPort<'p, 'a, H: HandleTrait> {
+    pub(crate) sd: &'p mut Sd<'a, H>,
+    pub(crate) port: u8,
+}
+
+impl<H: HandleTrait> SdPort<'_, '_, H> {
+    pub fn address(&self) -> u8 {
+        self.sd.address
+    }
+
+    pub fn port(&self) -> u8 {
+        self.port
+    }
+}
+
+/// An SMD device at an address in `0x20..=0x2F`.
+pub struct Smd<'a, H: HandleTrait> {
+    handle: &'a H,
+    address: u8,
+    last_status: Option<SmdStatus>,
+}
+
+impl<'a, H: HandleTrait> Smd<'a, H> {
+    /// Binds the SMD at `address` to `handle`.
+    pub fn new(handle: &'a H, address: u8) -> Result<Self, crate::Error> {
+        check_address(address, device_type::SMD)?;
+        Ok(Self {
+            handle,
+            address,
+            last_status: None,
+        })
+    }
+
+    pub fn address(&self) -> u8 {
+        self.address
+    }
+
+    /// Returns the last status received from the device.
+    pub fn last_status(&self) -> Option<&SmdStatus> {
+        self.last_status.as_ref()
+    }
+
+    fn keep(&mut self, status: SmdStatus) -> SmdStatus {
+        self.last_status = Some(status);
+        status
+    }
+
+    /// See `smd::send_angle`.
+    pub fn send_angle(&mut self, port: u8, angle: i16) -> Result<SmdStatus, crate::Error> {
+        let status = smd::send_angle(self.handle, self.address, port, angle)?;
+        Ok(self.keep(status))
+    }
+
+    /// See `smd::send_angles`.
+    pub fn send_angles(&mut self, angle_0: i16, angle_1: i16) -> Result<SmdStatus, crate::Error> {
+        let status = smd::send_angles(self.handle, self.address, angle_0, angle_1)?;
+        Ok(self.keep(status))
+    }
+
+    /// Returns the servo connected to `port` (0 or 1), for use through the `Servo` trait.
+    /// The port sends through this SMD and shares its last status.
+    pub fn port(&mut self, port: u8) -> Result<SmdPort<'_, 'a, H>, crate::Error> {
+        check_port(port)?;
+        Ok(SmdPort { smd: self, port })
+    }
+
+    /// See `smd::request_status`.
+    pub fn request_status(&self) -> Result<usize, crate::Error> {
+        smd::request_status(self.handle, self.address)
+    }
+
+    /// See `smd::receive_status`.
+    pub fn receive_status(&mut self) -> Result<SmdStatus, crate::Error> {
+        let status = smd::receive_status(self.handle, self.address)?;
+        Ok(self.keep(status))
+    }
+}
+
+/// One port of an SMD device.
+pub struct SmdPort<'p, 'a, H: HandleTrait> {
+    pub(crate) smd: &'p mut Smd<'a, H>,
+    pub(crate) port: u8,
+}
+
+impl<H: HandleTrait> SmdPort<'_, '_, H> {
+    pub fn address(&self) -> u8 {
+        self.smd.address
+    }
+
+    pub fn port(&self) -> u8 {
+        self.port
+    }
+}
+
+/// A BLMD controller, identified by a BLMD address in `0x30..=0x3F` and a controller ID in `1..=8`.
+pub struct BlMd<'a, H: HandleTrait> {
+    handle: &'a H,
+    address: u8,
+    controller_id: u8,
+    last_status: Option<BlMdStatus>,
+}
+
+impl<'a, H: HandleTrait> BlMd<'a, H> {
+    /// Binds the controller `controller_id` of the BLMD at `address` to `handle`.
+    pub fn new(handle: &'a H, address: u8, controller_id: u8) -> Result<Self, crate::Error> {
+        check_address(address, device_type::BLMD)?;
+        if !(1..=8).contains(&controller_id) {
+            return Err(crate::Error::InvalidAddress(controller_id));
+        }
+        Ok(Self {
+            handle,
+            address,
+            controller_id,
+            last_status: None,
+        })
+    }
+
+    pub fn address(&self) -> u8 {
+        self.address
+    }
+
+    pub fn controller_id(&self) -> u8 {
+        self.controller_id
+    }
+
+    /// Returns the last status received from the controller.
+    pub fn last_status(&self) -> Option<&BlMdStatus> {
+        self.last_status.as_ref()
+    }
+
+    fn keep(&mut self, status: BlMdStatus) -> BlMdStatus {
+        self.last_status = Some(status);
+        status
+    }
+
+    /// See `blmd::send_velocity`.
+    pub fn send_velocity(&mut self, velocity: i16) -> Result<BlMdStatus, crate::Error> {
+        let status = blmd::send_velocity(self.handle, self.address, self.controller_id, velocity)?;
+        Ok(self.keep(status))
+    }
+
+    /// See `blmd::send_current`.
+    pub fn send_current(&mut self, current: i16) -> Result<BlMdStatus, crate::Error> {
+        let status = blmd::send_current(self.handle, self.address, self.controller_id, current)?;
+        Ok(self.keep(status))
+    }
+
+    /// See `blmd::request_status`.
+    pub fn request_status(&self) -> Result<usize, crate::Error> {
+        blmd::request_status(self.handle, self.address, self.controller_id)
+    }
+
+    /// See `blmd::receive_status`.
+    pub fn receive_status(&mut self) -> Result<BlMdStatus, crate::Error> {
+        let status = blmd::receive_status(self.handle, self.controller_id)?;
+        Ok(self.keep(status))
+    }
+}
+
+/// The SR device.
+pub struct Sr<'a, H: HandleTrait> {
+    handle: &'a H,
+}
+
+impl<'a, H: HandleTrait> Sr<'a, H> {
+    /// Binds the SR to `handle`.
+    pub fn new(handle: &'a H) -> Self {
+        Self { handle }
+    }
+
+    /// See `sr::send_stop`.
+    pub fn send_stop(&self) -> Result<(), crate::Error> {
+        sr::send_stop(self.handle)
+    }
+
+    /// See `sr::send_start`.
+    pub fn send_start(&self, timeout: u16) -> Result<(), crate::Error> {
+        sr::send_start(self.handle, timeout)
+    }
+
+    /// See `sr::send_colors`.
+    pub fn send_colors(
+        &self,
+        red: u8,
+        green: u8,
+        blue: u8,
+        freq: f32,
+        timeout: u16,
+    ) -> Result<(), crate::Error> {
+        sr::send_colors(self.handle, red, green, blue, freq, timeout)
+    }
+
+    /// See `sr::request_status`.
+    pub fn request_status(&self) -> Result<usize, crate::Error> {
+        sr::request_status(self.handle)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::actuator::Solenoid;
+    use crate::recording::{Direction, Outcome, Record};
+    use crate::ReplayHandle;
+
+    fn record(direction: Direction, data: [u8; 8]) -> Record {
+        Record {
+            direction,
+            outcome: Outcome::Ok,
+            timestamp: std::time::Duration::ZERO,
+            data: data.to_vec(),
+        }
+    }
+
+    #[test]
+    fn ports_are_checked() {
+        let handle = ReplayHandle::new(Vec::new());
+        let mut sd = Sd::new(&handle, 0x10).unwrap();
+        assert!(sd.port(1).is_ok());
+        assert!(matches!(sd.port(2), Err(crate::Error::InvalidPort(2))));
+        let mut smd = Smd::new(&handle, 0x20).unwrap();
+        assert!(matches!(smd.port(7), Err(crate::Error::InvalidPort(7))));
+    }
+
+    #[test]
+    fn ports_share_the_status_of_their_device() {
+        let handle = ReplayHandle::new(vec![
+            record(Direction::Write, [0x11, 0x60, 0x03, 1, 0, 50, 0, 0]),
+            record(Direction::Read, [0x11, 0x00, 0, 20, 0, 50, 0, 0]),
+        ]);
+        let mut sd = Sd::new(&handle, 0x11).unwrap();
+        assert_eq!(sd.port(1).unwrap().set_power(50).unwrap(), 50);
+        assert_eq!(sd.port(0).unwrap().power(), Some(20));
+        assert_eq!(sd.last_status().map(|status| status.port_1), Some(50));
+        assert!(handle.divergences().is_empty());
+    }
+}
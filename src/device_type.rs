@@ -16,3 +16,30 @@ pub const SM: u8 = 0x50;
 pub const MASTER: u8 = 0x60;
 /// 0xF0
 pub const EMMERGENCY: u8 = 0xf0;
+
+/// Returns the device type of `address`, i.e. its upper four bits.
+pub fn of(address: u8) -> u8 {
+    address & 0xf0
+}
+
+/// Returns the name of `device_type`, or `None` if it is not a known device type.
+pub fn name(device_type: u8) -> Option<&'static str> {
+    match device_type {
+        MD => Some("MD"),
+        SD => Some("SD"),
+        SMD => Some("SMD"),
+        BLMD => Some("BLMD"),
+        SR => Some("SR"),
+        SM => Some("SM"),
+        MASTER => Some("MASTER"),
+        EMMERGENCY => Some("EMMERGENCY"),
+        _ => None,
+    }
+}
+
+/// Returns the device type called `name` (case-insensitive), or `None` if there is none.
+pub fn from_name(name: &str) -> Option<u8> {
+    [MD, SD, SMD, BLMD, SR, SM, MASTER, EMMERGENCY]
+        .into_iter()
+        .find(|&device_type| self::name(device_type).is_some_and(|n| n.eq_ignore_ascii_case(name)))
+}
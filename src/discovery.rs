@@ -0,0 +1,196 @@
+//! Discovery of the devices connected to the bus.
+
+use std::time::{Duration, Instant};
+
+use crate::frame::{self, Frame};
+use crate::{blmd, device_type, md, sd, smd, sr, HandleTrait};
+
+/// The time `scan` waits for the replies of each batch of status requests.
+pub const DEFAULT_DEADLINE: Duration = Duration::from_millis(50);
+
+/// The number of status requests `scan` sends before it collects their replies, so that
+/// the replies fit in the receive buffer of the adapter.
+pub const BATCH_SIZE: usize = 4;
+
+/// The range of BLMD controller IDs probed by `scan`.
+pub const BLMD_CONTROLLER_IDS: std::ops::RangeInclusive<u8> = 1..=8;
+
+/// A device that replied to a status request.
+#[derive(Debug)]
+pub struct Device {
+    pub device_type: u8,
+    /// The address of the device. For BLMD controllers this is the controller ID.
+    pub address: u8,
+    /// The first status frame received from the device.
+    pub status: Frame,
+}
+
+/// Probes every address range with status requests and returns the devices that replied.
+///
+/// The requests are sent `BATCH_SIZE` at a time, and the replies of each batch are collected
+/// before the next batch is sent. Each reply is decoded as the status of the device probed.
+///
+/// # Arguments
+///
+/// * `handle` - A reference to an object implementing the HandleTrait.
+///
+/// # Returns
+///
+/// A result containing the devices found, ordered by device type and address, or an Error.
+///
+/// # Example
+///
+/// Sample code to list the boards connected to the adapter.
+/// ```rust,no_run
+/// use motor_lib::{discovery, device_type, USBHandle, Error};
+/// fn main() -> Result<(), Error> {
+///     let handle = USBHandle::new(0x483, 0x5740, 1);
+///     for device in discovery::scan(&handle)? {
+///         let name = device_type::name(device.device_type).unwrap_or("?");
+///         println!("{} 0x{:02x}: {}", name, device.address, device.status);
+///     }
+///     Ok(())
+/// }
+/// ```
+pub fn scan(handle: &impl HandleTrait) -> Result<Vec<Device>, crate::Error> {
+    scan_with_deadline(handle, DEFAULT_DEADLINE)
+}
+
+/// Same as `scan`, but waits `deadline` for the replies of each batch of status requests.
+pub fn scan_with_deadline(
+    handle: &impl HandleTrait,
+    deadline: Duration,
+) -> Result<Vec<Device>, crate::Error> {
+    let mut probes: Vec<(u8, u8)> = Vec::new();
+    for device_type in [device_type::MD, device_type::SD, device_type::SMD] {
+        probes.extend((0..0x10).map(|id| (device_type, device_type | id)));
+    }
+    probes.extend(BLMD_CONTROLLER_IDS.map(|controller_id| (device_type::BLMD, controller_id)));
+    probes.push((device_type::SR, device_type::SR));
+
+    let mut devices: Vec<Device> = Vec::new();
+    for (index, batch) in probes.chunks(BATCH_SIZE).enumerate() {
+        for &(device_type, address) in batch {
+            match device_type {
+                device_type::MD => md::request_status(handle, address)?,
+                device_type::SD => sd::request_status(handle, address)?,
+                device_type::SMD => smd::request_status(handle, address)?,
+                device_type::BLMD => blmd::request_status(handle, device_type::BLMD, address)?,
+                _ => sr::request_status(handle)?,
+            };
+        }
+        // Late replies to an earlier batch are still taken.
+        let requested = &probes[..index * BATCH_SIZE + batch.len()];
+        collect(handle, requested, batch, deadline, &mut devices)?;
+    }
+    devices.sort_by_key(|device| (device.device_type, device.address));
+    Ok(devices)
+}
+
+/// Reads replies to the status requests of `requested` until every device of `batch` has
+/// replied or `deadline` has passed, and adds the devices that replied to `devices`.
+fn collect(
+    handle: &impl HandleTrait,
+    requested: &[(u8, u8)],
+    batch: &[(u8, u8)],
+    deadline: Duration,
+    devices: &mut Vec<Device>,
+) -> Result<(), crate::Error> {
+    let found = |devices: &[Device], (device_type, address): (u8, u8)| {
+        devices
+            .iter()
+            .any(|device| device.device_type == device_type && device.address == address)
+    };
+    let until = Instant::now() + deadline;
+    let mut receive_buf = [0; 8];
+    while !batch.iter().all(|&probe| found(devices, probe)) {
+        let remaining = until.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            break;
+        }
+        match handle.read_bulk(&mut receive_buf, remaining) {
+            Ok(_) => {}
+            Err(e) if e.is_timeout() => break,
+            Err(e) => return Err(e),
+        }
+        // A frame may match several devices, such as a BLMD status and the MD at 0x02, so
+        // the devices of the current batch are preferred.
+        let sender = frame::senders(&receive_buf)
+            .into_iter()
+            .filter(|&sender| requested.contains(&sender) && !found(devices, sender))
+            .min_by_key(|sender| !batch.contains(sender));
+        if let Some((device_type, address)) = sender {
+            devices.push(Device {
+                device_type,
+                address,
+                status: frame::decode_as(device_type, &receive_buf),
+            });
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+
+    /// A bus on which each request whose first two bytes are a key of `replies` is
+    /// answered with its reply.
+    struct Bus {
+        replies: Vec<([u8; 2], [u8; 8])>,
+        queue: RefCell<VecDeque<[u8; 8]>>,
+    }
+
+    impl HandleTrait for Bus {
+        fn read_bulk(&self, data: &mut [u8], _timeout: Duration) -> Result<usize, crate::Error> {
+            let frame = self.queue.borrow_mut().pop_front();
+            let frame = frame.ok_or(std::io::Error::from(std::io::ErrorKind::TimedOut))?;
+            data[..8].copy_from_slice(&frame);
+            Ok(8)
+        }
+
+        fn write_bulk(&self, data: &[u8], _timeout: Duration) -> Result<usize, crate::Error> {
+            let reply = self.replies.iter().find(|(key, _)| key[..] == data[..2]);
+            self.queue
+                .borrow_mut()
+                .extend(reply.map(|&(_, reply)| reply));
+            Ok(data.len())
+        }
+    }
+
+    #[test]
+    fn replies_are_decoded_as_the_device_probed() {
+        let bus = Bus {
+            replies: vec![
+                // The MD at 0x02, whose status looks like that of BLMD controller 1.
+                ([0x02, 0x60], [0x02, 0x01, 0, 90, 0, 10, 0, 0]),
+                ([0x30, 0x03], [0x02, 0x03, 0, 0, 0, 20, 0, 0]),
+                ([0x21, 0x60], [0x21, 0x60, 0, 45, 0, 90, 0, 0]),
+                ([0x40, 0x60], [0x40, 0x60, 0, 1, 0, 255, 0, 8]),
+            ],
+            queue: RefCell::new(VecDeque::new()),
+        };
+        let devices = scan(&bus).unwrap();
+        let found: Vec<(u8, u8)> = devices
+            .iter()
+            .map(|device| (device.device_type, device.address))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                (device_type::MD, 0x02),
+                (device_type::SMD, 0x21),
+                (device_type::BLMD, 3),
+                (device_type::SR, device_type::SR)
+            ]
+        );
+        assert!(matches!(devices[0].status, Frame::Md(ref status) if status.speed == 10));
+        assert!(matches!(devices[2].status, Frame::BlMd(ref status) if status.speed == 20));
+        assert!(matches!(
+            devices[3].status,
+            Frame::Sr(ref status) if status.voltage && status.color.red == 255 && status.freq == 2.0
+        ));
+    }
+}
@@ -0,0 +1,439 @@
+//! Decoding of raw 8-byte frames into commands and statuses.
+
+use std::fmt;
+
+use crate::recording::Direction;
+use crate::{blmd, device_type, md, sd, smd, sr};
+
+/// A command sent by the master to a device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Command {
+    pub device_type: u8,
+    /// The first byte of the frame. For BLMD commands this is the BLMD address.
+    pub address: u8,
+    /// The second byte of the frame. For BLMD commands this is the controller ID.
+    pub semi_id: u8,
+    pub mode: u8,
+    pub port: u8,
+    pub value_0: i16,
+    pub value_1: i16,
+    pub raw: [u8; 8],
+}
+
+/// A decoded frame.
+#[derive(Debug)]
+pub enum Frame {
+    Command(Command),
+    Emergency,
+    Md(md::MdStatus),
+    Sd(sd::SdStatus),
+    Smd(smd::SmdStatus),
+    BlMd(blmd::BlMdStatus),
+    Sr(sr::SrStatus),
+    Unknown([u8; 8]),
+}
+
+/// Decodes a frame that travelled in `direction`.
+///
+/// A status of the MD at 0x02 whose second byte is 1 to 8 has the same first two bytes as
+/// the status of a BLMD controller, and is decoded as a BLMD status. Use `decode_as` when
+/// the device that sent a frame is known, and `senders` to match a frame to a request.
+///
+/// # Arguments
+///
+/// * `direction` - Whether the frame was written to or read from the bus.
+/// * `buf` - The frame.
+///
+/// # Returns
+///
+/// The decoded frame. Frames that match no known layout are returned as `Frame::Unknown`.
+///
+/// # Example
+///
+/// ```rust
+/// use motor_lib::frame::{self, Frame};
+/// use motor_lib::recording::Direction;
+/// let frame = frame::decode(Direction::Read, &[0x01, 0x60, 0, 90, 0, 100, 1, 0]);
+/// assert!(matches!(frame, Frame::Md(ref status) if status.speed == 100));
+/// println!("{}", frame);
+/// ```
+pub fn decode(direction: Direction, buf: &[u8; 8]) -> Frame {
+    let word = |i: usize| (buf[i] as i16) << 8 | (buf[i + 1] as i16);
+    match direction {
+        Direction::Write if buf[0] == device_type::EMMERGENCY => Frame::Emergency,
+        Direction::Write => Frame::Command(Command {
+            device_type: device_type::of(buf[0]),
+            address: buf[0],
+            semi_id: buf[1],
+            mode: buf[2],
+            port: buf[3],
+            value_0: word(4),
+            value_1: word(6),
+            raw: *buf,
+        }),
+        Direction::Read if is_blmd_status(buf) => Frame::BlMd(blmd::decode_status(buf)),
+        Direction::Read => decode_as(device_type::of(buf[0]), buf),
+    }
+}
+
+/// Returns whether a frame read from the bus has the layout of a BLMD status. BLMD
+/// controllers report with the standard ID 0x201-0x208 in the first two bytes.
+fn is_blmd_status(buf: &[u8; 8]) -> bool {
+    buf[0] == 0x02 && (1..=8).contains(&buf[1])
+}
+
+/// Decodes a frame read from the bus as the status of a device of `device_type`.
+///
+/// # Returns
+///
+/// The decoded status, or `Frame::Unknown` if `device_type` has no status decoder or the
+/// frame does not have the layout of a BLMD status.
+///
+/// # Example
+///
+/// ```rust
+/// use motor_lib::device_type;
+/// use motor_lib::frame::{self, Frame};
+/// let frame = frame::decode_as(device_type::MD, &[0x02, 0x01, 0, 90, 0, 100, 0, 0]);
+/// assert!(matches!(frame, Frame::Md(ref status) if status.address == 0x02));
+/// ```
+pub fn decode_as(device_type: u8, buf: &[u8; 8]) -> Frame {
+    match device_type {
+        device_type::MD => Frame::Md(md::decode_status(buf)),
+        device_type::SD => Frame::Sd(sd::decode_status(buf)),
+        device_type::SMD => Frame::Smd(smd::decode_status(buf)),
+        device_type::BLMD if is_blmd_status(buf) => Frame::BlMd(blmd::decode_status(buf)),
+        device_type::SR => Frame::Sr(sr::decode_status(buf)),
+        _ => Frame::Unknown(*buf),
+    }
+}
+
+/// Returns the devices that may have sent a frame read from the bus, as the device type
+/// and address returned by `Frame::device`. A frame that `decode` takes for a BLMD status
+/// may also come from the MD at 0x02.
+pub fn senders(buf: &[u8; 8]) -> Vec<(u8, u8)> {
+    let mut senders: Vec<(u8, u8)> = decode(Direction::Read, buf).device().into_iter().collect();
+    if is_blmd_status(buf) {
+        senders.push((device_type::MD, buf[0]));
+    }
+    senders
+}
+
+impl Command {
+    /// Returns whether the command drives an output, rather than initializing the device or
+    /// requesting its status. Commands to unknown device types are assumed to drive one.
+    pub fn is_output(&self) -> bool {
+        match self.device_type {
+            device_type::MD => !matches!(self.mode, md::mode::INIT | md::mode::STATUS),
+            device_type::SD => self.mode != sd::mode::STATUS,
+            device_type::SMD => self.mode != smd::mode::STATUS,
+            device_type::BLMD => !matches!(self.mode, blmd::mode::INIT | blmd::mode::STATUS),
+            _ => true,
+        }
+    }
+}
+
+impl Frame {
+    /// Returns the device type and address the frame belongs to.
+    ///
+    /// BLMD frames are identified by their controller ID instead of an address.
+    pub fn device(&self) -> Option<(u8, u8)> {
+        match self {
+            Frame::Command(command) if command.device_type == device_type::BLMD => {
+                Some((device_type::BLMD, command.semi_id))
+            }
+            Frame::Command(command) => Some((command.device_type, command.address)),
+            Frame::Emergency => Some((device_type::EMMERGENCY, device_type::EMMERGENCY)),
+            Frame::Md(status) => Some((device_type::MD, status.address)),
+            Frame::Sd(status) => Some((device_type::SD, status.address)),
+            Frame::Smd(status) => Some((device_type::SMD, status.address)),
+            Frame::BlMd(status) => Some((device_type::BLMD, (status.std_id - 0x200) as u8)),
+            Frame::Sr(status) => Some((device_type::SR, status.address as u8)),
+            Frame::Unknown(_) => None,
+        }
+    }
+}
+
+/// Returns the name of `mode` for commands sent to `device_type`.
+pub fn mode_name(device_type: u8, mode: u8) -> Option<&'static str> {
+    let name = match (device_type, mode) {
+        (device_type::MD, md::mode::INIT) => "INIT",
+        (device_type::MD, md::mode::STATUS) => "STATUS",
+        (device_type::MD, md::mode::PWM) => "PWM",
+        (device_type::MD, md::mode::SPEED) => "SPEED",
+        (device_type::MD, md::mode::ANGLE) => "ANGLE",
+        (device_type::MD, md::mode::LIM_SW) => "LIM_SW",
+        (device_type::SD, sd::mode::STATUS) => "STATUS",
+        (device_type::SD, sd::mode::POWER) => "POWER",
+        (device_type::SD, sd::mode::LIM_SW) => "LIM_SW",
+        (device_type::SD, sd::mode::SINGLE_POWER) => "SINGLE_POWER",
+        (device_type::SMD, smd::mode::STATUS) => "STATUS",
+        (device_type::SMD, smd::mode::ANGLE) => "ANGLE",
+        (device_type::SMD, smd::mode::ANGLES) => "ANGLES",
+        (device_type::BLMD, blmd::mode::INIT) => "INIT",
+        (device_type::BLMD, blmd::mode::STATUS) => "STATUS",
+        (device_type::BLMD, blmd::mode::CURRENT) => "CURRENT",
+        (device_type::BLMD, blmd::mode::VELOCITY) => "VELOCITY",
+        (device_type::BLMD, blmd::mode::ANGLE) => "ANGLE",
+        (device_type::SR, sr::mode::STATUS) => "STATUS",
+        (device_type::SR, sr::mode::STOP) => "STOP",
+        (device_type::SR, sr::mode::START) => "START",
+        (device_type::SR, sr::mode::COLOR) => "COLOR",
+        _ => return None,
+    };
+    Some(name)
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let type_name = device_type::name(self.device_type).unwrap_or("?");
+        let mode = self.mode;
+        match mode_name(self.device_type, mode) {
+            Some(mode_name) if self.device_type == device_type::BLMD => write!(
+                f,
+                "{} 0x{:02x} controller {} {}",
+                type_name, self.address, self.semi_id, mode_name
+            )?,
+            Some(mode_name) => write!(f, "{} 0x{:02x} {}", type_name, self.address, mode_name)?,
+            None => {
+                return write!(
+                    f,
+                    "{} 0x{:02x} mode {} {:02x?}",
+                    type_name, self.address, mode, self.raw
+                )
+            }
+        }
+        match (self.device_type, mode) {
+            (device_type::MD, md::mode::PWM) => write!(f, " power={}", self.value_0),
+            (device_type::MD, md::mode::SPEED) => write!(f, " velocity={}", self.value_0),
+            (device_type::MD, md::mode::ANGLE) => write!(f, " angle={}", self.value_0),
+            (device_type::MD, md::mode::LIM_SW) => write!(
+                f,
+                " port={} power={} after_power={}",
+                self.port, self.value_0, self.value_1
+            ),
+            (device_type::SD, sd::mode::SINGLE_POWER) => {
+                write!(f, " port={} power={}", self.port, self.value_0)
+            }
+            (device_type::SD, sd::mode::POWER) => {
+                write!(f, " power_0={} power_1={}", self.value_0, self.value_1)
+            }
+            (device_type::SMD, smd::mode::ANGLE) => {
+                write!(f, " port={} angle={}", self.port, self.value_0)
+            }
+            (device_type::SMD, smd::mode::ANGLES) => {
+                write!(f, " angle_0={} angle_1={}", self.value_0, self.value_1)
+            }
+            (device_type::BLMD, blmd::mode::CURRENT) => write!(f, " current={}", self.value_0),
+            (device_type::BLMD, blmd::mode::VELOCITY) => write!(f, " velocity={}", self.value_0),
+            (device_type::SR, sr::mode::COLOR) => write!(
+                f,
+                " red={} green={} blue={} freq={}",
+                self.raw[5],
+                self.raw[4],
+                self.raw[6],
+                self.raw[7] as f32 / 4.0
+            ),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Frame::Command(command) => write!(f, "{}", command),
+            Frame::Emergency => write!(f, "EMMERGENCY"),
+            Frame::Md(status) => write!(
+                f,
+                "MD 0x{:02x} status angle={} speed={} limsw=[{}, {}]",
+                status.address,
+                status.angle,
+                status.speed,
+                status.limsw.limsw_0 as u8,
+                status.limsw.limsw_1 as u8
+            ),
+            Frame::Sd(status) => write!(
+                f,
+                "SD 0x{:02x} status port_0={} port_1={} limsw=[{}, {}]",
+                status.address,
+                status.port_0,
+                status.port_1,
+                status.limsw.limsw_0 as u8,
+                status.limsw.limsw_1 as u8
+            ),
+            Frame::Smd(status) => write!(
+                f,
+                "SMD 0x{:02x} status angle_0={} angle_1={}",
+                status.address, status.angle_0, status.angle_1
+            ),
+            Frame::BlMd(status) => write!(
+                f,
+                "BLMD controller {} status angle={} speed={} current={}",
+                status.std_id - 0x200,
+                status.angle,
+                status.speed,
+                status.current
+            ),
+            Frame::Sr(status) => write!(
+                f,
+                "SR 0x{:02x} status voltage={} color=#{:02x}{:02x}{:02x} freq={}",
+                status.address as u8,
+                status.voltage as u8,
+                status.color.red,
+                status.color.green,
+                status.color.blue,
+                status.freq
+            ),
+            Frame::Unknown(raw) => write!(f, "unknown {:02x?}", raw),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::HandleTrait;
+    use std::cell::RefCell;
+    use std::time::Duration;
+
+    /// Keeps the frames written to it, and never replies.
+    #[derive(Default)]
+    struct Capture {
+        written: RefCell<Vec<[u8; 8]>>,
+    }
+
+    impl HandleTrait for Capture {
+        fn read_bulk(&self, _data: &mut [u8], _timeout: Duration) -> Result<usize, crate::Error> {
+            Err(crate::Error::RUsbError(rusb::Error::Timeout))
+        }
+
+        fn write_bulk(&self, data: &[u8], _timeout: Duration) -> Result<usize, crate::Error> {
+            self.written.borrow_mut().push(data.try_into().unwrap());
+            Ok(data.len())
+        }
+    }
+
+    fn command(send: impl FnOnce(&Capture)) -> Command {
+        let handle = Capture::default();
+        send(&handle);
+        let buf = handle.written.borrow()[0];
+        match decode(Direction::Write, &buf) {
+            Frame::Command(command) => {
+                assert_eq!(command.raw, buf);
+                command
+            }
+            frame => panic!("{:?} is not a command", frame),
+        }
+    }
+
+    #[test]
+    fn commands_round_trip() {
+        let pwm = command(|handle| {
+            let _ = md::send_pwm(handle, 0x03, -300);
+        });
+        assert_eq!((pwm.device_type, pwm.address), (device_type::MD, 0x03));
+        assert_eq!((pwm.mode, pwm.value_0), (md::mode::PWM, -300));
+        assert!(pwm.is_output());
+
+        let limsw = command(|handle| {
+            let _ = md::send_limsw(handle, 0x01, 1, 200, -50);
+        });
+        assert_eq!(limsw.mode, md::mode::LIM_SW);
+        assert_eq!((limsw.port, limsw.value_0, limsw.value_1), (1, 200, -50));
+
+        let powers = command(|handle| {
+            let _ = sd::send_powers(handle, 0x12, 999, 500);
+        });
+        assert_eq!(
+            (powers.device_type, powers.address),
+            (device_type::SD, 0x12)
+        );
+        assert_eq!((powers.value_0, powers.value_1), (999, 500));
+
+        let angles = command(|handle| {
+            let _ = smd::send_angles(handle, 0x21, 90, -90);
+        });
+        assert_eq!((angles.value_0, angles.value_1), (90, -90));
+
+        let current = command(|handle| {
+            let _ = blmd::send_current(handle, device_type::BLMD, 3, 1000);
+        });
+        assert_eq!(
+            Frame::Command(current).device(),
+            Some((device_type::BLMD, 3))
+        );
+        assert_eq!(current.value_0, 1000);
+    }
+
+    #[test]
+    fn status_requests_are_not_outputs() {
+        let status = command(|handle| {
+            let _ = md::request_status(handle, 0x00);
+        });
+        assert!(!status.is_output());
+    }
+
+    #[test]
+    fn emergency_is_decoded() {
+        let handle = Capture::default();
+        crate::send_emergency(&handle).unwrap();
+        let buf = handle.written.borrow()[0];
+        assert!(matches!(decode(Direction::Write, &buf), Frame::Emergency));
+    }
+
+    #[test]
+    fn statuses_are_decoded_by_address() {
+        let frame = decode(Direction::Read, &[0x11, 0x60, 0x01, 0x00, 0xff, 0x38, 1, 0]);
+        match frame {
+            Frame::Sd(status) => {
+                assert_eq!((status.port_0, status.port_1), (256, -200));
+                assert!(status.limsw.limsw_0 && !status.limsw.limsw_1);
+            }
+            frame => panic!("{:?} is not an SD status", frame),
+        }
+        let frame = decode(Direction::Read, &[0x20, 0x60, 0, 45, 0xff, 0xd3, 0, 0]);
+        assert!(matches!(frame, Frame::Smd(status) if status.angle_1 == -45));
+        let frame = decode(Direction::Read, &[0x50, 0, 0, 0, 0, 0, 0, 0]);
+        assert!(matches!(frame, Frame::Unknown(_)));
+    }
+
+    #[test]
+    fn blmd_status_is_decoded_by_standard_id() {
+        let buf = [0x02, 0x05, 0x10, 0x00, 0x00, 0x64, 0xfc, 0x18];
+        let frame = decode(Direction::Read, &buf);
+        assert_eq!(frame.device(), Some((device_type::BLMD, 5)));
+        assert!(matches!(frame, Frame::BlMd(status) if status.current == -1000));
+    }
+
+    #[test]
+    fn md_status_from_0x02_is_decoded_as_expected_type() {
+        let buf = [0x02, 0x01, 0x00, 0x5a, 0x00, 0x64, 1, 1];
+        match decode_as(device_type::MD, &buf) {
+            Frame::Md(status) => {
+                assert_eq!(
+                    (status.address, status.angle, status.speed),
+                    (0x02, 90, 100)
+                );
+                assert!(status.limsw.limsw_0 && status.limsw.limsw_1);
+            }
+            frame => panic!("{:?} is not an MD status", frame),
+        }
+        assert!(matches!(decode_as(device_type::BLMD, &buf), Frame::BlMd(_)));
+        assert_eq!(
+            senders(&buf),
+            vec![(device_type::BLMD, 1), (device_type::MD, 0x02)]
+        );
+    }
+
+    #[test]
+    fn md_status_from_0x02_outside_the_blmd_ids_is_an_md_status() {
+        let buf = [0x02, 0x60, 0, 0, 0, 0, 0, 0];
+        assert!(matches!(decode(Direction::Read, &buf), Frame::Md(_)));
+        assert!(matches!(
+            decode_as(device_type::BLMD, &buf),
+            Frame::Unknown(_)
+        ));
+        assert_eq!(senders(&buf), vec![(device_type::MD, 0x02)]);
+    }
+}
@@ -1,2 +1,8 @@
 pub mod grpc;
+pub mod recording;
+pub mod replay;
+#[cfg(unix)]
+pub mod serial;
+#[cfg(target_os = "linux")]
+pub mod socketcan;
 pub mod usb;
@@ -1,6 +1,16 @@
 //! Implementation of gRPC client for USB communication.
 
-use crate::HandleTrait;
+use crate::blmd::BlMdStatus;
+use crate::frame::{self, Frame};
+use crate::md::MdStatus;
+use crate::recording::Direction;
+use crate::sd::SdStatus;
+use crate::smd::SmdStatus;
+use crate::{md, sd, HandleTrait};
+use std::cell::Cell;
+use std::future::Future;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
 use std::{cell::RefCell, time};
 pub mod pb {
     tonic::include_proto!("motor_lib");
@@ -8,56 +18,1059 @@ pub mod pb {
 
 use pb::WriteRequest;
 
+/// The metadata key with which the clients identify themselves, so that the server returns
+/// the replies to their own commands to them and knows which client holds the lease.
+pub const CLIENT_ID_KEY: &str = "client-id";
+
+/// The metadata key that selects the bus of a server with several USB adapters.
+pub const BUS_KEY: &str = "bus";
+
+static NEXT_CLIENT_ID: AtomicU32 = AtomicU32::new(0);
+
+/// Adds the `client-id` metadata of one client, and the `bus` metadata of the bus it uses,
+/// to every request.
+#[derive(Debug, Clone)]
+struct Caller {
+    id: u64,
+    bus: Option<tonic::metadata::AsciiMetadataValue>,
+}
+
+impl Caller {
+    fn new(bus: Option<&str>) -> Result<Self, crate::Error> {
+        let bus = bus
+            .map(|bus| {
+                bus.parse().map_err(|_| {
+                    tonic::Status::invalid_argument(format!("invalid bus name: {}", bus))
+                })
+            })
+            .transpose()?;
+        Ok(Self {
+            id: (std::process::id() as u64) << 32
+                | NEXT_CLIENT_ID.fetch_add(1, Ordering::Relaxed) as u64,
+            bus,
+        })
+    }
+
+    fn apply(&self, metadata: &mut tonic::metadata::MetadataMap) {
+        metadata.insert(CLIENT_ID_KEY, self.id.into());
+        if let Some(bus) = &self.bus {
+            metadata.insert(BUS_KEY, bus.clone());
+        }
+    }
+}
+
+impl tonic::service::Interceptor for Caller {
+    fn call(
+        &mut self,
+        mut request: tonic::Request<()>,
+    ) -> Result<tonic::Request<()>, tonic::Status> {
+        self.apply(request.metadata_mut());
+        Ok(request)
+    }
+}
+
+type Intercepted =
+    tonic::service::interceptor::InterceptedService<tonic::transport::Channel, Caller>;
+
+/// Connects to `url`, either `http://HOST:PORT` or `unix://PATH` for a Unix domain socket.
+async fn connect(url: &str) -> Result<tonic::transport::Channel, tonic::Status> {
+    #[cfg(unix)]
+    if let Some(path) = url.strip_prefix("unix://") {
+        let path = std::path::PathBuf::from(path);
+        // The URI is only used for the headers: every connection is made to `path`.
+        return tonic::transport::Endpoint::from_static("http://localhost")
+            .connect_with_connector(tower::service_fn(move |_| {
+                let path = path.clone();
+                async move {
+                    let stream = tokio::net::UnixStream::connect(path).await?;
+                    Ok::<_, std::io::Error>(hyper_util::rt::TokioIo::new(stream))
+                }
+            }))
+            .await
+            .map_err(|e| {
+                let reason = std::error::Error::source(&e).map_or(e.to_string(), |e| e.to_string());
+                tonic::Status::unavailable(format!("{}: {}", url, reason))
+            });
+    }
+    tonic::transport::Endpoint::from_shared(url.to_string())
+        .map_err(|e| tonic::Status::invalid_argument(e.to_string()))?
+        .connect()
+        .await
+        .map_err(|e| tonic::Status::unavailable(e.to_string()))
+}
+
+/// The holder of the controlling lease of a server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LeaseInfo {
+    /// The `client-id` metadata or address of the holder.
+    pub client: String,
+    pub name: String,
+    pub priority: u32,
+    pub acquired: SystemTime,
+}
+
+impl From<pb::LeaseInfo> for LeaseInfo {
+    fn from(info: pb::LeaseInfo) -> Self {
+        Self {
+            client: info.client,
+            name: info.name,
+            priority: info.priority,
+            acquired: UNIX_EPOCH + time::Duration::from_micros(info.acquired_us),
+        }
+    }
+}
+
+/// A bus of a server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BusInfo {
+    pub name: String,
+    /// The serial number of the adapter of the bus, or `None` if the bus takes any adapter.
+    pub serial: Option<String>,
+    pub connected: bool,
+}
+
+impl From<pb::BusInfo> for BusInfo {
+    fn from(info: pb::BusInfo) -> Self {
+        Self {
+            name: info.name,
+            serial: (!info.serial.is_empty()).then_some(info.serial),
+            connected: info.connected,
+        }
+    }
+}
+
+/// The `Lease` and `Buses` services, called with the identity of one client.
+struct ServerRpc {
+    lease: pb::lease_client::LeaseClient<Intercepted>,
+    buses: pb::buses_client::BusesClient<Intercepted>,
+}
+
+impl ServerRpc {
+    fn new(channel: tonic::transport::Channel, caller: Caller) -> Self {
+        Self {
+            lease: pb::lease_client::LeaseClient::with_interceptor(channel.clone(), caller.clone()),
+            buses: pb::buses_client::BusesClient::with_interceptor(channel, caller),
+        }
+    }
+
+    fn list_buses(
+        &self,
+        tokio_runtime: &tokio::runtime::Runtime,
+    ) -> Result<Vec<BusInfo>, crate::Error> {
+        let list = tokio_runtime.block_on(self.buses.clone().list_buses(pb::Empty {}))?;
+        Ok(list
+            .into_inner()
+            .buses
+            .into_iter()
+            .map(BusInfo::from)
+            .collect())
+    }
+
+    fn acquire(
+        &self,
+        tokio_runtime: &tokio::runtime::Runtime,
+        name: &str,
+        priority: u32,
+        ttl: time::Duration,
+    ) -> Result<LeaseInfo, crate::Error> {
+        let request = pb::AcquireLeaseRequest {
+            name: name.to_string(),
+            priority,
+            ttl_ms: ttl.as_millis().try_into().unwrap_or(u32::MAX),
+        };
+        let info = tokio_runtime.block_on(self.lease.clone().acquire_lease(request))?;
+        Ok(info.into_inner().into())
+    }
+
+    fn release(&self, tokio_runtime: &tokio::runtime::Runtime) -> Result<(), crate::Error> {
+        tokio_runtime.block_on(self.lease.clone().release_lease(pb::Empty {}))?;
+        Ok(())
+    }
+
+    fn holder(
+        &self,
+        tokio_runtime: &tokio::runtime::Runtime,
+    ) -> Result<Option<LeaseInfo>, crate::Error> {
+        let status = tokio_runtime.block_on(self.lease.clone().get_lease(pb::Empty {}))?;
+        Ok(status.into_inner().holder.map(LeaseInfo::from))
+    }
+}
+
 /// A handle to read and write an gRPC device.
 pub struct GrpcHandle {
     tokio_runtime: tokio::runtime::Runtime,
     client: RefCell<pb::usb_can_client::UsbCanClient<tonic::transport::Channel>>,
+    caller: Caller,
+    server: ServerRpc,
 }
 
 impl GrpcHandle {
+    /// Connects to the server at `url`, `http://HOST:PORT` or `unix://PATH` for a server
+    /// listening on a Unix domain socket.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// use motor_lib::{md, Error, GrpcHandle};
+    /// fn main() -> Result<(), Error> {
+    ///     let handle = GrpcHandle::new("unix:///run/usb_can_server.sock");
+    ///     md::send_speed(&handle, 0x00, 100)?;
+    ///     Ok(())
+    /// }
+    /// ```
     pub fn new(url: &str) -> Self {
-        let tokio_runtime = tokio::runtime::Runtime::new().unwrap();
-        let client = tokio_runtime.block_on(async {
-            pb::usb_can_client::UsbCanClient::connect(url.to_string())
-                .await
-                .unwrap()
-        });
-        Self {
-            tokio_runtime: tokio_runtime,
-            client: std::cell::RefCell::new(client),
-        }
+        Self::connect(url, None).unwrap()
+    }
+
+    /// Connects to the bus named `bus` of a server with several USB adapters.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// use motor_lib::{md, Error, GrpcHandle};
+    /// fn main() -> Result<(), Error> {
+    ///     let front = GrpcHandle::with_bus("http://127.0.0.1:50051", "front")?;
+    ///     let rear = GrpcHandle::with_bus("http://127.0.0.1:50051", "rear")?;
+    ///     md::send_speed(&front, 0x00, 100)?;
+    ///     md::send_speed(&rear, 0x00, 100)?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn with_bus(url: &str, bus: &str) -> Result<Self, crate::Error> {
+        Self::connect(url, Some(bus))
+    }
+
+    fn connect(url: &str, bus: Option<&str>) -> Result<Self, crate::Error> {
+        let tokio_runtime = tokio::runtime::Runtime::new()?;
+        let channel = tokio_runtime.block_on(connect(url))?;
+        let caller = Caller::new(bus)?;
+        Ok(Self {
+            tokio_runtime,
+            client: std::cell::RefCell::new(pb::usb_can_client::UsbCanClient::new(channel.clone())),
+            caller: caller.clone(),
+            server: ServerRpc::new(channel, caller),
+        })
+    }
+
+    /// Returns the buses of the server.
+    pub fn list_buses(&self) -> Result<Vec<BusInfo>, crate::Error> {
+        self.server.list_buses(&self.tokio_runtime)
+    }
+
+    /// Takes the controlling lease of the server, so that the commands of other clients are
+    /// rejected until it is released or expires.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name shown to the other clients.
+    /// * `priority` - A client with a higher priority can take the lease from this one.
+    /// * `ttl` - How long the lease lasts after the last write, or the server default if 0.
+    ///
+    /// # Returns
+    ///
+    /// A result containing the new lease, or an Error if a client with the same or a higher
+    /// priority holds it.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// use std::time::Duration;
+    /// use motor_lib::{md, Error, GrpcHandle};
+    /// fn main() -> Result<(), Error> {
+    ///     let handle = GrpcHandle::new("http://127.0.0.1:50051");
+    ///     handle.acquire_lease("controller", 0, Duration::from_secs(1))?;
+    ///     md::send_speed(&handle, 0x00, 100)?;
+    ///     handle.release_lease()?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn acquire_lease(
+        &self,
+        name: &str,
+        priority: u32,
+        ttl: time::Duration,
+    ) -> Result<LeaseInfo, crate::Error> {
+        self.server
+            .acquire(&self.tokio_runtime, name, priority, ttl)
+    }
+
+    /// Releases the lease held by this handle.
+    pub fn release_lease(&self) -> Result<(), crate::Error> {
+        self.server.release(&self.tokio_runtime)
+    }
+
+    /// Returns the holder of the lease, if any.
+    pub fn lease_holder(&self) -> Result<Option<LeaseInfo>, crate::Error> {
+        self.server.holder(&self.tokio_runtime)
+    }
+
+    fn request<T>(&self, message: T, timeout: time::Duration) -> tonic::Request<T> {
+        let mut request = tonic::Request::new(message);
+        request.set_timeout(timeout);
+        self.caller.apply(request.metadata_mut());
+        request
+    }
+}
+
+/// Tonic reports an expired `grpc-timeout` as `CANCELLED`. `GrpcHandle` never cancels its
+/// requests, so it is reported as a timeout instead.
+fn expired(status: tonic::Status) -> tonic::Status {
+    if status.code() == tonic::Code::Cancelled {
+        tonic::Status::deadline_exceeded(status.message())
+    } else {
+        status
     }
 }
 
 impl HandleTrait for GrpcHandle {
-    fn read_bulk(&self, data: &mut [u8], _timeout: time::Duration) -> Result<usize, crate::Error> {
-        let request = tonic::Request::new(pb::ReadRequest { size: data.len() as i32 });
+    fn read_bulk(&self, data: &mut [u8], timeout: time::Duration) -> Result<usize, crate::Error> {
+        let request = self.request(
+            pb::ReadRequest {
+                size: data.len() as i32,
+            },
+            timeout,
+        );
         self.tokio_runtime.block_on(async {
-            let response = self.client.borrow_mut().read(request).await?;
+            let response = self
+                .client
+                .borrow_mut()
+                .read(request)
+                .await
+                .map_err(expired)?;
             let recv_buf = response.into_inner().recv_buf;
             data.copy_from_slice(&recv_buf);
             Ok(recv_buf.len())
         })
     }
-    fn write_bulk(&self, data: &[u8], _timeout: time::Duration) -> Result<usize, crate::Error> {
-        let request = tonic::Request::new(WriteRequest {
-            send_buf: data.to_vec(),
-        });
+    fn write_bulk(&self, data: &[u8], timeout: time::Duration) -> Result<usize, crate::Error> {
+        let request = self.request(
+            WriteRequest {
+                send_buf: data.to_vec(),
+            },
+            timeout,
+        );
         self.tokio_runtime.block_on(async {
-            let response = self.client.borrow_mut().write(request).await?;
+            let response = self
+                .client
+                .borrow_mut()
+                .write(request)
+                .await
+                .map_err(expired)?;
             let size = response.into_inner().size.try_into()?;
             Ok(size)
         })
     }
 }
 
+/// A handle to read and write frames over the `Control` stream of `usb_can_server`.
+///
+/// Unlike `GrpcHandle`, which makes one request per frame, every frame is sent on a single
+/// bidirectional stream, and reads only return the replies to the commands of this handle.
+pub struct GrpcStreamHandle {
+    tokio_runtime: tokio::runtime::Runtime,
+    commands: tokio::sync::mpsc::Sender<pb::ControlFrame>,
+    replies: RefCell<tonic::Streaming<pb::ControlReply>>,
+    sequence: Cell<u64>,
+    server: ServerRpc,
+}
+
+impl GrpcStreamHandle {
+    /// Connects to the server at `url` and opens the `Control` stream. See `GrpcHandle::new`
+    /// for the form of `url`.
+    ///
+    /// # Example
+    ///
+    /// Sample code to rotate a motor connected to the MD at address 0x00 at 500 Hz.
+    /// ```rust,no_run
+    /// use motor_lib::{md, Error, GrpcStreamHandle};
+    /// fn main() -> Result<(), Error> {
+    ///     let handle = GrpcStreamHandle::new("http://127.0.0.1:50051")?;
+    ///     loop {
+    ///         md::send_speed(&handle, 0x00, 100)?;
+    ///         std::thread::sleep(std::time::Duration::from_millis(2));
+    ///     }
+    /// }
+    /// ```
+    pub fn new(url: &str) -> Result<Self, crate::Error> {
+        Self::connect(url, None)
+    }
+
+    /// Connects to the bus named `bus` of a server with several USB adapters, and opens
+    /// the `Control` stream.
+    pub fn with_bus(url: &str, bus: &str) -> Result<Self, crate::Error> {
+        Self::connect(url, Some(bus))
+    }
+
+    fn connect(url: &str, bus: Option<&str>) -> Result<Self, crate::Error> {
+        let tokio_runtime = tokio::runtime::Runtime::new()?;
+        let (commands, receiver) = tokio::sync::mpsc::channel(64);
+        let caller = Caller::new(bus)?;
+        let channel = tokio_runtime.block_on(connect(url))?;
+        let mut client =
+            pb::usb_can_client::UsbCanClient::with_interceptor(channel.clone(), caller.clone());
+        let stream = tokio_stream::wrappers::ReceiverStream::new(receiver);
+        let replies = tokio_runtime.block_on(client.control(stream))?;
+        Ok(Self {
+            tokio_runtime,
+            commands,
+            replies: RefCell::new(replies.into_inner()),
+            sequence: Cell::new(0),
+            server: ServerRpc::new(channel, caller),
+        })
+    }
+
+    /// See `GrpcHandle::acquire_lease`.
+    pub fn acquire_lease(
+        &self,
+        name: &str,
+        priority: u32,
+        ttl: time::Duration,
+    ) -> Result<LeaseInfo, crate::Error> {
+        self.server
+            .acquire(&self.tokio_runtime, name, priority, ttl)
+    }
+
+    /// See `GrpcHandle::release_lease`.
+    pub fn release_lease(&self) -> Result<(), crate::Error> {
+        self.server.release(&self.tokio_runtime)
+    }
+
+    /// See `GrpcHandle::lease_holder`.
+    pub fn lease_holder(&self) -> Result<Option<LeaseInfo>, crate::Error> {
+        self.server.holder(&self.tokio_runtime)
+    }
+
+    /// See `GrpcHandle::list_buses`.
+    pub fn list_buses(&self) -> Result<Vec<BusInfo>, crate::Error> {
+        self.server.list_buses(&self.tokio_runtime)
+    }
+
+    /// Returns the sequence number of the last frame written.
+    pub fn last_sequence(&self) -> u64 {
+        self.sequence.get()
+    }
+
+    /// Waits for the next reply.
+    ///
+    /// # Returns
+    ///
+    /// A result containing the sequence number of the command the reply answers, and the
+    /// status frame. An error is returned if the command could not be written to the bus.
+    pub fn read_reply(&self, timeout: time::Duration) -> Result<(u64, [u8; 8]), crate::Error> {
+        let mut replies = self.replies.borrow_mut();
+        let reply = self
+            .tokio_runtime
+            .block_on(async { tokio::time::timeout(timeout, replies.message()).await })
+            .map_err(|_| crate::Error::RUsbError(rusb::Error::Timeout))??
+            .ok_or_else(|| tonic::Status::unavailable("control stream closed"))?;
+        if reply.code != tonic::Code::Ok as i32 {
+            return Err(tonic::Status::new(reply.code.into(), reply.error).into());
+        }
+        let frame = <[u8; 8]>::try_from(reply.frame.as_slice())
+            .map_err(|_| tonic::Status::internal("frame is not 8 bytes"))?;
+        Ok((reply.sequence, frame))
+    }
+}
+
+impl HandleTrait for GrpcStreamHandle {
+    fn read_bulk(&self, data: &mut [u8], timeout: time::Duration) -> Result<usize, crate::Error> {
+        let (_, frame) = self.read_reply(timeout)?;
+        let len = data.len().min(frame.len());
+        data[..len].copy_from_slice(&frame[..len]);
+        Ok(len)
+    }
+    fn write_bulk(&self, data: &[u8], timeout: time::Duration) -> Result<usize, crate::Error> {
+        let sequence = self.sequence.get() + 1;
+        let command = pb::ControlFrame {
+            sequence,
+            frame: data.to_vec(),
+        };
+        // The send waits while the stream is full, for example when the server is stuck.
+        self.tokio_runtime
+            .block_on(async { tokio::time::timeout(timeout, self.commands.send(command)).await })
+            .map_err(|_| crate::Error::RUsbError(rusb::Error::Timeout))?
+            .map_err(|_| tonic::Status::unavailable("control stream closed"))?;
+        self.sequence.set(sequence);
+        Ok(data.len())
+    }
+}
+
 impl From<tonic::Status> for crate::Error {
     fn from(error: tonic::Status) -> Self {
         crate::Error::GrpcError(error)
     }
 }
+/// Maps an error of the bus to the status code a gRPC client can act on: `UNAVAILABLE`
+/// when the device is missing, `DEADLINE_EXCEEDED` when it did not answer in time.
+impl From<crate::Error> for tonic::Status {
+    fn from(error: crate::Error) -> Self {
+        let message = error.to_string();
+        match error {
+            crate::Error::GrpcError(status) => status,
+            crate::Error::RUsbError(e) => match e {
+                rusb::Error::Timeout => tonic::Status::deadline_exceeded(message),
+                rusb::Error::NoDevice
+                | rusb::Error::NotFound
+                | rusb::Error::Busy
+                | rusb::Error::Io => tonic::Status::unavailable(message),
+                rusb::Error::Pipe | rusb::Error::Interrupted => tonic::Status::aborted(message),
+                rusb::Error::Overflow => tonic::Status::data_loss(message),
+                rusb::Error::Access => tonic::Status::permission_denied(message),
+                rusb::Error::InvalidParam => tonic::Status::invalid_argument(message),
+                rusb::Error::NotSupported => tonic::Status::unimplemented(message),
+                _ => tonic::Status::internal(message),
+            },
+            crate::Error::IoError(e) => match e.kind() {
+                std::io::ErrorKind::TimedOut => tonic::Status::deadline_exceeded(message),
+                std::io::ErrorKind::NotFound
+                | std::io::ErrorKind::NotConnected
+                | std::io::ErrorKind::BrokenPipe => tonic::Status::unavailable(message),
+                _ => tonic::Status::internal(message),
+            },
+            crate::Error::InvalidAddress(_)
+            | crate::Error::InvalidPort(_)
+            | crate::Error::ConfigError(_) => tonic::Status::invalid_argument(message),
+            crate::Error::Unsupported(_) => tonic::Status::unimplemented(message),
+            crate::Error::LimitExceeded(_) => tonic::Status::out_of_range(message),
+            crate::Error::Fault(_) => tonic::Status::failed_precondition(message),
+        }
+    }
+}
+
 impl From<std::num::TryFromIntError> for crate::Error {
     fn from(_: std::num::TryFromIntError) -> Self {
         crate::Error::RUsbError(rusb::Error::Other)
     }
 }
+
+/// The status returned by `GrpcClient::get_status`.
+#[derive(Debug, Clone, Copy)]
+pub enum DeviceStatus {
+    Md(MdStatus),
+    Sd(SdStatus),
+    Smd(SmdStatus),
+    BlMd(BlMdStatus),
+}
+
+/// A frame received from `GrpcClient::subscribe_status`.
+#[derive(Debug)]
+pub struct StatusUpdate {
+    pub frame: Frame,
+    /// The time at which the server read the frame.
+    pub time: SystemTime,
+    /// The number of frames missed so far because the client read too slowly.
+    pub dropped: u64,
+}
+
+/// The frames of a `GrpcClient::subscribe_status` call, in the order they were read.
+pub struct StatusSubscription<'a> {
+    tokio_runtime: &'a tokio::runtime::Runtime,
+    stream: tonic::Streaming<pb::StatusFrame>,
+}
+
+impl Iterator for StatusSubscription<'_> {
+    type Item = Result<StatusUpdate, crate::Error>;
+
+    /// Waits for the next frame. Returns `None` when the server ends the stream.
+    fn next(&mut self) -> Option<Self::Item> {
+        let message = match self.tokio_runtime.block_on(self.stream.message()) {
+            Ok(message) => message?,
+            Err(e) => return Some(Err(e.into())),
+        };
+        let Ok(buf) = <[u8; 8]>::try_from(message.frame.as_slice()) else {
+            return Some(Err(tonic::Status::internal("frame is not 8 bytes").into()));
+        };
+        Some(Ok(StatusUpdate {
+            frame: frame::decode(Direction::Read, &buf),
+            time: UNIX_EPOCH + time::Duration::from_micros(message.timestamp_us),
+            dropped: message.dropped,
+        }))
+    }
+}
+
+/// A client of the typed `MotorControl` service of `usb_can_server`.
+///
+/// Unlike `GrpcHandle`, which passes raw frames through the server, the server builds and
+/// decodes the frames, validates the arguments and waits for the reply of the device.
+pub struct GrpcClient {
+    tokio_runtime: tokio::runtime::Runtime,
+    client: pb::motor_control_client::MotorControlClient<Intercepted>,
+    server: ServerRpc,
+    timeout: time::Duration,
+}
+
+impl GrpcClient {
+    /// How long a request waits for the reply of the device, unless set with `set_timeout`.
+    pub const DEFAULT_TIMEOUT: time::Duration = time::Duration::from_secs(5);
+
+    /// Connects to the server at `url`. See `GrpcHandle::new` for the form of `url`.
+    ///
+    /// # Example
+    ///
+    /// Sample code to rotate a motor connected to the MD at address 0x00 through a server on this machine.
+    /// ```rust,no_run
+    /// use motor_lib::{Error, GrpcClient};
+    /// fn main() -> Result<(), Error> {
+    ///     let client = GrpcClient::new("http://127.0.0.1:50051")?;
+    ///     let status = client.send_speed(0x00, 100)?;
+    ///     println!("{:?}", status);
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn new(url: &str) -> Result<Self, crate::Error> {
+        Self::connect(url, None)
+    }
+
+    /// Connects to the bus named `bus` of a server with several USB adapters.
+    pub fn with_bus(url: &str, bus: &str) -> Result<Self, crate::Error> {
+        Self::connect(url, Some(bus))
+    }
+
+    fn connect(url: &str, bus: Option<&str>) -> Result<Self, crate::Error> {
+        let tokio_runtime = tokio::runtime::Runtime::new()?;
+        let channel = tokio_runtime.block_on(connect(url))?;
+        let caller = Caller::new(bus)?;
+        Ok(Self {
+            tokio_runtime,
+            client: pb::motor_control_client::MotorControlClient::with_interceptor(
+                channel.clone(),
+                caller.clone(),
+            ),
+            server: ServerRpc::new(channel, caller),
+            timeout: Self::DEFAULT_TIMEOUT,
+        })
+    }
+
+    /// Sets how long the requests wait for the reply of the device. The server gives up on
+    /// a request at the same deadline.
+    pub fn set_timeout(&mut self, timeout: time::Duration) {
+        self.timeout = timeout;
+    }
+
+    /// See `GrpcHandle::list_buses`.
+    pub fn list_buses(&self) -> Result<Vec<BusInfo>, crate::Error> {
+        self.server.list_buses(&self.tokio_runtime)
+    }
+
+    /// See `GrpcHandle::acquire_lease`.
+    pub fn acquire_lease(
+        &self,
+        name: &str,
+        priority: u32,
+        ttl: time::Duration,
+    ) -> Result<LeaseInfo, crate::Error> {
+        self.server
+            .acquire(&self.tokio_runtime, name, priority, ttl)
+    }
+
+    /// See `GrpcHandle::release_lease`.
+    pub fn release_lease(&self) -> Result<(), crate::Error> {
+        self.server.release(&self.tokio_runtime)
+    }
+
+    /// See `GrpcHandle::lease_holder`.
+    pub fn lease_holder(&self) -> Result<Option<LeaseInfo>, crate::Error> {
+        self.server.holder(&self.tokio_runtime)
+    }
+
+    fn request<T>(&self, message: T) -> tonic::Request<T> {
+        let mut request = tonic::Request::new(message);
+        request.set_timeout(self.timeout);
+        request
+    }
+
+    /// Waits for the response to a request, up to the timeout even if the server does not
+    /// answer at all.
+    fn call<T>(
+        &self,
+        future: impl Future<Output = Result<tonic::Response<T>, tonic::Status>>,
+    ) -> Result<T, crate::Error> {
+        let response = self
+            .tokio_runtime
+            .block_on(async { tokio::time::timeout(self.timeout, future).await })
+            .map_err(|_| crate::Error::RUsbError(rusb::Error::Timeout))?
+            .map_err(expired)?;
+        Ok(response.into_inner())
+    }
+
+    /// See `md::send_pwm`.
+    pub fn send_pwm(&self, address: u8, power: i16) -> Result<MdStatus, crate::Error> {
+        let request = md_request(address, power);
+        self.call(self.client.clone().send_pwm(self.request(request)))
+            .map(MdStatus::from)
+    }
+
+    /// See `md::send_speed`.
+    pub fn send_speed(&self, address: u8, velocity: i16) -> Result<MdStatus, crate::Error> {
+        let request = md_request(address, velocity);
+        self.call(self.client.clone().send_speed(self.request(request)))
+            .map(MdStatus::from)
+    }
+
+    /// See `md::send_angle`.
+    pub fn send_angle(&self, address: u8, angle: i16) -> Result<MdStatus, crate::Error> {
+        let request = md_request(address, angle);
+        self.call(self.client.clone().send_angle(self.request(request)))
+            .map(MdStatus::from)
+    }
+
+    /// See `md::send_limsw`.
+    pub fn send_limsw(
+        &self,
+        address: u8,
+        port: u8,
+        power: i16,
+        after_power: i16,
+    ) -> Result<MdStatus, crate::Error> {
+        let request = pb::LimSwRequest {
+            address: address.into(),
+            port: port.into(),
+            power: power.into(),
+            after_power: after_power.into(),
+        };
+        self.call(self.client.clone().send_lim_sw(self.request(request)))
+            .map(MdStatus::from)
+    }
+
+    /// See `sd::send_power`.
+    pub fn sd_set_power(
+        &self,
+        address: u8,
+        port: u8,
+        power: i16,
+    ) -> Result<SdStatus, crate::Error> {
+        let request = pb::SdPowerRequest {
+            address: address.into(),
+            port: port.into(),
+            power: power.into(),
+        };
+        self.call(self.client.clone().sd_set_power(self.request(request)))
+            .map(SdStatus::from)
+    }
+
+    /// See `smd::send_angles`.
+    pub fn smd_set_angles(
+        &self,
+        address: u8,
+        angle_0: i16,
+        angle_1: i16,
+    ) -> Result<SmdStatus, crate::Error> {
+        let request = pb::SmdAnglesRequest {
+            address: address.into(),
+            angle_0: angle_0.into(),
+            angle_1: angle_1.into(),
+        };
+        self.call(self.client.clone().smd_set_angles(self.request(request)))
+            .map(SmdStatus::from)
+    }
+
+    /// See `blmd::send_current`.
+    pub fn blmd_set_current(
+        &self,
+        address: u8,
+        controller_id: u8,
+        current: i16,
+    ) -> Result<BlMdStatus, crate::Error> {
+        let request = pb::BlmdCurrentRequest {
+            address: address.into(),
+            controller_id: controller_id.into(),
+            current: current.into(),
+        };
+        self.call(self.client.clone().blmd_set_current(self.request(request)))
+            .map(BlMdStatus::from)
+    }
+
+    /// See `sr::send_colors`.
+    pub fn sr_set_color(
+        &self,
+        red: u8,
+        green: u8,
+        blue: u8,
+        freq: f32,
+    ) -> Result<(), crate::Error> {
+        let request = pb::SrColorRequest {
+            red: red.into(),
+            green: green.into(),
+            blue: blue.into(),
+            freq,
+        };
+        self.call(self.client.clone().sr_set_color(self.request(request)))
+            .map(|_| ())
+    }
+
+    /// See `send_emergency`.
+    pub fn emergency(&self) -> Result<(), crate::Error> {
+        self.call(self.client.clone().emergency(self.request(pb::Empty {})))
+            .map(|_| ())
+    }
+
+    /// Requests the status of the device at `address`. `controller_id` is only used for BLMD addresses.
+    pub fn get_status(&self, address: u8, controller_id: u8) -> Result<DeviceStatus, crate::Error> {
+        let request = pb::StatusRequest {
+            address: address.into(),
+            controller_id: controller_id.into(),
+        };
+        let status = self.call(self.client.clone().get_status(self.request(request)))?;
+        match status.status {
+            Some(pb::device_status::Status::Md(status)) => Ok(DeviceStatus::Md(status.into())),
+            Some(pb::device_status::Status::Sd(status)) => Ok(DeviceStatus::Sd(status.into())),
+            Some(pb::device_status::Status::Smd(status)) => Ok(DeviceStatus::Smd(status.into())),
+            Some(pb::device_status::Status::Blmd(status)) => Ok(DeviceStatus::BlMd(status.into())),
+            None => Err(tonic::Status::internal("empty status").into()),
+        }
+    }
+
+    /// Subscribes to the frames the server reads from the bus, without sending anything.
+    ///
+    /// # Arguments
+    ///
+    /// * `device_types` - The device types to receive, or every frame if empty.
+    /// * `addresses` - The addresses to receive, or every frame if empty. BLMD frames are
+    ///   matched by controller ID.
+    ///
+    /// # Returns
+    ///
+    /// A result containing a blocking iterator over the frames.
+    ///
+    /// # Example
+    ///
+    /// Sample code to print the statuses of every MD.
+    /// ```rust,no_run
+    /// use motor_lib::{device_type, Error, GrpcClient};
+    /// fn main() -> Result<(), Error> {
+    ///     let client = GrpcClient::new("http://127.0.0.1:50051")?;
+    ///     for update in client.subscribe_status(&[device_type::MD], &[])? {
+    ///         println!("{}", update?.frame);
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn subscribe_status(
+        &self,
+        device_types: &[u8],
+        addresses: &[u8],
+    ) -> Result<StatusSubscription<'_>, crate::Error> {
+        let request = pb::SubscribeRequest {
+            device_types: device_types.iter().map(|&t| t.into()).collect(),
+            addresses: addresses.iter().map(|&a| a.into()).collect(),
+        };
+        let stream = self.call(self.client.clone().subscribe_status(request))?;
+        Ok(StatusSubscription {
+            tokio_runtime: &self.tokio_runtime,
+            stream,
+        })
+    }
+}
+
+fn md_request(address: u8, value: i16) -> pb::MdRequest {
+    pb::MdRequest {
+        address: address.into(),
+        value: value.into(),
+    }
+}
+
+// The status messages carry the fields of the status structs widened to 32 bits, so the
+// conversions back truncate.
+
+impl From<md::LimSwStatus> for pb::LimSwStatus {
+    fn from(status: md::LimSwStatus) -> Self {
+        Self {
+            limsw_0: status.limsw_0,
+            limsw_1: status.limsw_1,
+        }
+    }
+}
+
+impl From<sd::LimSwStatus> for pb::LimSwStatus {
+    fn from(status: sd::LimSwStatus) -> Self {
+        Self {
+            limsw_0: status.limsw_0,
+            limsw_1: status.limsw_1,
+        }
+    }
+}
+
+impl From<MdStatus> for pb::MdStatus {
+    fn from(status: MdStatus) -> Self {
+        Self {
+            address: status.address.into(),
+            semi_id: status.semi_id.into(),
+            angle: status.angle.into(),
+            speed: status.speed.into(),
+            limsw: Some(status.limsw.into()),
+        }
+    }
+}
+
+impl From<pb::MdStatus> for MdStatus {
+    fn from(status: pb::MdStatus) -> Self {
+        let limsw = status.limsw.unwrap_or_default();
+        Self {
+            address: status.address as u8,
+            semi_id: status.semi_id as u8,
+            angle: status.angle as i16,
+            speed: status.speed as i16,
+            limsw: md::LimSwStatus {
+                limsw_0: limsw.limsw_0,
+                limsw_1: limsw.limsw_1,
+            },
+        }
+    }
+}
+
+impl From<SdStatus> for pb::SdStatus {
+    fn from(status: SdStatus) -> Self {
+        Self {
+            address: status.address.into(),
+            semi_id: status.semi_id.into(),
+            port_0: status.port_0.into(),
+            port_1: status.port_1.into(),
+            limsw: Some(status.limsw.into()),
+        }
+    }
+}
+
+impl From<pb::SdStatus> for SdStatus {
+    fn from(status: pb::SdStatus) -> Self {
+        let limsw = status.limsw.unwrap_or_default();
+        Self {
+            address: status.address as u8,
+            semi_id: status.semi_id as u8,
+            port_0: status.port_0 as i16,
+            port_1: status.port_1 as i16,
+            limsw: sd::LimSwStatus {
+                limsw_0: limsw.limsw_0,
+                limsw_1: limsw.limsw_1,
+            },
+        }
+    }
+}
+
+impl From<SmdStatus> for pb::SmdStatus {
+    fn from(status: SmdStatus) -> Self {
+        Self {
+            address: status.address.into(),
+            semi_id: status.semi_id.into(),
+            angle_0: status.angle_0.into(),
+            angle_1: status.angle_1.into(),
+        }
+    }
+}
+
+impl From<pb::SmdStatus> for SmdStatus {
+    fn from(status: pb::SmdStatus) -> Self {
+        Self {
+            address: status.address as u8,
+            semi_id: status.semi_id as u8,
+            angle_0: status.angle_0 as i16,
+            angle_1: status.angle_1 as i16,
+        }
+    }
+}
+
+impl From<BlMdStatus> for pb::BlmdStatus {
+    fn from(status: BlMdStatus) -> Self {
+        Self {
+            std_id: status.std_id.into(),
+            angle: status.angle.into(),
+            speed: status.speed.into(),
+            current: status.current.into(),
+        }
+    }
+}
+
+impl From<pb::BlmdStatus> for BlMdStatus {
+    fn from(status: pb::BlmdStatus) -> Self {
+        Self {
+            std_id: status.std_id as u16,
+            angle: status.angle as i16,
+            speed: status.speed as i16,
+            current: status.current as i16,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::ErrorKind;
+    use tonic::Code;
+
+    #[test]
+    fn errors_map_to_the_code_a_client_acts_on() {
+        let cases: Vec<(crate::Error, Code)> = vec![
+            (rusb::Error::Timeout.into(), Code::DeadlineExceeded),
+            (rusb::Error::NoDevice.into(), Code::Unavailable),
+            (rusb::Error::NotFound.into(), Code::Unavailable),
+            (rusb::Error::Busy.into(), Code::Unavailable),
+            (rusb::Error::Io.into(), Code::Unavailable),
+            (rusb::Error::Pipe.into(), Code::Aborted),
+            (rusb::Error::Interrupted.into(), Code::Aborted),
+            (rusb::Error::Overflow.into(), Code::DataLoss),
+            (rusb::Error::Access.into(), Code::PermissionDenied),
+            (rusb::Error::InvalidParam.into(), Code::InvalidArgument),
+            (rusb::Error::NotSupported.into(), Code::Unimplemented),
+            (rusb::Error::NoMem.into(), Code::Internal),
+            (rusb::Error::Other.into(), Code::Internal),
+            (
+                std::io::Error::from(ErrorKind::TimedOut).into(),
+                Code::DeadlineExceeded,
+            ),
+            (
+                std::io::Error::from(ErrorKind::NotFound).into(),
+                Code::Unavailable,
+            ),
+            (
+                std::io::Error::from(ErrorKind::NotConnected).into(),
+                Code::Unavailable,
+            ),
+            (
+                std::io::Error::from(ErrorKind::BrokenPipe).into(),
+                Code::Unavailable,
+            ),
+            (
+                std::io::Error::from(ErrorKind::InvalidData).into(),
+                Code::Internal,
+            ),
+            (
+                tonic::Status::resource_exhausted("full").into(),
+                Code::ResourceExhausted,
+            ),
+            (crate::Error::InvalidAddress(0x7f), Code::InvalidArgument),
+            (crate::Error::InvalidPort(2), Code::InvalidArgument),
+            (
+                crate::Error::ConfigError("bad".into()),
+                Code::InvalidArgument,
+            ),
+            (crate::Error::Unsupported("status"), Code::Unimplemented),
+            (
+                crate::Error::LimitExceeded("angle".into()),
+                Code::OutOfRange,
+            ),
+            (
+                crate::Error::Fault("stall".into()),
+                Code::FailedPrecondition,
+            ),
+        ];
+        for (error, code) in cases {
+            let message = error.to_string();
+            let status = tonic::Status::from(error);
+            assert_eq!(status.code(), code, "{}", message);
+        }
+    }
+
+    #[test]
+    fn an_expired_request_is_a_timeout() {
+        let status = expired(tonic::Status::cancelled("Timeout expired"));
+        assert_eq!(status.code(), Code::DeadlineExceeded);
+        assert_eq!(status.message(), "Timeout expired");
+        assert_eq!(
+            expired(tonic::Status::unavailable("gone")).code(),
+            Code::Unavailable
+        );
+
+        assert!(crate::Error::GrpcError(status).is_timeout());
+        assert!(!crate::Error::GrpcError(tonic::Status::cancelled("")).is_timeout());
+        assert!(!crate::Error::GrpcError(tonic::Status::unavailable("")).is_timeout());
+    }
+}
@@ -0,0 +1,527 @@
+//! Implementation of a handle that records every frame to a binary log file.
+//!
+//! A log file starts with [`MAGIC`] and is followed by records of the form
+//! `[direction: u8][outcome: u8][timestamp: u64 LE, µs][len: u8][data; len]`.
+//! Timestamps are measured from the moment recording started and keep counting
+//! across rotated files, so a whole session can be read back in order.
+
+use crate::HandleTrait;
+use std::cell::RefCell;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time;
+
+/// The bytes every log file starts with.
+pub const MAGIC: [u8; 5] = *b"MLOG\x01";
+
+/// The default size at which a log file is rotated.
+pub const DEFAULT_MAX_BYTES: u64 = 16 * 1024 * 1024;
+/// The default number of rotated files kept besides the active one.
+pub const DEFAULT_MAX_FILES: usize = 4;
+
+/// The direction of a recorded frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// A frame sent to the bus with `write_bulk`.
+    Write,
+    /// A frame received from the bus with `read_bulk`.
+    Read,
+}
+
+/// The result of a recorded transfer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Ok,
+    Timeout,
+    Error,
+}
+
+/// A single recorded frame.
+#[derive(Debug, Clone)]
+pub struct Record {
+    pub direction: Direction,
+    pub outcome: Outcome,
+    /// Time since the recording started.
+    pub timestamp: time::Duration,
+    pub data: Vec<u8>,
+}
+
+impl Record {
+    fn encode(&self, out: &mut impl Write) -> io::Result<usize> {
+        let len = self.data.len().min(u8::MAX as usize);
+        let mut header = [0; 11];
+        header[0] = match self.direction {
+            Direction::Write => 0,
+            Direction::Read => 1,
+        };
+        header[1] = match self.outcome {
+            Outcome::Ok => 0,
+            Outcome::Timeout => 1,
+            Outcome::Error => 2,
+        };
+        header[2..10].copy_from_slice(&(self.timestamp.as_micros() as u64).to_le_bytes());
+        header[10] = len as u8;
+        out.write_all(&header)?;
+        out.write_all(&self.data[..len])?;
+        Ok(header.len() + len)
+    }
+
+    fn decode(input: &mut impl Read) -> io::Result<Option<Record>> {
+        let mut header = [0; 11];
+        match input.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+        let direction = match header[0] {
+            0 => Direction::Write,
+            1 => Direction::Read,
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "bad direction")),
+        };
+        let outcome = match header[1] {
+            0 => Outcome::Ok,
+            1 => Outcome::Timeout,
+            2 => Outcome::Error,
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "bad outcome")),
+        };
+        let mut micros = [0; 8];
+        micros.copy_from_slice(&header[2..10]);
+        let mut data = vec![0; header[10] as usize];
+        match input.read_exact(&mut data) {
+            Ok(()) => {}
+            // A record cut short by a crash or power loss ends the file.
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+        Ok(Some(Record {
+            direction,
+            outcome,
+            timestamp: time::Duration::from_micros(u64::from_le_bytes(micros)),
+            data,
+        }))
+    }
+}
+
+/// Reads every record from a single log file.
+pub fn read_records(path: impl AsRef<Path>) -> Result<Vec<Record>, crate::Error> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut magic = [0; MAGIC.len()];
+    reader.read_exact(&mut magic)?;
+    if magic != MAGIC {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "not a motor_lib log file").into());
+    }
+    let mut records = Vec::new();
+    while let Some(record) = Record::decode(&mut reader)? {
+        records.push(record);
+    }
+    Ok(records)
+}
+
+/// Reads a whole session, including rotated files, oldest first.
+///
+/// `path` is the path that was given to [`RecordingHandle::new`].
+pub fn read_session(path: impl AsRef<Path>) -> Result<Vec<Record>, crate::Error> {
+    let path = path.as_ref();
+    let rotated: Vec<PathBuf> = (1..)
+        .map(|index| rotated_path(path, index))
+        .take_while(|rotated_path| rotated_path.exists())
+        .collect();
+    let mut records = Vec::new();
+    for rotated_path in rotated.iter().rev() {
+        records.extend(read_records(rotated_path)?);
+    }
+    if path.exists() {
+        records.extend(read_records(path)?);
+    }
+    Ok(records)
+}
+
+fn rotated_path(path: &Path, index: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}", index));
+    PathBuf::from(name)
+}
+
+struct LogFile {
+    path: PathBuf,
+    writer: BufWriter<File>,
+    size: u64,
+    max_bytes: u64,
+    max_files: usize,
+}
+
+impl LogFile {
+    fn create(path: PathBuf, max_bytes: u64, max_files: usize) -> io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(&path)?);
+        writer.write_all(&MAGIC)?;
+        writer.flush()?;
+        Ok(Self {
+            path,
+            writer,
+            size: MAGIC.len() as u64,
+            max_bytes,
+            max_files,
+        })
+    }
+
+    fn append(&mut self, record: &Record) -> io::Result<()> {
+        if self.size >= self.max_bytes {
+            self.rotate()?;
+        }
+        self.size += record.encode(&mut self.writer)? as u64;
+        // Flush every record so the log survives the robot being switched off.
+        self.writer.flush()
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.writer.flush()?;
+        if self.max_files == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            let oldest = rotated_path(&self.path, self.max_files);
+            if oldest.exists() {
+                fs::remove_file(oldest)?;
+            }
+            for index in (1..self.max_files).rev() {
+                let from = rotated_path(&self.path, index);
+                if from.exists() {
+                    fs::rename(from, rotated_path(&self.path, index + 1))?;
+                }
+            }
+            fs::rename(&self.path, rotated_path(&self.path, 1))?;
+        }
+        *self = Self::create(self.path.clone(), self.max_bytes, self.max_files)?;
+        Ok(())
+    }
+}
+
+/// A handle that forwards reads and writes to another handle and records every frame.
+///
+/// Failing to write the log never affects the wrapped handle; recording stops at the
+/// first log error, which is kept until [`RecordingHandle::take_error`] is called.
+pub struct RecordingHandle<H: HandleTrait> {
+    inner: H,
+    log: RefCell<Option<LogFile>>,
+    error: RefCell<Option<io::Error>>,
+    started: time::Instant,
+}
+
+impl<H: HandleTrait> RecordingHandle<H> {
+    /// Wraps `inner` and records to `path`, rotating with the default limits.
+    ///
+    /// # Example
+    ///
+    /// Sample code to record the traffic of a match to `match.mlog`.
+    /// ```rust,no_run
+    /// use motor_lib::{md, Error, RecordingHandle, USBHandle};
+    /// fn main() -> Result<(), Error> {
+    ///     let handle = RecordingHandle::new(USBHandle::new(0x483, 0x5740, 1), "match.mlog")?;
+    ///     md::send_pwm(&handle, 0x00, 1000)?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn new(inner: H, path: impl AsRef<Path>) -> Result<Self, crate::Error> {
+        Self::with_rotation(inner, path, DEFAULT_MAX_BYTES, DEFAULT_MAX_FILES)
+    }
+
+    /// Wraps `inner` and records to `path`.
+    ///
+    /// Once the file reaches `max_bytes` it is renamed to `path.1` (shifting older files
+    /// up to `path.<max_files>`) and a new file is started.
+    pub fn with_rotation(
+        inner: H,
+        path: impl AsRef<Path>,
+        max_bytes: u64,
+        max_files: usize,
+    ) -> Result<Self, crate::Error> {
+        let log = LogFile::create(path.as_ref().to_path_buf(), max_bytes, max_files)?;
+        Ok(Self {
+            inner,
+            log: RefCell::new(Some(log)),
+            error: RefCell::new(None),
+            started: time::Instant::now(),
+        })
+    }
+
+    /// Returns a reference to the wrapped handle.
+    pub fn inner(&self) -> &H {
+        &self.inner
+    }
+
+    /// Returns `true` until a log error stops the recording.
+    pub fn is_recording(&self) -> bool {
+        self.log.borrow().is_some()
+    }
+
+    /// Returns the error that stopped the recording, if it has not been taken yet.
+    ///
+    /// # Example
+    ///
+    /// Sample code to warn once the log can no longer be written.
+    /// ```rust,no_run
+    /// use motor_lib::{md, Error, RecordingHandle, USBHandle};
+    /// fn main() -> Result<(), Error> {
+    ///     let handle = RecordingHandle::new(USBHandle::new(0x483, 0x5740, 1), "match.mlog")?;
+    ///     md::send_pwm(&handle, 0x00, 1000)?;
+    ///     if let Some(e) = handle.take_error() {
+    ///         eprintln!("Recording stopped: {}", e);
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn take_error(&self) -> Option<crate::Error> {
+        self.error.borrow_mut().take().map(Into::into)
+    }
+
+    /// Stops recording and returns the wrapped handle.
+    pub fn into_inner(self) -> H {
+        self.inner
+    }
+
+    fn record(&self, direction: Direction, result: &Result<usize, crate::Error>, data: &[u8]) {
+        let mut log = self.log.borrow_mut();
+        let Some(file) = log.as_mut() else {
+            return;
+        };
+        let failure = |e: &crate::Error| {
+            if e.is_timeout() {
+                Outcome::Timeout
+            } else {
+                Outcome::Error
+            }
+        };
+        // Failed writes keep the frame that was attempted; failed reads have no data.
+        let (outcome, data) = match (direction, result) {
+            (_, Ok(size)) => (Outcome::Ok, &data[..(*size).min(data.len())]),
+            (Direction::Write, Err(e)) => (failure(e), data),
+            (Direction::Read, Err(e)) => (failure(e), &data[..0]),
+        };
+        let record = Record {
+            direction,
+            outcome,
+            timestamp: self.started.elapsed(),
+            data: data.to_vec(),
+        };
+        if let Err(e) = file.append(&record) {
+            let e = io::Error::new(
+                e.kind(),
+                format!("recording to {} stopped: {}", file.path.display(), e),
+            );
+            *self.error.borrow_mut() = Some(e);
+            *log = None;
+        }
+    }
+}
+
+impl<H: HandleTrait> HandleTrait for RecordingHandle<H> {
+    fn read_bulk(&self, data: &mut [u8], timeout: time::Duration) -> Result<usize, crate::Error> {
+        let result = self.inner.read_bulk(data, timeout);
+        self.record(Direction::Read, &result, data);
+        result
+    }
+
+    fn write_bulk(&self, data: &[u8], timeout: time::Duration) -> Result<usize, crate::Error> {
+        let result = self.inner.write_bulk(data, timeout);
+        self.record(Direction::Write, &result, data);
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ReplayHandle;
+
+    const TIMEOUT: time::Duration = time::Duration::from_millis(10);
+
+    /// A directory removed with its files at the end of a test.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!(
+                "motor_lib_recording_{}.{}",
+                name,
+                std::process::id()
+            ));
+            fs::create_dir_all(&path).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn frame(index: u8) -> [u8; 8] {
+        [index, 0x60, 0x02, 0, 0, 0, 0, 0]
+    }
+
+    /// The size of a record of an 8-byte frame.
+    const RECORD_SIZE: u64 = 11 + 8;
+
+    #[test]
+    fn a_file_is_the_magic_then_the_records() {
+        let dir = TempDir::new("format");
+        let path = dir.0.join("bus.mlog");
+        let handle = RecordingHandle::new(ReplayHandle::new(Vec::new()), &path).unwrap();
+        handle.write_bulk(&frame(1), TIMEOUT).unwrap();
+        drop(handle);
+
+        let bytes = fs::read(&path).unwrap();
+        assert_eq!(bytes.len() as u64, MAGIC.len() as u64 + RECORD_SIZE);
+        assert_eq!(bytes[..5], *b"MLOG\x01");
+        let header = &bytes[5..16];
+        assert_eq!(header[..2], [0, 0]);
+        let micros = u64::from_le_bytes(header[2..10].try_into().unwrap());
+        assert!(micros < 1_000_000, "{}", micros);
+        assert_eq!(header[10], 8);
+        assert_eq!(bytes[16..], frame(1));
+
+        let records = read_records(&path).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].direction, Direction::Write);
+        assert_eq!(records[0].outcome, Outcome::Ok);
+        assert_eq!(records[0].timestamp.as_micros() as u64, micros);
+        assert_eq!(records[0].data, frame(1));
+    }
+
+    #[test]
+    fn records_round_trip_with_their_outcome() {
+        let dir = TempDir::new("outcomes");
+        let path = dir.0.join("bus.mlog");
+        let bus = ReplayHandle::new(vec![
+            Record {
+                direction: Direction::Read,
+                outcome: Outcome::Ok,
+                timestamp: time::Duration::ZERO,
+                data: frame(2).to_vec(),
+            },
+            Record {
+                direction: Direction::Read,
+                outcome: Outcome::Timeout,
+                timestamp: time::Duration::ZERO,
+                data: Vec::new(),
+            },
+            Record {
+                direction: Direction::Read,
+                outcome: Outcome::Error,
+                timestamp: time::Duration::ZERO,
+                data: Vec::new(),
+            },
+        ]);
+        let handle = RecordingHandle::new(bus, &path).unwrap();
+        let mut buf = [0; 8];
+        handle.read_bulk(&mut buf, TIMEOUT).unwrap();
+        handle.read_bulk(&mut buf, TIMEOUT).unwrap_err();
+        handle.read_bulk(&mut buf, TIMEOUT).unwrap_err();
+        drop(handle);
+
+        let records = read_records(&path).unwrap();
+        let outcomes: Vec<_> = records.iter().map(|record| record.outcome).collect();
+        assert_eq!(outcomes, [Outcome::Ok, Outcome::Timeout, Outcome::Error]);
+        assert!(records
+            .iter()
+            .all(|record| record.direction == Direction::Read));
+        assert_eq!(records[0].data, frame(2));
+        assert!(records[1].data.is_empty() && records[2].data.is_empty());
+        assert!(records
+            .windows(2)
+            .all(|pair| pair[0].timestamp <= pair[1].timestamp));
+    }
+
+    #[test]
+    fn rotation_keeps_max_files_and_a_session_reads_oldest_first() {
+        let dir = TempDir::new("rotation");
+        let path = dir.0.join("bus.mlog");
+        // Each file holds a single record.
+        let max_bytes = MAGIC.len() as u64 + RECORD_SIZE;
+        let handle =
+            RecordingHandle::with_rotation(ReplayHandle::new(Vec::new()), &path, max_bytes, 2)
+                .unwrap();
+        for index in 0..5 {
+            handle.write_bulk(&frame(index), TIMEOUT).unwrap();
+        }
+        assert!(handle.take_error().is_none());
+        drop(handle);
+
+        assert_eq!(read_records(&path).unwrap()[0].data, frame(4));
+        assert_eq!(
+            read_records(rotated_path(&path, 1)).unwrap()[0].data,
+            frame(3)
+        );
+        assert_eq!(
+            read_records(rotated_path(&path, 2)).unwrap()[0].data,
+            frame(2)
+        );
+        assert!(!rotated_path(&path, 3).exists());
+
+        let session: Vec<u8> = read_session(&path)
+            .unwrap()
+            .iter()
+            .map(|record| record.data[0])
+            .collect();
+        assert_eq!(session, [2, 3, 4]);
+    }
+
+    #[test]
+    fn a_truncated_last_record_is_ignored() {
+        let dir = TempDir::new("truncated");
+        let path = dir.0.join("bus.mlog");
+        let handle = RecordingHandle::new(ReplayHandle::new(Vec::new()), &path).unwrap();
+        handle.write_bulk(&frame(1), TIMEOUT).unwrap();
+        handle.write_bulk(&frame(2), TIMEOUT).unwrap();
+        drop(handle);
+
+        let file = fs::OpenOptions::new().write(true).open(&path).unwrap();
+        let full = file.metadata().unwrap().len();
+        // Cut the last record in its data, then in its header.
+        for cut in [3, RECORD_SIZE - 4] {
+            file.set_len(full - cut).unwrap();
+            let records = read_records(&path).unwrap();
+            assert_eq!(records.len(), 1);
+            assert_eq!(records[0].data, frame(1));
+        }
+    }
+
+    #[test]
+    fn other_files_are_rejected() {
+        let dir = TempDir::new("magic");
+        let path = dir.0.join("bus.mlog");
+        fs::write(&path, b"MLOG\x02").unwrap();
+        assert!(read_records(&path).is_err());
+        fs::write(&path, b"ML").unwrap();
+        assert!(read_records(&path).is_err());
+    }
+
+    #[test]
+    fn a_log_error_stops_recording_and_is_kept() {
+        let dir = TempDir::new("error");
+        let path = dir.0.join("bus.mlog");
+        let frame = frame(1);
+        let records = (0..2)
+            .map(|_| Record {
+                direction: Direction::Write,
+                outcome: Outcome::Ok,
+                timestamp: time::Duration::ZERO,
+                data: frame.to_vec(),
+            })
+            .collect();
+        // Rotating without keeping files removes the log, which fails once it is gone.
+        let handle =
+            RecordingHandle::with_rotation(ReplayHandle::new(records), &path, 0, 0).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(handle.write_bulk(&frame, TIMEOUT).unwrap(), 8);
+        assert!(!handle.is_recording());
+        assert!(handle.take_error().is_some());
+        assert!(handle.take_error().is_none());
+
+        assert_eq!(handle.write_bulk(&frame, TIMEOUT).unwrap(), 8);
+        assert!(handle.take_error().is_none());
+        assert!(!path.exists());
+    }
+}
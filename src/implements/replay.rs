@@ -0,0 +1,283 @@
+//! Implementation of a handle that plays back a session recorded by `RecordingHandle`.
+
+use crate::recording::{self, Direction, Outcome, Record};
+use crate::HandleTrait;
+use std::cell::{Cell, RefCell};
+use std::path::Path;
+use std::{fmt, io, thread, time};
+
+/// An outbound frame that did not match the recording.
+#[derive(Debug, Clone)]
+pub struct Divergence {
+    /// The position of the frame among all outbound frames, starting at 0.
+    pub index: usize,
+    /// The time of the recorded frame, or `None` if the recording had no more frames.
+    pub timestamp: Option<time::Duration>,
+    pub expected: Option<Vec<u8>>,
+    pub actual: Vec<u8>,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.expected, self.timestamp) {
+            (Some(expected), Some(timestamp)) => write!(
+                f,
+                "write #{} at {:?}: expected {:02x?}, got {:02x?}",
+                self.index, timestamp, expected, self.actual
+            ),
+            _ => write!(
+                f,
+                "write #{}: unexpected {:02x?} after the end of the recording",
+                self.index, self.actual
+            ),
+        }
+    }
+}
+
+/// A handle that serves recorded inbound frames and checks outbound frames against the recording.
+///
+/// Reads return the recorded inbound frames in order, including recorded timeouts and errors.
+/// Writes are compared with the recorded outbound frames in order and any mismatch is kept as
+/// a [`Divergence`]; the recorded result of the write is returned either way.
+pub struct ReplayHandle {
+    reads: Vec<Record>,
+    writes: Vec<Record>,
+    read_cursor: Cell<usize>,
+    write_cursor: Cell<usize>,
+    realtime: bool,
+    origin: time::Duration,
+    started: Cell<Option<time::Instant>>,
+    divergences: RefCell<Vec<Divergence>>,
+}
+
+impl ReplayHandle {
+    /// Creates a handle that replays `records`.
+    pub fn new(records: Vec<Record>) -> Self {
+        let origin = records
+            .first()
+            .map(|record| record.timestamp)
+            .unwrap_or_default();
+        let (reads, writes) = records
+            .into_iter()
+            .partition(|record| record.direction == Direction::Read);
+        Self {
+            reads,
+            writes,
+            read_cursor: Cell::new(0),
+            write_cursor: Cell::new(0),
+            realtime: false,
+            origin,
+            started: Cell::new(None),
+            divergences: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// Creates a handle that replays the session recorded to `path`, including rotated files.
+    ///
+    /// # Example
+    ///
+    /// Sample code to check that the control code still sends what it sent during a match.
+    /// ```rust,no_run
+    /// use motor_lib::{md, Error, ReplayHandle};
+    /// fn main() -> Result<(), Error> {
+    ///     let handle = ReplayHandle::open("match.mlog")?;
+    ///     md::send_pwm(&handle, 0x00, 1000)?;
+    ///     for divergence in handle.divergences() {
+    ///         println!("{}", divergence);
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, crate::Error> {
+        Ok(Self::new(recording::read_session(path)?))
+    }
+
+    /// Delays each inbound frame so that it arrives as long after the first transfer as it
+    /// did after the first recorded frame.
+    pub fn set_realtime(&mut self, realtime: bool) {
+        self.realtime = realtime;
+    }
+
+    /// Returns the outbound frames that did not match the recording so far.
+    pub fn divergences(&self) -> Vec<Divergence> {
+        self.divergences.borrow().clone()
+    }
+
+    /// Returns true once every recorded frame has been replayed.
+    pub fn is_finished(&self) -> bool {
+        self.read_cursor.get() >= self.reads.len() && self.write_cursor.get() >= self.writes.len()
+    }
+
+    fn elapsed(&self) -> time::Duration {
+        let started = self.started.get().unwrap_or_else(|| {
+            let now = time::Instant::now();
+            self.started.set(Some(now));
+            now
+        });
+        started.elapsed()
+    }
+}
+
+fn recorded_result(record: &Record, size: usize) -> Result<usize, crate::Error> {
+    match record.outcome {
+        Outcome::Ok => Ok(size),
+        Outcome::Timeout => Err(io::Error::from(io::ErrorKind::TimedOut).into()),
+        Outcome::Error => Err(io::Error::other("recorded transfer error").into()),
+    }
+}
+
+impl HandleTrait for ReplayHandle {
+    fn read_bulk(&self, data: &mut [u8], timeout: time::Duration) -> Result<usize, crate::Error> {
+        let elapsed = self.elapsed();
+        let Some(record) = self.reads.get(self.read_cursor.get()) else {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "end of recording").into());
+        };
+        let due = record.timestamp.saturating_sub(self.origin);
+        if self.realtime && due > elapsed {
+            let wait = due - elapsed;
+            if wait > timeout {
+                thread::sleep(timeout);
+                return Err(io::Error::from(io::ErrorKind::TimedOut).into());
+            }
+            thread::sleep(wait);
+        }
+        self.read_cursor.set(self.read_cursor.get() + 1);
+        let len = data.len().min(record.data.len());
+        data[..len].copy_from_slice(&record.data[..len]);
+        recorded_result(record, len)
+    }
+
+    fn write_bulk(&self, data: &[u8], _timeout: time::Duration) -> Result<usize, crate::Error> {
+        self.elapsed();
+        let index = self.write_cursor.get();
+        self.write_cursor.set(index + 1);
+        let Some(record) = self.writes.get(index) else {
+            self.divergences.borrow_mut().push(Divergence {
+                index,
+                timestamp: None,
+                expected: None,
+                actual: data.to_vec(),
+            });
+            return Ok(data.len());
+        };
+        if record.data != data {
+            self.divergences.borrow_mut().push(Divergence {
+                index,
+                timestamp: Some(record.timestamp),
+                expected: Some(record.data.clone()),
+                actual: data.to_vec(),
+            });
+        }
+        recorded_result(record, data.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::RecordingHandle;
+    use std::{env, fs, process};
+
+    const TIMEOUT: time::Duration = time::Duration::from_millis(100);
+    const REQUEST: [u8; 8] = [0x01, 0x60, 0x01, 0, 0, 0, 0, 0];
+    const REPLY: [u8; 8] = [0x01, 0, 0, 90, 0, 100, 0, 0];
+
+    fn record(direction: Direction, outcome: Outcome, millis: u64, data: &[u8]) -> Record {
+        Record {
+            direction,
+            outcome,
+            timestamp: time::Duration::from_millis(millis),
+            data: data.to_vec(),
+        }
+    }
+
+    /// Records a session in which a status request is answered, the next read times out
+    /// and the one after that fails.
+    fn recorded_session(name: &str) -> ReplayHandle {
+        let path = env::temp_dir().join(format!("motor_lib_replay_{}.{}", name, process::id()));
+        let bus = ReplayHandle::new(vec![
+            record(Direction::Write, Outcome::Ok, 0, &REQUEST),
+            record(Direction::Read, Outcome::Ok, 0, &REPLY),
+            record(Direction::Read, Outcome::Timeout, 0, &[]),
+            record(Direction::Read, Outcome::Error, 0, &[]),
+        ]);
+        let recorder = RecordingHandle::new(bus, &path).unwrap();
+        let mut buf = [0; 8];
+        recorder.write_bulk(&REQUEST, TIMEOUT).unwrap();
+        recorder.read_bulk(&mut buf, TIMEOUT).unwrap();
+        recorder.read_bulk(&mut buf, TIMEOUT).unwrap_err();
+        recorder.read_bulk(&mut buf, TIMEOUT).unwrap_err();
+        assert!(recorder.take_error().is_none());
+        drop(recorder);
+        let replay = ReplayHandle::open(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        replay
+    }
+
+    #[test]
+    fn reads_come_back_in_order_with_their_results() {
+        let replay = recorded_session("reads");
+        let mut buf = [0; 8];
+        replay.write_bulk(&REQUEST, TIMEOUT).unwrap();
+        assert_eq!(replay.read_bulk(&mut buf, TIMEOUT).unwrap(), 8);
+        assert_eq!(buf, REPLY);
+        assert!(replay
+            .read_bulk(&mut buf, TIMEOUT)
+            .unwrap_err()
+            .is_timeout());
+        let error = replay.read_bulk(&mut buf, TIMEOUT).unwrap_err();
+        assert!(!error.is_timeout());
+        assert!(replay.is_finished());
+        assert!(replay.divergences().is_empty());
+
+        let end = replay.read_bulk(&mut buf, TIMEOUT).unwrap_err();
+        assert!(end.to_string().contains("end of recording"));
+        assert!(replay.is_finished());
+    }
+
+    #[test]
+    fn writes_that_differ_are_divergences() {
+        let replay = recorded_session("writes");
+        let other = [0x02, 0x60, 0x01, 0, 0, 0, 0, 0];
+        assert_eq!(replay.write_bulk(&other, TIMEOUT).unwrap(), 8);
+        assert_eq!(replay.write_bulk(&REQUEST, TIMEOUT).unwrap(), 8);
+
+        let divergences = replay.divergences();
+        assert_eq!(divergences.len(), 2);
+        assert_eq!(divergences[0].index, 0);
+        assert_eq!(divergences[0].expected.as_deref(), Some(&REQUEST[..]));
+        assert_eq!(divergences[0].actual, other);
+        assert_eq!(divergences[1].index, 1);
+        assert_eq!(divergences[1].timestamp, None);
+        assert_eq!(divergences[1].expected, None);
+        assert_eq!(divergences[1].actual, REQUEST);
+    }
+
+    #[test]
+    fn realtime_waits_for_the_recorded_gap() {
+        let mut replay = ReplayHandle::new(vec![
+            record(Direction::Write, Outcome::Ok, 1000, &REQUEST),
+            record(Direction::Read, Outcome::Ok, 1050, &REPLY),
+            record(Direction::Read, Outcome::Ok, 2050, &REPLY),
+        ]);
+        replay.set_realtime(true);
+        let mut buf = [0; 8];
+        replay.write_bulk(&REQUEST, TIMEOUT).unwrap();
+
+        let started = time::Instant::now();
+        replay.read_bulk(&mut buf, TIMEOUT).unwrap();
+        let waited = started.elapsed();
+        assert!(waited >= time::Duration::from_millis(40), "{:?}", waited);
+        assert!(waited < time::Duration::from_millis(500), "{:?}", waited);
+
+        let started = time::Instant::now();
+        assert!(replay
+            .read_bulk(&mut buf, TIMEOUT)
+            .unwrap_err()
+            .is_timeout());
+        let waited = started.elapsed();
+        assert!(waited >= TIMEOUT, "{:?}", waited);
+        assert!(waited < time::Duration::from_millis(900), "{:?}", waited);
+        assert!(!replay.is_finished());
+    }
+}
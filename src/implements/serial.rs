@@ -0,0 +1,331 @@
+//! Implementation of a serial (CDC-ACM / tty) backend.
+//!
+//! The adapter sends and receives the same raw 8-byte frames over its CDC data interface as
+//! over the bulk endpoint used by `USBHandle`, without any framing. A tty is a byte stream,
+//! so the frames are cut every 8 bytes. The adapter writes each frame in one USB packet, so
+//! the bytes received only stop in the middle of a frame when bytes were lost or the tty was
+//! opened in the middle of a frame.
+//!
+//! A read that takes every byte received so far therefore ends on a frame boundary. Until the
+//! first such read, the handle does not know where the frames start, and drops the bytes in
+//! excess of whole frames from the oldest end. After that, a read of whole frames that leaves
+//! a partial frame before them means that the rest of that frame was lost, and the partial
+//! frame is dropped. A partial frame that is still incomplete once the line has been idle for
+//! `RESYNC_GAP` is dropped as well.
+
+use crate::HandleTrait;
+use std::cell::{Cell, RefCell};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use std::time;
+
+/// The number of bytes of a frame.
+pub const FRAME_SIZE: usize = 8;
+/// How long the line must stay idle before a partial frame is dropped.
+pub const RESYNC_GAP: time::Duration = time::Duration::from_millis(20);
+/// The most bytes taken from the tty at once.
+const CHUNK_SIZE: usize = 256;
+
+/// A handle to read and write a serial device such as `/dev/ttyACM0`.
+pub struct SerialHandle {
+    port: File,
+    buffer: RefCell<Vec<u8>>,
+    /// Whether the buffer starts on a frame boundary. Until then, a partial frame is at its
+    /// start rather than at its end.
+    synced: Cell<bool>,
+}
+
+impl SerialHandle {
+    /// Opens the tty at `path` and switches it to raw mode.
+    ///
+    /// # Example
+    ///
+    /// Sample code to rotate a motor connected to the MD at address 0x00 through `/dev/ttyACM0`.
+    /// ```rust,no_run
+    /// use motor_lib::{md, Error, SerialHandle};
+    /// fn main() -> Result<(), Error> {
+    ///     let handle = SerialHandle::new("/dev/ttyACM0")?;
+    ///     md::send_pwm(&handle, 0x00, 1000)?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn new(path: impl AsRef<Path>) -> Result<Self, crate::Error> {
+        let port = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NOCTTY | libc::O_NONBLOCK)
+            .open(path)?;
+
+        let fd = port.as_raw_fd();
+        let mut termios: libc::termios = unsafe { std::mem::zeroed() };
+        if unsafe { libc::tcgetattr(fd, &mut termios) } < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        unsafe { libc::cfmakeraw(&mut termios) };
+        termios.c_cflag |= libc::CLOCAL | libc::CREAD;
+        if unsafe { libc::tcsetattr(fd, libc::TCSANOW, &termios) } < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        unsafe { libc::tcflush(fd, libc::TCIOFLUSH) };
+
+        Ok(Self {
+            port,
+            buffer: RefCell::new(Vec::with_capacity(CHUNK_SIZE)),
+            synced: Cell::new(false),
+        })
+    }
+
+    fn wait(&self, events: libc::c_short, timeout: time::Duration) -> Result<(), crate::Error> {
+        let mut pollfd = libc::pollfd {
+            fd: self.port.as_raw_fd(),
+            events,
+            revents: 0,
+        };
+        let timeout_ms = timeout.as_millis().min(libc::c_int::MAX as u128) as libc::c_int;
+        match unsafe { libc::poll(&mut pollfd, 1, timeout_ms) } {
+            n if n < 0 => Err(io::Error::last_os_error().into()),
+            0 => Err(io::Error::from(io::ErrorKind::TimedOut).into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Drops the partial frame of `buffer`, which is at its start until the handle is synced.
+    fn drop_partial(&self, buffer: &mut Vec<u8>) {
+        let excess = buffer.len() % FRAME_SIZE;
+        if self.synced.get() {
+            buffer.truncate(buffer.len() - excess);
+        } else {
+            buffer.drain(..excess);
+        }
+        self.synced.set(true);
+    }
+}
+
+impl HandleTrait for SerialHandle {
+    fn read_bulk(&self, data: &mut [u8], timeout: time::Duration) -> Result<usize, crate::Error> {
+        let deadline = time::Instant::now() + timeout;
+        let mut buffer = self.buffer.borrow_mut();
+        loop {
+            let excess = buffer.len() % FRAME_SIZE;
+            // Once synced, the oldest frame is complete even if the next one is still arriving.
+            if buffer.len() >= FRAME_SIZE && (self.synced.get() || excess == 0) {
+                self.synced.set(true);
+                let len = data.len().min(FRAME_SIZE);
+                data[..len].copy_from_slice(&buffer[..len]);
+                buffer.drain(..FRAME_SIZE);
+                return Ok(len);
+            }
+            let remaining = deadline.saturating_duration_since(time::Instant::now());
+            let wait = match excess {
+                0 => remaining,
+                _ => remaining.min(RESYNC_GAP),
+            };
+            match self.wait(libc::POLLIN, wait) {
+                Err(e) if e.is_timeout() && wait < remaining => {
+                    self.drop_partial(&mut buffer);
+                    continue;
+                }
+                result => result?,
+            }
+            let mut chunk = [0; CHUNK_SIZE];
+            match (&self.port).read(&mut chunk) {
+                Ok(0) => return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+                Ok(size) => {
+                    let partial = buffer.len() - excess..buffer.len();
+                    buffer.extend_from_slice(&chunk[..size]);
+                    // Every byte received so far was read, so the buffer ends a frame.
+                    if size < CHUNK_SIZE {
+                        if !self.synced.get() {
+                            self.drop_partial(&mut buffer);
+                        } else if size % FRAME_SIZE == 0 {
+                            buffer.drain(partial);
+                        }
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    fn write_bulk(&self, data: &[u8], timeout: time::Duration) -> Result<usize, crate::Error> {
+        let len = data.len().min(FRAME_SIZE);
+        let mut frame = [0; FRAME_SIZE];
+        frame[..len].copy_from_slice(&data[..len]);
+
+        let deadline = time::Instant::now() + timeout;
+        let mut written = 0;
+        while written < FRAME_SIZE {
+            self.wait(
+                libc::POLLOUT,
+                deadline.saturating_duration_since(time::Instant::now()),
+            )?;
+            match (&self.port).write(&frame[written..]) {
+                Ok(size) => written += size,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CStr;
+    use std::os::fd::FromRawFd;
+
+    /// Opens a pseudo-terminal pair, and returns the master side, which plays the adapter,
+    /// and a handle on the slave side.
+    fn open_pty() -> (File, File, SerialHandle) {
+        let (mut master, mut slave) = (0, 0);
+        let result = unsafe {
+            libc::openpty(
+                &mut master,
+                &mut slave,
+                std::ptr::null_mut(),
+                std::ptr::null(),
+                std::ptr::null(),
+            )
+        };
+        assert_eq!(result, 0, "{}", io::Error::last_os_error());
+        let (master, slave) = unsafe { (File::from_raw_fd(master), File::from_raw_fd(slave)) };
+        let path = unsafe { CStr::from_ptr(libc::ttyname(slave.as_raw_fd())) }
+            .to_str()
+            .unwrap()
+            .to_string();
+        let handle = SerialHandle::new(path).unwrap();
+        // The slave stays open so the master does not see a hang-up.
+        (master, slave, handle)
+    }
+
+    const TIMEOUT: time::Duration = time::Duration::from_secs(1);
+
+    #[test]
+    fn frames_are_written_raw() {
+        let (mut master, _slave, handle) = open_pty();
+        let frame = [0x00, 0x60, 2, 0, 0x03, 0xe8, 0, 0];
+        assert_eq!(handle.write_bulk(&frame, TIMEOUT).unwrap(), FRAME_SIZE);
+        let mut received = [0; FRAME_SIZE];
+        master.read_exact(&mut received).unwrap();
+        assert_eq!(received, frame);
+    }
+
+    #[test]
+    fn frames_are_cut_every_8_bytes() {
+        let (mut master, _slave, handle) = open_pty();
+        let frames = [[1, 2, 3, 4, 5, 6, 7, 8], [0xa5, 0, 0, 0, 0, 0, 0, 0xff]];
+        // Two frames split across three writes.
+        master.write_all(&frames[0][..5]).unwrap();
+        master
+            .write_all(&[&frames[0][5..], &frames[1][..2]].concat())
+            .unwrap();
+        master.write_all(&frames[1][2..]).unwrap();
+        for frame in frames {
+            let mut received = [0; FRAME_SIZE];
+            assert_eq!(
+                handle.read_bulk(&mut received, TIMEOUT).unwrap(),
+                FRAME_SIZE
+            );
+            assert_eq!(received, frame);
+        }
+    }
+
+    #[test]
+    fn partial_frame_is_dropped_when_the_line_is_idle() {
+        let (mut master, _slave, handle) = open_pty();
+        master.write_all(&[0xde, 0xad, 0xbe]).unwrap();
+        std::thread::sleep(RESYNC_GAP * 3);
+        let frame = [0x10, 0x60, 1, 0, 0, 0, 0, 0];
+        master.write_all(&frame).unwrap();
+        let mut received = [0; FRAME_SIZE];
+        handle.read_bulk(&mut received, TIMEOUT).unwrap();
+        assert_eq!(received, frame);
+    }
+
+    #[test]
+    fn read_times_out_without_a_whole_frame() {
+        let (mut master, _slave, handle) = open_pty();
+        master.write_all(&[1, 2, 3]).unwrap();
+        let mut received = [0; FRAME_SIZE];
+        let error = handle
+            .read_bulk(&mut received, time::Duration::from_millis(50))
+            .unwrap_err();
+        assert!(error.is_timeout());
+    }
+
+    #[test]
+    fn a_stream_that_starts_mid_frame_is_synced_without_a_gap() {
+        let (mut master, _slave, handle) = open_pty();
+        let frames: Vec<[u8; FRAME_SIZE]> =
+            (1..=100).map(|i| [0x01, 0, 0, i, 0, 0, 0, 0]).collect();
+        // The tail of a frame sent before the tty was opened, and then a busy line that is
+        // never idle for `RESYNC_GAP` until after the read times out.
+        master
+            .write_all(&[&[0xad, 0xbe, 0xef][..], &frames[0]].concat())
+            .unwrap();
+        let writer = {
+            let frames = frames.clone();
+            std::thread::spawn(move || {
+                for frame in &frames[1..] {
+                    std::thread::sleep(time::Duration::from_millis(2));
+                    master.write_all(frame).unwrap();
+                }
+                master
+            })
+        };
+        for frame in &frames[..10] {
+            let mut received = [0; FRAME_SIZE];
+            handle
+                .read_bulk(&mut received, time::Duration::from_millis(100))
+                .unwrap();
+            assert_eq!(received, *frame);
+        }
+        writer.join().unwrap();
+    }
+
+    #[test]
+    fn the_oldest_frame_is_returned_while_the_next_arrives() {
+        let (mut master, _slave, handle) = open_pty();
+        let frames = [[1, 2, 3, 4, 5, 6, 7, 8], [0x10, 0, 0, 0, 0, 0, 0, 1]];
+        let mut received = [0; FRAME_SIZE];
+        master.write_all(&frames[0]).unwrap();
+        handle.read_bulk(&mut received, TIMEOUT).unwrap();
+
+        master
+            .write_all(&[&frames[0][..], &frames[1][..3]].concat())
+            .unwrap();
+        let started = time::Instant::now();
+        handle.read_bulk(&mut received, TIMEOUT).unwrap();
+        assert_eq!(received, frames[0]);
+        assert!(started.elapsed() < RESYNC_GAP, "{:?}", started.elapsed());
+
+        master.write_all(&frames[1][3..]).unwrap();
+        handle.read_bulk(&mut received, TIMEOUT).unwrap();
+        assert_eq!(received, frames[1]);
+    }
+
+    #[test]
+    fn a_frame_cut_short_is_dropped_when_whole_frames_follow() {
+        let (mut master, _slave, handle) = open_pty();
+        let frames = [[1, 2, 3, 4, 5, 6, 7, 8], [0x10, 0, 0, 0, 0, 0, 0, 1]];
+        let mut received = [0; FRAME_SIZE];
+        master.write_all(&frames[0]).unwrap();
+        handle.read_bulk(&mut received, TIMEOUT).unwrap();
+
+        // The last 3 bytes of this frame are lost.
+        master.write_all(&[0x20, 0x60, 1, 0, 0]).unwrap();
+        let short = time::Duration::from_millis(5);
+        assert!(handle
+            .read_bulk(&mut received, short)
+            .unwrap_err()
+            .is_timeout());
+        master.write_all(&frames[1]).unwrap();
+        handle.read_bulk(&mut received, TIMEOUT).unwrap();
+        assert_eq!(received, frames[1]);
+    }
+}
@@ -0,0 +1,257 @@
+//! Implementation of a SocketCAN backend for Linux CAN interfaces.
+
+use crate::HandleTrait;
+use std::ffi::CString;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::{io, mem, time};
+
+/// Conversion between the library's 8-byte frames and CAN frames.
+///
+/// The USB adapter performs this conversion in its firmware. When talking to the bus
+/// directly, a mapping decides which CAN identifier an outbound frame is sent with and
+/// how a received CAN frame is turned back into the layout the device modules decode.
+pub trait IdMapping {
+    /// Returns the CAN identifier used to transmit `frame`.
+    fn outbound_id(&self, frame: &[u8]) -> u32;
+    /// Rebuilds a library frame from a received CAN frame, or `None` to ignore it.
+    fn inbound_frame(&self, can_id: u32, data: &[u8]) -> Option<[u8; 8]>;
+}
+
+/// The default mapping, mirroring the USB adapter.
+///
+/// Outbound frames are sent unchanged with the CAN identifier `base + frame[0]`.
+/// Inbound frames from BLMD controllers (`0x201`–`0x208`) are rewritten so that the
+/// first two bytes hold the standard identifier, as `blmd::receive_status` expects;
+/// every other frame is passed through unchanged.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AddressIdMapping {
+    pub base: u32,
+}
+
+impl IdMapping for AddressIdMapping {
+    fn outbound_id(&self, frame: &[u8]) -> u32 {
+        self.base + frame.first().copied().unwrap_or(0) as u32
+    }
+
+    fn inbound_frame(&self, can_id: u32, data: &[u8]) -> Option<[u8; 8]> {
+        let mut frame = [0; 8];
+        if (0x201..=0x208).contains(&can_id) {
+            frame[0] = (can_id >> 8) as u8;
+            frame[1] = can_id as u8;
+            let len = data.len().min(6);
+            frame[2..2 + len].copy_from_slice(&data[..len]);
+        } else {
+            let len = data.len().min(8);
+            frame[..len].copy_from_slice(&data[..len]);
+        }
+        Some(frame)
+    }
+}
+
+/// A handle to read and write a Linux SocketCAN interface.
+pub struct SocketCanHandle<M: IdMapping = AddressIdMapping> {
+    socket: OwnedFd,
+    mapping: M,
+}
+
+impl SocketCanHandle {
+    /// Opens the CAN interface `interface` (for example `can0` or `vcan0`) with the default ID mapping.
+    ///
+    /// # Example
+    ///
+    /// Sample code to rotate a motor connected to the MD at address 0x00 through `can0`.
+    /// ```rust,no_run
+    /// use motor_lib::{md, Error, SocketCanHandle};
+    /// fn main() -> Result<(), Error> {
+    ///     let handle = SocketCanHandle::new("can0")?;
+    ///     md::send_pwm(&handle, 0x00, 1000)?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn new(interface: &str) -> Result<Self, crate::Error> {
+        Self::with_mapping(interface, AddressIdMapping::default())
+    }
+}
+
+impl<M: IdMapping> SocketCanHandle<M> {
+    /// Opens the CAN interface `interface` with a custom ID mapping.
+    pub fn with_mapping(interface: &str, mapping: M) -> Result<Self, crate::Error> {
+        let name =
+            CString::new(interface).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let ifindex = unsafe { libc::if_nametoindex(name.as_ptr()) };
+        if ifindex == 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+
+        let fd = unsafe {
+            libc::socket(
+                libc::PF_CAN,
+                libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+                libc::CAN_RAW,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        let socket = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        let mut address: libc::sockaddr_can = unsafe { mem::zeroed() };
+        address.can_family = libc::AF_CAN as libc::sa_family_t;
+        address.can_ifindex = ifindex as libc::c_int;
+        let result = unsafe {
+            libc::bind(
+                socket.as_raw_fd(),
+                &address as *const libc::sockaddr_can as *const libc::sockaddr,
+                mem::size_of::<libc::sockaddr_can>() as libc::socklen_t,
+            )
+        };
+        if result < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+
+        Ok(Self { socket, mapping })
+    }
+
+    fn wait(&self, events: libc::c_short, timeout: time::Duration) -> Result<(), crate::Error> {
+        let mut pollfd = libc::pollfd {
+            fd: self.socket.as_raw_fd(),
+            events,
+            revents: 0,
+        };
+        let timeout_ms = timeout.as_millis().min(libc::c_int::MAX as u128) as libc::c_int;
+        match unsafe { libc::poll(&mut pollfd, 1, timeout_ms) } {
+            n if n < 0 => Err(io::Error::last_os_error().into()),
+            0 => Err(io::Error::from(io::ErrorKind::TimedOut).into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl<M: IdMapping> HandleTrait for SocketCanHandle<M> {
+    fn read_bulk(&self, data: &mut [u8], timeout: time::Duration) -> Result<usize, crate::Error> {
+        let deadline = time::Instant::now() + timeout;
+        loop {
+            self.wait(
+                libc::POLLIN,
+                deadline.saturating_duration_since(time::Instant::now()),
+            )?;
+            let mut can_frame: libc::can_frame = unsafe { mem::zeroed() };
+            let size = unsafe {
+                libc::read(
+                    self.socket.as_raw_fd(),
+                    &mut can_frame as *mut libc::can_frame as *mut libc::c_void,
+                    mem::size_of::<libc::can_frame>(),
+                )
+            };
+            if size < 0 {
+                return Err(io::Error::last_os_error().into());
+            }
+            if can_frame.can_id & (libc::CAN_ERR_FLAG | libc::CAN_RTR_FLAG) != 0 {
+                continue;
+            }
+            let can_id = if can_frame.can_id & libc::CAN_EFF_FLAG != 0 {
+                can_frame.can_id & libc::CAN_EFF_MASK
+            } else {
+                can_frame.can_id & libc::CAN_SFF_MASK
+            };
+            let dlc = (can_frame.can_dlc as usize).min(libc::CAN_MAX_DLEN);
+            if let Some(frame) = self.mapping.inbound_frame(can_id, &can_frame.data[..dlc]) {
+                let len = data.len().min(frame.len());
+                data[..len].copy_from_slice(&frame[..len]);
+                return Ok(len);
+            }
+        }
+    }
+
+    fn write_bulk(&self, data: &[u8], timeout: time::Duration) -> Result<usize, crate::Error> {
+        let can_id = self.mapping.outbound_id(data);
+        let len = data.len().min(libc::CAN_MAX_DLEN);
+        let mut can_frame: libc::can_frame = unsafe { mem::zeroed() };
+        can_frame.can_id = if can_id > libc::CAN_SFF_MASK {
+            (can_id & libc::CAN_EFF_MASK) | libc::CAN_EFF_FLAG
+        } else {
+            can_id
+        };
+        can_frame.can_dlc = len as u8;
+        can_frame.data[..len].copy_from_slice(&data[..len]);
+
+        self.wait(libc::POLLOUT, timeout)?;
+        let size = unsafe {
+            libc::write(
+                self.socket.as_raw_fd(),
+                &can_frame as *const libc::can_frame as *const libc::c_void,
+                mem::size_of::<libc::can_frame>(),
+            )
+        };
+        if size < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn outbound_id_is_base_plus_address() {
+        let mapping = AddressIdMapping { base: 0x100 };
+        assert_eq!(mapping.outbound_id(&[0x12, 0x60, 2, 0, 0, 0, 0, 0]), 0x112);
+        assert_eq!(mapping.outbound_id(&[]), 0x100);
+        assert_eq!(AddressIdMapping::default().outbound_id(&[0x30, 1]), 0x30);
+    }
+
+    #[test]
+    fn blmd_statuses_get_their_id_in_front() {
+        let mapping = AddressIdMapping::default();
+        assert_eq!(
+            mapping.inbound_frame(0x203, &[1, 2, 3, 4, 5, 6, 7, 8]),
+            Some([0x02, 0x03, 1, 2, 3, 4, 5, 6])
+        );
+        assert_eq!(
+            mapping.inbound_frame(0x208, &[1, 2]),
+            Some([0x02, 0x08, 1, 2, 0, 0, 0, 0])
+        );
+    }
+
+    #[test]
+    fn other_frames_pass_through() {
+        let mapping = AddressIdMapping::default();
+        assert_eq!(
+            mapping.inbound_frame(0x01, &[1, 0x60, 0, 90, 0, 100, 0, 0]),
+            Some([1, 0x60, 0, 90, 0, 100, 0, 0])
+        );
+        assert_eq!(
+            mapping.inbound_frame(0x209, &[9, 9]),
+            Some([9, 9, 0, 0, 0, 0, 0, 0])
+        );
+    }
+
+    /// Needs a virtual CAN interface:
+    /// `ip link add dev vcan0 type vcan && ip link set up vcan0`.
+    #[test]
+    #[ignore]
+    fn frames_cross_a_vcan_interface() {
+        let timeout = time::Duration::from_millis(500);
+        let receiver = SocketCanHandle::new("vcan0").unwrap();
+        let sender = SocketCanHandle::new("vcan0").unwrap();
+        let frame = [0x01, 0x60, 0x02, 0, 0x03, 0xe8, 0, 0];
+        assert_eq!(sender.write_bulk(&frame, timeout).unwrap(), 8);
+        let mut received = [0; 8];
+        assert_eq!(receiver.read_bulk(&mut received, timeout).unwrap(), 8);
+        assert_eq!(received, frame);
+
+        let blmd =
+            SocketCanHandle::with_mapping("vcan0", AddressIdMapping { base: 0x200 }).unwrap();
+        blmd.write_bulk(&[0x03, 0, 0, 0x10, 0, 0x20, 0, 0], timeout)
+            .unwrap();
+        receiver.read_bulk(&mut received, timeout).unwrap();
+        assert_eq!(received, [0x02, 0x03, 0x03, 0, 0, 0x10, 0, 0x20]);
+
+        assert!(receiver
+            .read_bulk(&mut received, timeout)
+            .unwrap_err()
+            .is_timeout());
+    }
+}
@@ -1,15 +1,39 @@
 //! This library provides an interface for controlling various motor devices via USB.
 use std::{fmt, time::Duration};
 
+pub mod actuator;
 pub mod blmd;
+pub mod calibration;
+pub mod cli;
+pub mod device;
 pub mod device_type;
+pub mod discovery;
+pub mod frame;
 mod implements;
 pub mod md;
+pub mod monitor;
+pub mod robot;
+pub mod safety;
+pub mod scheduler;
 pub mod sd;
 pub mod smd;
 pub mod sr;
 pub use implements::grpc;
+pub use implements::grpc::GrpcClient;
 pub use implements::grpc::GrpcHandle;
+pub use implements::grpc::GrpcStreamHandle;
+pub use implements::recording;
+pub use implements::recording::RecordingHandle;
+pub use implements::replay;
+pub use implements::replay::ReplayHandle;
+#[cfg(unix)]
+pub use implements::serial;
+#[cfg(unix)]
+pub use implements::serial::SerialHandle;
+#[cfg(target_os = "linux")]
+pub use implements::socketcan;
+#[cfg(target_os = "linux")]
+pub use implements::socketcan::SocketCanHandle;
 pub use implements::usb;
 pub use implements::usb::USBHandle;
 
@@ -17,6 +41,30 @@ pub use implements::usb::USBHandle;
 pub enum Error {
     RUsbError(rusb::Error),
     GrpcError(tonic::Status),
+    IoError(std::io::Error),
+    InvalidAddress(u8),
+    InvalidPort(u8),
+    Unsupported(&'static str),
+    ConfigError(String),
+    LimitExceeded(String),
+    Fault(String),
+}
+
+impl Error {
+    /// Returns true if the error means that no frame arrived or could be sent in time.
+    pub fn is_timeout(&self) -> bool {
+        match self {
+            crate::Error::RUsbError(e) => *e == rusb::Error::Timeout,
+            crate::Error::GrpcError(e) => e.code() == tonic::Code::DeadlineExceeded,
+            crate::Error::IoError(e) => e.kind() == std::io::ErrorKind::TimedOut,
+            crate::Error::InvalidAddress(_)
+            | crate::Error::InvalidPort(_)
+            | crate::Error::Unsupported(_)
+            | crate::Error::ConfigError(_)
+            | crate::Error::LimitExceeded(_)
+            | crate::Error::Fault(_) => false,
+        }
+    }
 }
 
 impl fmt::Display for crate::Error {
@@ -24,16 +72,39 @@ impl fmt::Display for crate::Error {
         match self {
             crate::Error::RUsbError(e) => write!(f, "RUsbError: {}", e),
             crate::Error::GrpcError(e) => write!(f, "gRPCError: {}", e),
+            crate::Error::IoError(e) => write!(f, "IoError: {}", e),
+            crate::Error::InvalidAddress(a) => write!(f, "InvalidAddress: 0x{:02x}", a),
+            crate::Error::InvalidPort(p) => write!(f, "InvalidPort: {}", p),
+            crate::Error::Unsupported(c) => write!(f, "Unsupported: {}", c),
+            crate::Error::ConfigError(e) => write!(f, "ConfigError: {}", e),
+            crate::Error::LimitExceeded(e) => write!(f, "LimitExceeded: {}", e),
+            crate::Error::Fault(e) => write!(f, "Fault: {}", e),
         }
     }
 }
 
+impl From<std::io::Error> for crate::Error {
+    fn from(error: std::io::Error) -> Self {
+        crate::Error::IoError(error)
+    }
+}
+
 /// A trait defining the interface for USB handle operations.
 pub trait HandleTrait {
     fn read_bulk(&self, data: &mut [u8], timeout: Duration) -> Result<usize, Error>;
     fn write_bulk(&self, data: &[u8], timeout: Duration) -> Result<usize, Error>;
 }
 
+impl<H: HandleTrait + ?Sized> HandleTrait for Box<H> {
+    fn read_bulk(&self, data: &mut [u8], timeout: Duration) -> Result<usize, Error> {
+        (**self).read_bulk(data, timeout)
+    }
+
+    fn write_bulk(&self, data: &[u8], timeout: Duration) -> Result<usize, Error> {
+        (**self).write_bulk(data, timeout)
+    }
+}
+
 /// Sends an emergency signal to the drobo CAN device (for example, MD, SD, etc.)   
 /// It's not possible to confirm whether the signal was sent properly, and this function always returns nothing.
 ///
@@ -13,13 +13,13 @@ pub mod mode {
     pub const LIM_SW: u8 = 5;
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub struct LimSwStatus {
     pub limsw_0: bool,
     pub limsw_1: bool,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub struct MdStatus {
     pub address: u8,
     pub semi_id: u8,
@@ -214,6 +214,45 @@ pub fn send_limsw(
     return receive_status(handle, address);
 }
 
+/// Requests the status of the specified MD device without changing its output.
+///
+/// # Arguments
+///
+/// * `handle` - A reference to an object implementing the USBHandleTrait.
+/// * `address` - The address of the MD device.
+///
+/// # Returns
+///
+/// A result containing the number of bytes written or a Error.
+/// The reply can be read with `receive_status`.
+///
+/// # Example
+///
+/// Sample code to request and retrieve status data from the MD at address 0x00.
+/// ```rust,no_run
+/// use motor_lib::{USBHandle, Error, md};
+/// fn main() -> Result<(), Error> {
+///     let handle = USBHandle::new(0x483, 0x5740, 1);
+///     md::request_status(&handle, 0x00)?;
+///     let status = md::receive_status(&handle, 0x00)?;
+///     println!("{:?}", status);
+///     Ok(())
+/// }
+/// ```
+pub fn request_status(handle: &impl HandleTrait, address: u8) -> Result<usize, crate::Error> {
+    let send_buf: [u8; 8] = [
+        address,
+        device_type::MASTER,
+        mode::STATUS,
+        0,
+        0,
+        0,
+        0,
+        0,
+    ];
+    handle.write_bulk(&send_buf, Duration::from_millis(5000))
+}
+
 /// Receive a data from the specified MD device.
 ///
 /// # Arguments
@@ -246,17 +285,30 @@ pub fn receive_status(handle: &impl HandleTrait, address: u8) -> Result<MdStatus
     loop {
         handle.read_bulk(&mut receive_buf, Duration::from_millis(5000))?;
         if address == receive_buf[0] {
-            let rpm = (receive_buf[4] as i16) << 8 | (receive_buf[5] as i16);
-            return Ok(MdStatus {
-                address: receive_buf[0],
-                semi_id: receive_buf[1],
-                angle: ((receive_buf[2] as i16) << 8 | (receive_buf[3] as i16)),
-                speed: rpm,
-                limsw: LimSwStatus {
-                    limsw_0: receive_buf[6] == 1,
-                    limsw_1: receive_buf[7] == 1,
-                },
-            });
+            return Ok(decode_status(&receive_buf));
         }
     }
 }
+
+/// Decodes a status frame received from an MD device.
+///
+/// # Arguments
+///
+/// * `receive_buf` - The frame received from the MD device.
+///
+/// # Returns
+///
+/// The status of the MD device.
+pub fn decode_status(receive_buf: &[u8; 8]) -> MdStatus {
+    let rpm = (receive_buf[4] as i16) << 8 | (receive_buf[5] as i16);
+    MdStatus {
+        address: receive_buf[0],
+        semi_id: receive_buf[1],
+        angle: ((receive_buf[2] as i16) << 8 | (receive_buf[3] as i16)),
+        speed: rpm,
+        limsw: LimSwStatus {
+            limsw_0: receive_buf[6] == 1,
+            limsw_1: receive_buf[7] == 1,
+        },
+    }
+}
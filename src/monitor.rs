@@ -0,0 +1,503 @@
+//! Stall and over-current detection from the status of a motor.
+//!
+//! A `Monitor` compares each status with the last command. It reports a stall when a
+//! large PWM duty cycle, velocity or current is commanded while the measured speed stays
+//! near zero for `stall_time`, and an over-current when the measured current stays above
+//! `max_current` for `overcurrent_time`. Position commands are not checked for stalls,
+//! since a motor holding its position stands still. A `Monitored` motor runs the monitor on every
+//! status it receives, calls an event handler, and can cut the output on a fault.
+//!
+//! ```rust,no_run
+//! use std::time::Duration;
+//! use motor_lib::actuator::Motor;
+//! use motor_lib::device::Md;
+//! use motor_lib::monitor::{Monitored, MonitorConfig};
+//! use motor_lib::{Error, USBHandle};
+//! fn main() -> Result<(), Error> {
+//!     let handle = USBHandle::new(0x483, 0x5740, 1);
+//!     let config = MonitorConfig {
+//!         stall_duty: Some(600),
+//!         stall_speed: 5,
+//!         stall_time: Duration::from_millis(500),
+//!         cut_output: true,
+//!         ..MonitorConfig::default()
+//!     };
+//!     let mut motor = Monitored::new(Md::new(&handle, 0x00)?, config);
+//!     motor.on_event(|event| eprintln!("motor 0x00: {}", event));
+//!     loop {
+//!         motor.set_duty(800)?;
+//!         std::thread::sleep(Duration::from_millis(10));
+//!     }
+//! }
+//! ```
+
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use crate::actuator::{Motor, MotorStatus};
+
+/// A command sent to a motor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Output {
+    Duty(i16),
+    Velocity(i16),
+    Position(i16),
+    Current(i16),
+}
+
+/// A fault found by a `Monitor`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    /// The motor has been driven hard without turning for `duration`.
+    Stall { duration: Duration },
+    /// The measured current has stayed above the limit for `duration`.
+    Overcurrent { current: i16, duration: Duration },
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Event::Stall { duration } => write!(f, "stalled for {:?}", duration),
+            Event::Overcurrent { current, duration } => {
+                write!(f, "current {} for {:?}", current, duration)
+            }
+        }
+    }
+}
+
+/// The thresholds of a `Monitor`, in raw device units.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MonitorConfig {
+    /// The smallest absolute PWM duty cycle that counts as driving hard. `None` disables
+    /// stall detection for duty cycle commands.
+    pub stall_duty: Option<i16>,
+    /// The smallest absolute current command that counts as driving hard. `None` disables
+    /// stall detection for current commands.
+    pub stall_current: Option<i16>,
+    /// The smallest absolute velocity command that counts as driving hard. `None` disables
+    /// stall detection for velocity commands.
+    pub stall_velocity: Option<i16>,
+    /// The largest absolute measured speed that counts as not turning.
+    pub stall_speed: i16,
+    pub stall_time: Duration,
+    /// The largest absolute measured current allowed. `None` disables over-current detection.
+    pub max_current: Option<i16>,
+    pub overcurrent_time: Duration,
+    /// Sends a zero output on a fault and refuses further commands until `reset`.
+    pub cut_output: bool,
+}
+
+impl Default for MonitorConfig {
+    fn default() -> Self {
+        Self {
+            stall_duty: None,
+            stall_current: None,
+            stall_velocity: None,
+            stall_speed: 0,
+            stall_time: Duration::from_millis(500),
+            max_current: None,
+            overcurrent_time: Duration::from_millis(500),
+            cut_output: false,
+        }
+    }
+}
+
+/// Detects stalls and over-currents from a sequence of statuses.
+#[derive(Debug, Clone)]
+pub struct Monitor {
+    config: MonitorConfig,
+    stall_since: Option<Instant>,
+    stall_reported: bool,
+    overcurrent_since: Option<Instant>,
+    overcurrent_reported: bool,
+}
+
+impl Monitor {
+    pub fn new(config: MonitorConfig) -> Self {
+        Self {
+            config,
+            stall_since: None,
+            stall_reported: false,
+            overcurrent_since: None,
+            overcurrent_reported: false,
+        }
+    }
+
+    pub fn config(&self) -> &MonitorConfig {
+        &self.config
+    }
+
+    /// Forgets the conditions seen so far.
+    pub fn reset(&mut self) {
+        *self = Self::new(self.config);
+    }
+
+    fn driving_hard(&self, output: Option<Output>) -> bool {
+        let exceeds = |value: i16, threshold: Option<i16>| {
+            threshold.is_some_and(|t| value.unsigned_abs() >= t.unsigned_abs())
+        };
+        match output {
+            Some(Output::Duty(duty)) => exceeds(duty, self.config.stall_duty),
+            Some(Output::Current(current)) => exceeds(current, self.config.stall_current),
+            Some(Output::Velocity(velocity)) => exceeds(velocity, self.config.stall_velocity),
+            Some(Output::Position(_)) | None => false,
+        }
+    }
+
+    /// Checks `status`, received at `now` while `output` was commanded.
+    ///
+    /// # Returns
+    ///
+    /// The fault found, once per occurrence: a condition is reported again only after it
+    /// has cleared.
+    pub fn update(
+        &mut self,
+        output: Option<Output>,
+        status: &MotorStatus,
+        now: Instant,
+    ) -> Option<Event> {
+        let stalled = self.driving_hard(output)
+            && status.speed.unsigned_abs() <= self.config.stall_speed.unsigned_abs();
+        let overcurrent = match (status.current, self.config.max_current) {
+            (Some(current), Some(max)) => current.unsigned_abs() > max.unsigned_abs(),
+            _ => false,
+        };
+
+        let stall = check(
+            stalled,
+            now,
+            self.config.stall_time,
+            &mut self.stall_since,
+            &mut self.stall_reported,
+        )
+        .map(|duration| Event::Stall { duration });
+        let overcurrent = check(
+            overcurrent,
+            now,
+            self.config.overcurrent_time,
+            &mut self.overcurrent_since,
+            &mut self.overcurrent_reported,
+        )
+        .map(|duration| Event::Overcurrent {
+            current: status.current.unwrap_or_default(),
+            duration,
+        });
+        overcurrent.or(stall)
+    }
+}
+
+/// Tracks how long a condition has held, and returns its duration once it reaches `time`.
+fn check(
+    condition: bool,
+    now: Instant,
+    time: Duration,
+    since: &mut Option<Instant>,
+    reported: &mut bool,
+) -> Option<Duration> {
+    if !condition {
+        *since = None;
+        *reported = false;
+        return None;
+    }
+    let duration = now.saturating_duration_since(*since.get_or_insert(now));
+    if duration >= time && !*reported {
+        *reported = true;
+        Some(duration)
+    } else {
+        None
+    }
+}
+
+type Handler<'a> = Box<dyn FnMut(&Event) + 'a>;
+
+/// A motor whose statuses are checked by a `Monitor`.
+pub struct Monitored<'a, M: Motor> {
+    motor: M,
+    monitor: Monitor,
+    output: Option<Output>,
+    fault: Option<Event>,
+    handler: Option<Handler<'a>>,
+}
+
+impl<'a, M: Motor> Monitored<'a, M> {
+    pub fn new(motor: M, config: MonitorConfig) -> Self {
+        Self {
+            motor,
+            monitor: Monitor::new(config),
+            output: None,
+            fault: None,
+            handler: None,
+        }
+    }
+
+    /// Calls `handler` with every fault found.
+    pub fn on_event(&mut self, handler: impl FnMut(&Event) + 'a) {
+        self.handler = Some(Box::new(handler));
+    }
+
+    /// Returns the fault that cut the output, if any.
+    pub fn fault(&self) -> Option<&Event> {
+        self.fault.as_ref()
+    }
+
+    /// Clears the fault and allows commands again.
+    pub fn reset(&mut self) {
+        self.fault = None;
+        self.monitor.reset();
+    }
+
+    /// Returns the wrapped motor, for commands that bypass the monitor.
+    pub fn inner(&mut self) -> &mut M {
+        &mut self.motor
+    }
+
+    /// Checks a status received outside of the commands of this object, for example by polling.
+    pub fn observe(&mut self, status: &MotorStatus) -> Result<(), crate::Error> {
+        let Some(event) = self.monitor.update(self.output, status, Instant::now()) else {
+            return Ok(());
+        };
+        if let Some(handler) = &mut self.handler {
+            handler(&event);
+        }
+        if self.monitor.config().cut_output && self.fault.is_none() {
+            self.fault = Some(event);
+            let result = match self.output {
+                Some(Output::Duty(_)) => self.motor.set_duty(0),
+                None => self
+                    .motor
+                    .set_duty(0)
+                    .or_else(|_| self.motor.set_current(0)),
+                Some(Output::Velocity(_)) => self.motor.set_velocity(0),
+                Some(Output::Current(_)) => self.motor.set_current(0),
+                // Holding the current position is the safest output for a position command.
+                // Without a known position, the output is zeroed instead.
+                Some(Output::Position(_)) => match self.motor.status() {
+                    Some(status) => self.motor.set_position(status.angle),
+                    None => self
+                        .motor
+                        .set_duty(0)
+                        .or_else(|_| self.motor.set_current(0)),
+                },
+            };
+            result?;
+        }
+        Ok(())
+    }
+
+    fn send(
+        &mut self,
+        output: Output,
+        send: impl FnOnce(&mut M) -> Result<MotorStatus, crate::Error>,
+    ) -> Result<MotorStatus, crate::Error> {
+        if let Some(fault) = &self.fault {
+            return Err(crate::Error::Fault(fault.to_string()));
+        }
+        let status = send(&mut self.motor)?;
+        self.output = Some(output);
+        self.observe(&status)?;
+        Ok(status)
+    }
+}
+
+impl<M: Motor> Motor for Monitored<'_, M> {
+    fn set_duty(&mut self, duty: i16) -> Result<MotorStatus, crate::Error> {
+        self.send(Output::Duty(duty), |motor| motor.set_duty(duty))
+    }
+
+    fn set_velocity(&mut self, velocity: i16) -> Result<MotorStatus, crate::Error> {
+        self.send(Output::Velocity(velocity), |motor| {
+            motor.set_velocity(velocity)
+        })
+    }
+
+    fn set_position(&mut self, position: i16) -> Result<MotorStatus, crate::Error> {
+        self.send(Output::Position(position), |motor| {
+            motor.set_position(position)
+        })
+    }
+
+    fn set_current(&mut self, current: i16) -> Result<MotorStatus, crate::Error> {
+        self.send(Output::Current(current), |motor| motor.set_current(current))
+    }
+
+    fn status(&self) -> Option<MotorStatus> {
+        self.motor.status()
+    }
+
+    fn read_status(&mut self) -> Result<MotorStatus, crate::Error> {
+        let status = self.motor.read_status()?;
+        self.observe(&status)?;
+        Ok(status)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status(speed: i16, current: Option<i16>) -> MotorStatus {
+        MotorStatus {
+            angle: 0,
+            speed,
+            current,
+            limsw: None,
+        }
+    }
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn stall_is_reported_once_after_its_time() {
+        let mut monitor = Monitor::new(MonitorConfig {
+            stall_duty: Some(600),
+            stall_speed: 5,
+            stall_time: ms(100),
+            ..MonitorConfig::default()
+        });
+        let start = Instant::now();
+        let output = Some(Output::Duty(-800));
+        assert_eq!(monitor.update(output, &status(3, None), start), None);
+        assert_eq!(
+            monitor.update(output, &status(-3, None), start + ms(100)),
+            Some(Event::Stall { duration: ms(100) })
+        );
+        assert_eq!(
+            monitor.update(output, &status(0, None), start + ms(200)),
+            None
+        );
+        // Turning clears the condition, so the next stall is reported again.
+        assert_eq!(
+            monitor.update(output, &status(50, None), start + ms(300)),
+            None
+        );
+        assert_eq!(
+            monitor.update(output, &status(0, None), start + ms(400)),
+            None
+        );
+        assert!(monitor
+            .update(output, &status(0, None), start + ms(500))
+            .is_some());
+    }
+
+    #[test]
+    fn light_and_position_commands_do_not_stall() {
+        let mut monitor = Monitor::new(MonitorConfig {
+            stall_duty: Some(600),
+            stall_velocity: Some(100),
+            stall_time: Duration::ZERO,
+            ..MonitorConfig::default()
+        });
+        let now = Instant::now();
+        assert_eq!(
+            monitor.update(Some(Output::Duty(500)), &status(0, None), now),
+            None
+        );
+        assert_eq!(
+            monitor.update(Some(Output::Position(90)), &status(0, None), now),
+            None
+        );
+        assert!(monitor
+            .update(Some(Output::Velocity(-100)), &status(0, None), now)
+            .is_some());
+    }
+
+    #[test]
+    fn overcurrent_wins_over_stall() {
+        let mut monitor = Monitor::new(MonitorConfig {
+            stall_current: Some(1000),
+            stall_time: Duration::ZERO,
+            max_current: Some(2000),
+            overcurrent_time: Duration::ZERO,
+            ..MonitorConfig::default()
+        });
+        let event = monitor.update(
+            Some(Output::Current(3000)),
+            &status(0, Some(-2500)),
+            Instant::now(),
+        );
+        assert_eq!(
+            event,
+            Some(Event::Overcurrent {
+                current: -2500,
+                duration: Duration::ZERO
+            })
+        );
+    }
+
+    /// A motor in position mode that has never reported its status.
+    #[derive(Default)]
+    struct Blind {
+        sent: Vec<Output>,
+    }
+
+    impl Motor for Blind {
+        fn set_duty(&mut self, duty: i16) -> Result<MotorStatus, crate::Error> {
+            self.sent.push(Output::Duty(duty));
+            Ok(status(0, None))
+        }
+
+        fn set_velocity(&mut self, velocity: i16) -> Result<MotorStatus, crate::Error> {
+            self.sent.push(Output::Velocity(velocity));
+            Ok(status(0, None))
+        }
+
+        fn set_position(&mut self, position: i16) -> Result<MotorStatus, crate::Error> {
+            self.sent.push(Output::Position(position));
+            Ok(status(0, None))
+        }
+
+        fn set_current(&mut self, current: i16) -> Result<MotorStatus, crate::Error> {
+            self.sent.push(Output::Current(current));
+            Ok(status(0, None))
+        }
+
+        fn status(&self) -> Option<MotorStatus> {
+            None
+        }
+
+        fn read_status(&mut self) -> Result<MotorStatus, crate::Error> {
+            Err(crate::Error::Unsupported("no status"))
+        }
+    }
+
+    #[test]
+    fn cut_output_stops_a_motor_without_status() {
+        let mut motor = Monitored::new(
+            Blind::default(),
+            MonitorConfig {
+                stall_velocity: Some(10),
+                stall_time: Duration::ZERO,
+                cut_output: true,
+                ..MonitorConfig::default()
+            },
+        );
+        motor.set_position(90).unwrap();
+        motor.observe(&status(0, Some(9000))).unwrap();
+        assert!(motor.fault().is_none());
+
+        motor.set_velocity(20).unwrap();
+        assert!(motor.fault().is_some());
+        assert!(motor.set_velocity(20).is_err());
+        assert_eq!(
+            motor.inner().sent,
+            [
+                Output::Position(90),
+                Output::Velocity(20),
+                Output::Velocity(0)
+            ]
+        );
+
+        let monitor = MonitorConfig {
+            max_current: Some(100),
+            overcurrent_time: Duration::ZERO,
+            cut_output: true,
+            ..MonitorConfig::default()
+        };
+        let mut motor = Monitored::new(Blind::default(), monitor);
+        motor.set_position(90).unwrap();
+        motor.observe(&status(0, Some(200))).unwrap();
+        assert_eq!(motor.inner().sent, [Output::Position(90), Output::Duty(0)]);
+    }
+}
@@ -0,0 +1,515 @@
+//! A registry of the devices of a robot, loaded from a configuration file.
+//!
+//! The configuration names each device and gives its type and address, so that mechanism
+//! code asks for `robot.motor("left_front")` instead of hard-coding addresses. Files ending
+//! in `.json` are read as JSON, every other file as TOML. Motors and servos are handed out
+//! wrapped in their `Calibration` and `SafetyLimits`, so they are commanded in physical
+//! units and cannot be driven past their limits.
+//!
+//! ```toml
+//! [transport]
+//! type = "usb"              # or: type = "grpc", url = "http://127.0.0.1:50051", bus = "front"
+//! vendor_id = 0x483
+//! product_id = 0x5740
+//! interface = 1
+//!
+//! [devices.left_front]
+//! type = "md"
+//! address = 0x00
+//! gear_ratio = 19.2
+//! inverted = true
+//! wheel_radius = 0.05
+//! limits = { max_velocity = 1.5, max_duty = 800 }
+//!
+//! [devices.arm]
+//! type = "blmd"
+//! address = 0x30
+//! controller_id = 2
+//!
+//! [devices.hand]
+//! type = "smd"
+//! address = 0x20
+//! port = 1
+//! limits = { min_position = -1.57, max_position = 1.57, action = "reject" }
+//! ```
+
+use std::collections::btree_map::Entry;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use serde::{Deserialize, Deserializer};
+
+use crate::actuator::Motor;
+use crate::calibration::{Calibrated, Calibration, PhysicalLimits, RawUnits};
+use crate::device::{BlMd, Md, Sd, SdPort, Smd, SmdPort};
+use crate::safety::{Limited, SafetyLimits};
+use crate::{cli, device_type, GrpcHandle, HandleTrait, USBHandle};
+
+/// The contents of a robot configuration file.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RobotConfig {
+    pub transport: TransportConfig,
+    #[serde(default)]
+    pub devices: BTreeMap<String, DeviceConfig>,
+}
+
+/// How the robot reaches the bus.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum TransportConfig {
+    Usb {
+        #[serde(default = "default_vendor_id")]
+        vendor_id: u16,
+        #[serde(default = "default_product_id")]
+        product_id: u16,
+        #[serde(default = "default_interface")]
+        interface: u8,
+    },
+    Grpc {
+        url: String,
+        /// The bus of a server with several USB adapters.
+        #[serde(default)]
+        bus: Option<String>,
+    },
+}
+
+fn default_vendor_id() -> u16 {
+    cli::VENDOR_ID
+}
+
+fn default_product_id() -> u16 {
+    cli::PRODUCT_ID
+}
+
+fn default_interface() -> u8 {
+    cli::INTERFACE
+}
+
+impl TransportConfig {
+    /// Opens the transport.
+    pub fn open(&self) -> Result<Box<dyn HandleTrait>, crate::Error> {
+        Ok(match self {
+            TransportConfig::Usb {
+                vendor_id,
+                product_id,
+                interface,
+            } => Box::new(USBHandle::new(*vendor_id, *product_id, *interface)),
+            TransportConfig::Grpc { url, bus: None } => Box::new(GrpcHandle::new(url)),
+            TransportConfig::Grpc {
+                url,
+                bus: Some(bus),
+            } => Box::new(GrpcHandle::with_bus(url, bus)?),
+        })
+    }
+}
+
+/// A named device of the robot.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DeviceConfig {
+    /// One of the `device_type` constants, written by name (`"md"`, `"blmd"`, `"smd"` or `"sd"`).
+    #[serde(rename = "type", deserialize_with = "deserialize_device_type")]
+    pub device_type: u8,
+    pub address: u8,
+    /// The BLMD controller ID, required for BLMD devices.
+    pub controller_id: Option<u8>,
+    /// The SMD or SD port, required for SMD and SD devices.
+    pub port: Option<u8>,
+    /// The number of motor turns per output turn.
+    #[serde(default = "default_gear_ratio")]
+    pub gear_ratio: f64,
+    /// Reverses the direction of the device.
+    #[serde(default)]
+    pub inverted: bool,
+    /// The output position, in radians, at which the device reports a raw angle of zero.
+    #[serde(default)]
+    pub offset: f64,
+    /// The radius of the wheel on the output, in meters, to command velocities in m/s.
+    pub wheel_radius: Option<f64>,
+    pub limits: Option<LimitsConfig>,
+}
+
+fn default_gear_ratio() -> f64 {
+    1.0
+}
+
+/// The safety limits of a device, in the physical units of its calibration.
+pub type LimitsConfig = PhysicalLimits;
+
+fn deserialize_device_type<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u8, D::Error> {
+    let name = String::deserialize(deserializer)?;
+    match device_type::from_name(&name) {
+        Some(t @ (device_type::MD | device_type::BLMD | device_type::SMD | device_type::SD)) => {
+            Ok(t)
+        }
+        _ => Err(serde::de::Error::custom(format!(
+            "unknown device type `{}`, expected md, blmd, smd or sd",
+            name
+        ))),
+    }
+}
+
+impl DeviceConfig {
+    /// Returns the calibration of the device, or `None` if its values have no physical unit.
+    pub fn calibration(&self) -> Option<Calibration> {
+        Some(Calibration {
+            inverted: self.inverted,
+            gear_ratio: self.gear_ratio,
+            offset: self.offset,
+            wheel_radius: self.wheel_radius,
+            raw: RawUnits::of(self.device_type)?,
+        })
+    }
+}
+
+impl DeviceConfig {
+    /// Returns the safety limits of the device in the raw units of `calibration`.
+    pub fn safety_limits(&self, calibration: &Calibration) -> SafetyLimits {
+        self.limits
+            .map(|limits| calibration.raw_limits(&limits))
+            .unwrap_or_default()
+    }
+}
+
+impl RobotConfig {
+    /// Parses a configuration written in TOML.
+    pub fn from_toml_str(s: &str) -> Result<Self, crate::Error> {
+        toml::from_str(s).map_err(|e| crate::Error::ConfigError(e.to_string()))
+    }
+
+    /// Parses a configuration written in JSON.
+    pub fn from_json_str(s: &str) -> Result<Self, crate::Error> {
+        serde_json::from_str(s).map_err(|e| crate::Error::ConfigError(e.to_string()))
+    }
+
+    /// Reads a configuration file, as JSON if its extension is `.json` and as TOML otherwise.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, crate::Error> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)?;
+        let config = if path.extension().is_some_and(|e| e == "json") {
+            Self::from_json_str(&contents)
+        } else {
+            Self::from_toml_str(&contents)
+        };
+        config.map_err(|e| match e {
+            crate::Error::ConfigError(e) => {
+                crate::Error::ConfigError(format!("{}: {}", path.display(), e))
+            }
+            e => e,
+        })
+    }
+
+    /// Checks that every device has an address of its type and the fields its type needs.
+    pub fn validate(&self) -> Result<(), crate::Error> {
+        for (name, device) in &self.devices {
+            let error =
+                |message: &str| Err(crate::Error::ConfigError(format!("{}: {}", name, message)));
+            if device_type::of(device.address) != device.device_type {
+                return error(&format!(
+                    "address 0x{:02x} is not a {} address",
+                    device.address,
+                    device_type::name(device.device_type).unwrap_or("?")
+                ));
+            }
+            match device.device_type {
+                device_type::BLMD => match device.controller_id {
+                    Some(1..=8) => {}
+                    Some(_) => return error("controller_id must be in 1..=8"),
+                    None => return error("BLMD devices need a controller_id"),
+                },
+                device_type::SMD | device_type::SD => match device.port {
+                    Some(0 | 1) => {}
+                    Some(_) => return error("port must be 0 or 1"),
+                    None => return error("SMD and SD devices need a port"),
+                },
+                _ => {}
+            }
+            if device.gear_ratio == 0.0 || !device.gear_ratio.is_finite() {
+                return error("gear_ratio must be a finite, non-zero number");
+            }
+            if !device.offset.is_finite() {
+                return error("offset must be a finite number");
+            }
+            if device
+                .wheel_radius
+                .is_some_and(|r| !(r.is_finite() && r > 0.0))
+            {
+                return error("wheel_radius must be a positive number");
+            }
+            if let Some(limits) = device.limits {
+                let values = [
+                    limits.min_position,
+                    limits.max_position,
+                    limits.max_velocity,
+                    limits.max_current,
+                ];
+                if values.into_iter().flatten().any(|v| !v.is_finite()) {
+                    return error("limits must be finite numbers");
+                }
+                if let (Some(min), Some(max)) = (limits.min_position, limits.max_position) {
+                    if min > max {
+                        return error("limits.min_position must not exceed limits.max_position");
+                    }
+                }
+                if limits.max_duty.is_some_and(|m| m < 0)
+                    || limits.max_velocity.is_some_and(|m| m < 0.0)
+                    || limits.max_current.is_some_and(|m| m < 0.0)
+                {
+                    return error("limits.max_* must not be negative");
+                }
+                if [limits.positive_limsw, limits.negative_limsw]
+                    .into_iter()
+                    .flatten()
+                    .any(|port| port > 1)
+                {
+                    return error("limit switch ports must be 0 or 1");
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn calibration_of(device: &DeviceConfig) -> Result<Calibration, crate::Error> {
+    device
+        .calibration()
+        .ok_or(crate::Error::Unsupported("device has no calibrated units"))
+}
+
+/// Returns the error for the device `name`, which is missing or not a `what`.
+fn not_a(config: &RobotConfig, name: &str, what: &str) -> crate::Error {
+    match config.devices.get(name) {
+        Some(device) => crate::Error::ConfigError(format!(
+            "`{}` is a {}, not a {}",
+            name,
+            device_type::name(device.device_type).unwrap_or("?"),
+            what
+        )),
+        None => crate::Error::ConfigError(format!("no device named `{}`", name)),
+    }
+}
+
+/// A motor handed out by `Robot::motor`.
+pub type RobotMotor<'h> = Calibrated<Limited<Box<dyn Motor + 'h>>>;
+
+/// A servo handed out by `Robot::servo`, a view of the SMD it is connected to.
+pub type RobotServo<'r, 'h, H> = Calibrated<Limited<SmdPort<'r, 'h, H>>>;
+
+/// A servo of the robot, on one port of an SMD.
+struct Servo {
+    address: u8,
+    port: u8,
+    calibration: Calibration,
+    limits: SafetyLimits,
+}
+
+/// The devices of a robot, bound to the handle of its transport.
+///
+/// Every device is built once, when the robot is created, so the last status each device
+/// received stays available between calls. Servos and solenoids on the two ports of one
+/// SMD or SD share that device and its status.
+pub struct Robot<'h, H: HandleTrait = Box<dyn HandleTrait>> {
+    config: RobotConfig,
+    handle: &'h H,
+    motors: BTreeMap<String, RobotMotor<'h>>,
+    servos: BTreeMap<String, Servo>,
+    /// The SD address and port of each solenoid.
+    solenoids: BTreeMap<String, (u8, u8)>,
+    smds: BTreeMap<u8, Smd<'h, H>>,
+    sds: BTreeMap<u8, Sd<'h, H>>,
+}
+
+impl<'h, H: HandleTrait> Robot<'h, H> {
+    /// Builds the devices of `config` on `handle`, usually the handle opened from
+    /// `config.transport`.
+    ///
+    /// # Example
+    ///
+    /// Sample code to rotate the motor named `left_front` in `robot.toml`.
+    /// ```rust,no_run
+    /// use motor_lib::robot::{Robot, RobotConfig};
+    /// use motor_lib::Error;
+    /// fn main() -> Result<(), Error> {
+    ///     let config = RobotConfig::from_file("robot.toml")?;
+    ///     let handle = config.transport.open()?;
+    ///     let mut robot = Robot::new(config, &handle)?;
+    ///     robot.motor("left_front")?.set_velocity(0.5)?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn new(config: RobotConfig, handle: &'h H) -> Result<Self, crate::Error> {
+        config.validate()?;
+        let mut motors = BTreeMap::new();
+        let mut servos = BTreeMap::new();
+        let mut solenoids = BTreeMap::new();
+        let mut smds = BTreeMap::new();
+        let mut sds = BTreeMap::new();
+        for (name, device) in &config.devices {
+            let port = device.port.unwrap_or_default();
+            match device.device_type {
+                device_type::MD | device_type::BLMD => {
+                    let motor: Box<dyn Motor + 'h> = match device.device_type {
+                        device_type::MD => Box::new(Md::new(handle, device.address)?),
+                        _ => Box::new(BlMd::new(
+                            handle,
+                            device.address,
+                            device.controller_id.unwrap_or_default(),
+                        )?),
+                    };
+                    let calibration = calibration_of(device)?;
+                    let limits = device.safety_limits(&calibration);
+                    motors.insert(
+                        name.clone(),
+                        Calibrated::new(Limited::new(motor, limits), calibration),
+                    );
+                }
+                device_type::SMD => {
+                    if let Entry::Vacant(entry) = smds.entry(device.address) {
+                        entry.insert(Smd::new(handle, device.address)?);
+                    }
+                    let calibration = calibration_of(device)?;
+                    servos.insert(
+                        name.clone(),
+                        Servo {
+                            address: device.address,
+                            port,
+                            calibration,
+                            limits: device.safety_limits(&calibration),
+                        },
+                    );
+                }
+                _ => {
+                    if let Entry::Vacant(entry) = sds.entry(device.address) {
+                        entry.insert(Sd::new(handle, device.address)?);
+                    }
+                    solenoids.insert(name.clone(), (device.address, port));
+                }
+            }
+        }
+        Ok(Self {
+            config,
+            handle,
+            motors,
+            servos,
+            solenoids,
+            smds,
+            sds,
+        })
+    }
+
+    pub fn config(&self) -> &RobotConfig {
+        &self.config
+    }
+
+    pub fn handle(&self) -> &'h H {
+        self.handle
+    }
+
+    /// Returns the configuration of the device named `name`.
+    pub fn device(&self, name: &str) -> Result<&DeviceConfig, crate::Error> {
+        self.config
+            .devices
+            .get(name)
+            .ok_or_else(|| crate::Error::ConfigError(format!("no device named `{}`", name)))
+    }
+
+    /// Returns the MD or BLMD named `name`, calibrated and limited as configured.
+    pub fn motor(&mut self, name: &str) -> Result<&mut RobotMotor<'h>, crate::Error> {
+        let config = &self.config;
+        self.motors
+            .get_mut(name)
+            .ok_or_else(|| not_a(config, name, "motor"))
+    }
+
+    /// Returns the SMD port named `name`, calibrated and limited as configured.
+    pub fn servo(&mut self, name: &str) -> Result<RobotServo<'_, 'h, H>, crate::Error> {
+        let servo = self
+            .servos
+            .get(name)
+            .ok_or_else(|| not_a(&self.config, name, "servo"))?;
+        let port = self
+            .smds
+            .get_mut(&servo.address)
+            .unwrap()
+            .port(servo.port)?;
+        Ok(Calibrated::new(
+            Limited::new(port, servo.limits),
+            servo.calibration,
+        ))
+    }
+
+    /// Returns the SD port named `name`.
+    pub fn solenoid(&mut self, name: &str) -> Result<SdPort<'_, 'h, H>, crate::Error> {
+        let &(address, port) = self
+            .solenoids
+            .get(name)
+            .ok_or_else(|| not_a(&self.config, name, "solenoid"))?;
+        self.sds.get_mut(&address).unwrap().port(port)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recording::{Direction, Outcome, Record};
+    use crate::ReplayHandle;
+
+    const CONFIG: &str = r#"
+        [transport]
+        type = "usb"
+
+        [devices.wheel]
+        type = "md"
+        address = 0x01
+
+        [devices.hand]
+        type = "smd"
+        address = 0x20
+        port = 1
+    "#;
+
+    fn record(direction: Direction, data: [u8; 8]) -> Record {
+        Record {
+            direction,
+            outcome: Outcome::Ok,
+            timestamp: std::time::Duration::ZERO,
+            data: data.to_vec(),
+        }
+    }
+
+    #[test]
+    fn devices_keep_their_status_between_calls() {
+        let handle = ReplayHandle::new(vec![
+            record(Direction::Write, [0x01, 0x60, 0x02, 0, 0, 100, 0, 0]),
+            record(Direction::Read, [0x01, 0x00, 0, 90, 0, 30, 0, 0]),
+        ]);
+        let config = RobotConfig::from_toml_str(CONFIG).unwrap();
+        let mut robot = Robot::new(config, &handle).unwrap();
+        assert!(robot.motor("wheel").unwrap().status().is_none());
+        robot.motor("wheel").unwrap().set_duty(100).unwrap();
+        let status = robot.motor("wheel").unwrap().status().unwrap();
+        assert!((status.position - std::f64::consts::FRAC_PI_2).abs() < 1e-9);
+        assert!(handle.divergences().is_empty());
+    }
+
+    #[test]
+    fn devices_are_handed_out_by_kind() {
+        let handle = ReplayHandle::new(Vec::new());
+        let config = RobotConfig::from_toml_str(CONFIG).unwrap();
+        let mut robot = Robot::new(config, &handle).unwrap();
+        assert!(robot.servo("hand").is_ok());
+        assert!(matches!(
+            robot.motor("hand"),
+            Err(crate::Error::ConfigError(e)) if e.contains("SMD")
+        ));
+        assert!(robot.solenoid("gripper").is_err());
+    }
+
+    #[test]
+    fn invalid_devices_are_rejected_on_load() {
+        let handle = ReplayHandle::new(Vec::new());
+        let config = RobotConfig::from_toml_str(&CONFIG.replace("0x01", "0x11")).unwrap();
+        assert!(Robot::new(config, &handle).is_err());
+    }
+}
@@ -0,0 +1,393 @@
+//! Software limits checked before a command is sent to a device.
+//!
+//! A `Limited` device clamps or rejects every command outside its `SafetyLimits`, and can
+//! refuse motion further into a pressed limit switch. Before its first motion toward a
+//! configured switch, it requests the status of the driver, so an unknown switch state
+//! never lets a command through. Limits are in raw device units; wrap the `Limited` device
+//! in a `calibration::Calibrated` to command it in physical units.
+//!
+//! ```rust,no_run
+//! use motor_lib::actuator::Motor;
+//! use motor_lib::device::Md;
+//! use motor_lib::safety::{Action, Limited, SafetyLimits};
+//! use motor_lib::{Error, USBHandle};
+//! fn main() -> Result<(), Error> {
+//!     let handle = USBHandle::new(0x483, 0x5740, 1);
+//!     let limits = SafetyLimits {
+//!         min_position: Some(-90),
+//!         max_position: Some(90),
+//!         max_duty: Some(500),
+//!         action: Action::Clamp,
+//!         positive_limsw: Some(1),
+//!         ..SafetyLimits::default()
+//!     };
+//!     let mut arm = Limited::new(Md::new(&handle, 0x00)?, limits);
+//!     arm.set_duty(1000)?; // sent as 500
+//!     arm.set_position(120)?; // sent as 90
+//!     Ok(())
+//! }
+//! ```
+
+use serde::Deserialize;
+
+use crate::actuator::{Motor, MotorStatus, Servo};
+use crate::md::LimSwStatus;
+
+/// What happens to a command outside the limits.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    /// The command is sent with the value moved to the nearest limit.
+    #[default]
+    Clamp,
+    /// The command is not sent and `Error::LimitExceeded` is returned.
+    Reject,
+}
+
+/// The limits of one device, in raw device units. `None` means unlimited.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SafetyLimits {
+    pub min_position: Option<i16>,
+    pub max_position: Option<i16>,
+    /// The largest absolute PWM duty cycle.
+    pub max_duty: Option<i16>,
+    /// The largest absolute speed.
+    pub max_speed: Option<i16>,
+    /// The largest absolute current.
+    pub max_current: Option<i16>,
+    pub action: Action,
+    /// The port of the limit switch pressed at the positive end of travel.
+    pub positive_limsw: Option<u8>,
+    /// The port of the limit switch pressed at the negative end of travel.
+    pub negative_limsw: Option<u8>,
+}
+
+impl SafetyLimits {
+    fn apply(
+        &self,
+        what: &str,
+        value: i16,
+        min: Option<i16>,
+        max: Option<i16>,
+    ) -> Result<i16, crate::Error> {
+        let limited = value.clamp(min.unwrap_or(i16::MIN), max.unwrap_or(i16::MAX));
+        if limited != value && self.action == Action::Reject {
+            return Err(crate::Error::LimitExceeded(format!(
+                "{} {} is outside {}..={}",
+                what,
+                value,
+                min.unwrap_or(i16::MIN),
+                max.unwrap_or(i16::MAX)
+            )));
+        }
+        Ok(limited)
+    }
+
+    fn apply_magnitude(
+        &self,
+        what: &str,
+        value: i16,
+        max: Option<i16>,
+    ) -> Result<i16, crate::Error> {
+        let max = max.map(i16::saturating_abs);
+        self.apply(what, value, max.map(|m| -m), max)
+    }
+
+    /// Limits a position command.
+    pub fn position(&self, position: i16) -> Result<i16, crate::Error> {
+        self.apply("position", position, self.min_position, self.max_position)
+    }
+
+    /// Limits a PWM duty cycle command.
+    pub fn duty(&self, duty: i16) -> Result<i16, crate::Error> {
+        self.apply_magnitude("duty", duty, self.max_duty)
+    }
+
+    /// Limits a speed command.
+    pub fn speed(&self, speed: i16) -> Result<i16, crate::Error> {
+        self.apply_magnitude("speed", speed, self.max_speed)
+    }
+
+    /// Limits a current command.
+    pub fn current(&self, current: i16) -> Result<i16, crate::Error> {
+        self.apply_magnitude("current", current, self.max_current)
+    }
+
+    /// Returns an error if moving in the direction of `direction` pushes further into a
+    /// pressed limit switch.
+    pub fn check_limsw(&self, limsw: &LimSwStatus, direction: i16) -> Result<(), crate::Error> {
+        let pressed = |port: Option<u8>| match port {
+            Some(0) => limsw.limsw_0,
+            Some(1) => limsw.limsw_1,
+            _ => false,
+        };
+        if (direction > 0 && pressed(self.positive_limsw))
+            || (direction < 0 && pressed(self.negative_limsw))
+        {
+            return Err(crate::Error::LimitExceeded(format!(
+                "limit switch pressed in the {} direction",
+                if direction > 0 {
+                    "positive"
+                } else {
+                    "negative"
+                }
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// A motor or servo whose commands are checked against `SafetyLimits`.
+pub struct Limited<D> {
+    device: D,
+    limits: SafetyLimits,
+}
+
+impl<D> Limited<D> {
+    pub fn new(device: D, limits: SafetyLimits) -> Self {
+        Self { device, limits }
+    }
+
+    pub fn limits(&self) -> &SafetyLimits {
+        &self.limits
+    }
+
+    /// Returns the wrapped device, for commands that bypass the limits.
+    pub fn inner(&mut self) -> &mut D {
+        &mut self.device
+    }
+
+    pub fn into_inner(self) -> D {
+        self.device
+    }
+}
+
+impl<M: Motor> Limited<M> {
+    fn has_limsw(&self) -> bool {
+        self.limits.positive_limsw.is_some() || self.limits.negative_limsw.is_some()
+    }
+
+    /// Returns the last status of the driver, or requests it if none has been received.
+    fn known_status(&mut self) -> Result<MotorStatus, crate::Error> {
+        match self.device.status() {
+            Some(status) => Ok(status),
+            None => self.device.read_status(),
+        }
+    }
+
+    fn check_limsw(&mut self, direction: i16) -> Result<(), crate::Error> {
+        let port = match direction.signum() {
+            1 => self.limits.positive_limsw,
+            -1 => self.limits.negative_limsw,
+            _ => None,
+        };
+        if port.is_none() {
+            return Ok(());
+        }
+        match self.known_status()?.limsw {
+            Some(limsw) => self.limits.check_limsw(&limsw, direction),
+            None => Err(crate::Error::LimitExceeded(
+                "the driver reports no limit switch state".to_string(),
+            )),
+        }
+    }
+}
+
+impl<M: Motor> Motor for Limited<M> {
+    fn set_duty(&mut self, duty: i16) -> Result<MotorStatus, crate::Error> {
+        let duty = self.limits.duty(duty)?;
+        self.check_limsw(duty)?;
+        self.device.set_duty(duty)
+    }
+
+    fn set_velocity(&mut self, velocity: i16) -> Result<MotorStatus, crate::Error> {
+        let velocity = self.limits.speed(velocity)?;
+        self.check_limsw(velocity)?;
+        self.device.set_velocity(velocity)
+    }
+
+    fn set_position(&mut self, position: i16) -> Result<MotorStatus, crate::Error> {
+        let position = self.limits.position(position)?;
+        if self.has_limsw() {
+            let angle = self.known_status()?.angle;
+            self.check_limsw(position.saturating_sub(angle).signum())?;
+        }
+        self.device.set_position(position)
+    }
+
+    fn set_current(&mut self, current: i16) -> Result<MotorStatus, crate::Error> {
+        let current = self.limits.current(current)?;
+        self.check_limsw(current)?;
+        self.device.set_current(current)
+    }
+
+    fn status(&self) -> Option<MotorStatus> {
+        self.device.status()
+    }
+
+    fn read_status(&mut self) -> Result<MotorStatus, crate::Error> {
+        self.device.read_status()
+    }
+}
+
+impl<S: Servo> Servo for Limited<S> {
+    fn set_angle(&mut self, angle: i16) -> Result<i16, crate::Error> {
+        let angle = self.limits.position(angle)?;
+        self.device.set_angle(angle)
+    }
+
+    fn angle(&self) -> Option<i16> {
+        self.device.angle()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A motor that records the commands it receives.
+    #[derive(Default)]
+    struct Fake {
+        status: Option<MotorStatus>,
+        reported: Option<MotorStatus>,
+        sent: Vec<i16>,
+    }
+
+    impl Fake {
+        fn send(&mut self, value: i16) -> Result<MotorStatus, crate::Error> {
+            self.sent.push(value);
+            Ok(self.status.unwrap_or(MotorStatus {
+                angle: 0,
+                speed: 0,
+                current: None,
+                limsw: None,
+            }))
+        }
+    }
+
+    impl Motor for Fake {
+        fn set_duty(&mut self, duty: i16) -> Result<MotorStatus, crate::Error> {
+            self.send(duty)
+        }
+
+        fn set_velocity(&mut self, velocity: i16) -> Result<MotorStatus, crate::Error> {
+            self.send(velocity)
+        }
+
+        fn set_position(&mut self, position: i16) -> Result<MotorStatus, crate::Error> {
+            self.send(position)
+        }
+
+        fn set_current(&mut self, current: i16) -> Result<MotorStatus, crate::Error> {
+            self.send(current)
+        }
+
+        fn status(&self) -> Option<MotorStatus> {
+            self.status
+        }
+
+        fn read_status(&mut self) -> Result<MotorStatus, crate::Error> {
+            self.status = self.reported;
+            self.status.ok_or(crate::Error::Unsupported("no status"))
+        }
+    }
+
+    fn pressed(limsw_0: bool, limsw_1: bool) -> MotorStatus {
+        MotorStatus {
+            angle: 0,
+            speed: 0,
+            current: None,
+            limsw: Some(LimSwStatus { limsw_0, limsw_1 }),
+        }
+    }
+
+    #[test]
+    fn values_are_clamped_to_the_limits() {
+        let limits = SafetyLimits {
+            min_position: Some(-90),
+            max_position: Some(90),
+            max_duty: Some(500),
+            ..SafetyLimits::default()
+        };
+        assert_eq!(limits.position(120).unwrap(), 90);
+        assert_eq!(limits.position(-120).unwrap(), -90);
+        assert_eq!(limits.position(45).unwrap(), 45);
+        assert_eq!(limits.duty(-1000).unwrap(), -500);
+        assert_eq!(limits.speed(i16::MIN).unwrap(), i16::MIN);
+    }
+
+    #[test]
+    fn rejected_values_are_errors() {
+        let limits = SafetyLimits {
+            max_current: Some(100),
+            action: Action::Reject,
+            ..SafetyLimits::default()
+        };
+        assert!(limits.current(100).is_ok());
+        assert!(matches!(
+            limits.current(-101),
+            Err(crate::Error::LimitExceeded(_))
+        ));
+    }
+
+    #[test]
+    fn motion_into_a_pressed_switch_is_refused() {
+        let limits = SafetyLimits {
+            positive_limsw: Some(1),
+            ..SafetyLimits::default()
+        };
+        let mut motor = Limited::new(
+            Fake {
+                status: Some(pressed(false, true)),
+                ..Fake::default()
+            },
+            limits,
+        );
+        assert!(motor.set_duty(100).is_err());
+        assert!(motor.set_duty(-100).is_ok());
+        assert_eq!(motor.inner().sent, [-100]);
+    }
+
+    #[test]
+    fn unknown_switch_state_is_read_before_the_first_motion() {
+        let limits = SafetyLimits {
+            positive_limsw: Some(0),
+            ..SafetyLimits::default()
+        };
+        let mut motor = Limited::new(
+            Fake {
+                reported: Some(pressed(true, false)),
+                ..Fake::default()
+            },
+            limits,
+        );
+        assert!(motor.set_velocity(10).is_err());
+        assert!(motor.inner().sent.is_empty());
+
+        let mut motor = Limited::new(Fake::default(), limits);
+        assert!(motor.set_velocity(10).is_err());
+        assert!(motor.inner().sent.is_empty());
+    }
+
+    #[test]
+    fn drivers_without_switches_are_refused_motion_toward_one() {
+        let limits = SafetyLimits {
+            negative_limsw: Some(0),
+            ..SafetyLimits::default()
+        };
+        let mut motor = Limited::new(
+            Fake {
+                status: Some(MotorStatus {
+                    angle: 0,
+                    speed: 0,
+                    current: Some(0),
+                    limsw: None,
+                }),
+                ..Fake::default()
+            },
+            limits,
+        );
+        assert!(motor.set_current(-10).is_err());
+        assert!(motor.set_current(10).is_ok());
+    }
+}
@@ -0,0 +1,521 @@
+//! A scheduler that runs tasks at fixed rates over a shared handle.
+//!
+//! Tasks are run one at a time on the thread that calls `Scheduler::run`, so handles that
+//! cannot be shared between threads can be used. Each task is scheduled against its own
+//! timeline rather than after its previous run, so its rate does not drift. When several
+//! tasks are due, the one with the highest priority runs first.
+//!
+//! A `SchedulerControl` obtained from `Scheduler::control` can be sent to another thread to
+//! stop the scheduler or to trigger an emergency stop, which sends the emergency frame
+//! before any other task runs. A task that blocks would delay that frame, so a control
+//! obtained from `Scheduler::control_with_handle` also sends it at once over a second
+//! handle, from the thread that calls `SchedulerControl::emergency`. Either way the
+//! shutdown actions run last, to zero outputs.
+//!
+//! ```rust,no_run
+//! use std::time::Duration;
+//! use motor_lib::scheduler::Scheduler;
+//! use motor_lib::{md, sd, Error, USBHandle};
+//! fn main() -> Result<(), Error> {
+//!     let handle = USBHandle::new(0x483, 0x5740, 1);
+//!     let mut scheduler = Scheduler::new(&handle);
+//!     scheduler.every("drive", Duration::from_millis(10), |handle| {
+//!         md::send_speed(handle, 0x00, 100)?;
+//!         Ok(())
+//!     });
+//!     scheduler.every("poll sd", Duration::from_millis(100), |handle| {
+//!         sd::request_status(handle, 0x10)?;
+//!         Ok(())
+//!     });
+//!     scheduler.on_shutdown(|handle| {
+//!         md::send_pwm(handle, 0x00, 0)?;
+//!         Ok(())
+//!     });
+//!
+//!     let control = scheduler.control();
+//!     std::thread::spawn(move || {
+//!         std::thread::sleep(Duration::from_secs(10));
+//!         control.stop();
+//!     });
+//!     for stats in scheduler.run()? {
+//!         println!("{}", stats);
+//!     }
+//!     Ok(())
+//! }
+//! ```
+
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, Thread};
+use std::time::{Duration, Instant};
+
+use crate::HandleTrait;
+
+type Action<'a, H> = Box<dyn FnMut(&H) -> Result<(), crate::Error> + 'a>;
+
+/// The timing statistics of one task.
+#[derive(Debug, Clone, Default)]
+pub struct TaskStats {
+    pub name: String,
+    pub period: Duration,
+    pub runs: u64,
+    /// Runs that finished after the task was next due.
+    pub overruns: u64,
+    /// Periods skipped because the task could not catch up.
+    pub skipped: u64,
+    pub errors: u64,
+    pub last_error: Option<String>,
+    /// The largest delay between the time a run was due and the time it started.
+    pub max_jitter: Duration,
+    total_jitter: Duration,
+    /// The longest time a run took.
+    pub max_duration: Duration,
+}
+
+impl TaskStats {
+    /// Returns the mean delay between the time a run was due and the time it started.
+    pub fn mean_jitter(&self) -> Duration {
+        if self.runs == 0 {
+            Duration::ZERO
+        } else {
+            let mean = self.total_jitter.as_nanos() / u128::from(self.runs);
+            Duration::from_nanos(mean as u64)
+        }
+    }
+}
+
+impl fmt::Display for TaskStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: every {:?}, {} runs, {} overruns, {} skipped, {} errors, jitter {:?} mean / {:?} max, duration {:?} max",
+            self.name,
+            self.period,
+            self.runs,
+            self.overruns,
+            self.skipped,
+            self.errors,
+            self.mean_jitter(),
+            self.max_jitter,
+            self.max_duration
+        )
+    }
+}
+
+struct Task<'a, H> {
+    priority: u8,
+    due: Instant,
+    action: Action<'a, H>,
+    stats: TaskStats,
+}
+
+#[derive(Default)]
+struct Shared {
+    stop: AtomicBool,
+    emergency: AtomicBool,
+    thread: Mutex<Option<Thread>>,
+}
+
+type EmergencyHandle = Arc<Mutex<Box<dyn HandleTrait + Send>>>;
+
+/// Stops a running `Scheduler` from another thread.
+#[derive(Clone)]
+pub struct SchedulerControl {
+    shared: Arc<Shared>,
+    /// The handle over which `emergency` sends the emergency frame itself.
+    handle: Option<EmergencyHandle>,
+}
+
+impl SchedulerControl {
+    fn wake(&self) {
+        if let Some(thread) = self.shared.thread.lock().unwrap().as_ref() {
+            thread.unpark();
+        }
+    }
+
+    /// Stops the scheduler after the task currently running.
+    pub fn stop(&self) {
+        self.shared.stop.store(true, Ordering::SeqCst);
+        self.wake();
+    }
+
+    /// Sends the emergency frame before any other task runs, then stops the scheduler.
+    ///
+    /// With a control from `Scheduler::control_with_handle`, the frame is also sent at once
+    /// over the second handle, and the result is that of this transfer. Otherwise the
+    /// frame is sent by the scheduler once the task currently running returns.
+    pub fn emergency(&self) -> Result<(), crate::Error> {
+        self.shared.emergency.store(true, Ordering::SeqCst);
+        self.wake();
+        match &self.handle {
+            Some(handle) => crate::send_emergency(&*handle.lock().unwrap()).map(|_| ()),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Runs tasks at fixed rates over `handle`.
+pub struct Scheduler<'a, H: HandleTrait> {
+    handle: &'a H,
+    tasks: Vec<Task<'a, H>>,
+    shutdown: Vec<Action<'a, H>>,
+    shared: Arc<Shared>,
+}
+
+impl<'a, H: HandleTrait> Scheduler<'a, H> {
+    pub fn new(handle: &'a H) -> Self {
+        Self {
+            handle,
+            tasks: Vec::new(),
+            shutdown: Vec::new(),
+            shared: Arc::default(),
+        }
+    }
+
+    /// Runs `action` every `period`.
+    pub fn every(
+        &mut self,
+        name: &str,
+        period: Duration,
+        action: impl FnMut(&H) -> Result<(), crate::Error> + 'a,
+    ) {
+        self.every_with_priority(name, period, 0, action);
+    }
+
+    /// Runs `action` every `period`, before due tasks with a lower `priority`.
+    pub fn every_with_priority(
+        &mut self,
+        name: &str,
+        period: Duration,
+        priority: u8,
+        action: impl FnMut(&H) -> Result<(), crate::Error> + 'a,
+    ) {
+        self.tasks.push(Task {
+            priority,
+            due: Instant::now(),
+            action: Box::new(action),
+            stats: TaskStats {
+                name: name.to_string(),
+                period: period.max(Duration::from_micros(1)),
+                ..TaskStats::default()
+            },
+        });
+    }
+
+    /// Runs `action` when the scheduler stops, for example to zero the outputs.
+    pub fn on_shutdown(&mut self, action: impl FnMut(&H) -> Result<(), crate::Error> + 'a) {
+        self.shutdown.push(Box::new(action));
+    }
+
+    /// Returns a handle to stop the scheduler from another thread.
+    pub fn control(&self) -> SchedulerControl {
+        SchedulerControl {
+            shared: self.shared.clone(),
+            handle: None,
+        }
+    }
+
+    /// Same as `control`, but `SchedulerControl::emergency` also sends the emergency frame
+    /// at once over `handle`, without waiting for the task currently running. `handle` is a
+    /// second connection to the bus, such as a `GrpcHandle` to the server that shares it.
+    pub fn control_with_handle(
+        &self,
+        handle: impl HandleTrait + Send + 'static,
+    ) -> SchedulerControl {
+        SchedulerControl {
+            shared: self.shared.clone(),
+            handle: Some(Arc::new(Mutex::new(Box::new(handle)))),
+        }
+    }
+
+    /// Returns the statistics of every task.
+    pub fn stats(&self) -> Vec<TaskStats> {
+        self.tasks.iter().map(|task| task.stats.clone()).collect()
+    }
+
+    /// Runs the tasks until `SchedulerControl::stop` or `SchedulerControl::emergency` is called.
+    ///
+    /// # Returns
+    ///
+    /// A result containing the statistics of every task, or the first Error of the shutdown.
+    pub fn run(&mut self) -> Result<Vec<TaskStats>, crate::Error> {
+        self.run_until(None)
+    }
+
+    /// Same as `run`, but also stops after `duration`.
+    pub fn run_for(&mut self, duration: Duration) -> Result<Vec<TaskStats>, crate::Error> {
+        self.run_until(Some(Instant::now() + duration))
+    }
+
+    fn run_until(&mut self, until: Option<Instant>) -> Result<Vec<TaskStats>, crate::Error> {
+        *self.shared.thread.lock().unwrap() = Some(thread::current());
+        let start = Instant::now();
+        for task in &mut self.tasks {
+            task.due = start;
+        }
+
+        let mut emergency_result = Ok(());
+        loop {
+            if self.shared.emergency.load(Ordering::SeqCst) {
+                emergency_result = crate::send_emergency(self.handle).map(|_| ());
+                break;
+            }
+            let now = Instant::now();
+            if self.shared.stop.load(Ordering::SeqCst) || until.is_some_and(|u| now >= u) {
+                break;
+            }
+
+            let next = self
+                .tasks
+                .iter_mut()
+                .filter(|task| task.due <= now)
+                .max_by_key(|task| (task.priority, std::cmp::Reverse(task.due)));
+            let Some(task) = next else {
+                let wake = self.tasks.iter().map(|task| task.due).min();
+                let wake = match (wake, until) {
+                    (Some(wake), Some(until)) => wake.min(until),
+                    (wake, until) => wake.or(until).unwrap_or(now + Duration::from_secs(1)),
+                };
+                thread::park_timeout(wake.saturating_duration_since(now));
+                continue;
+            };
+
+            let jitter = now - task.due;
+            let result = (task.action)(self.handle);
+            let end = Instant::now();
+
+            let stats = &mut task.stats;
+            stats.runs += 1;
+            stats.total_jitter += jitter;
+            stats.max_jitter = stats.max_jitter.max(jitter);
+            stats.max_duration = stats.max_duration.max(end - now);
+            if let Err(e) = result {
+                stats.errors += 1;
+                stats.last_error = Some(e.to_string());
+            }
+
+            task.due += stats.period;
+            if end > task.due {
+                stats.overruns += 1;
+                let (due, skipped) = catch_up(task.due, end, stats.period);
+                task.due = due;
+                stats.skipped = stats.skipped.saturating_add(skipped);
+            }
+        }
+
+        *self.shared.thread.lock().unwrap() = None;
+        self.shared.stop.store(false, Ordering::SeqCst);
+        self.shared.emergency.store(false, Ordering::SeqCst);
+        let mut shutdown_result = Ok(());
+        for action in &mut self.shutdown {
+            let result = action(self.handle);
+            if shutdown_result.is_ok() {
+                shutdown_result = result;
+            }
+        }
+        emergency_result?;
+        shutdown_result?;
+        Ok(self.stats())
+    }
+}
+
+/// Skips the periods that already passed by `end` instead of running back to back.
+///
+/// # Returns
+///
+/// The time the task is next due, which is at most one period before `end`, and the
+/// number of periods skipped.
+fn catch_up(due: Instant, end: Instant, period: Duration) -> (Instant, u64) {
+    let behind = (end - due).as_nanos() / period.as_nanos();
+    let skipped = u64::try_from(behind).unwrap_or(u64::MAX);
+    let due = u64::try_from(period.as_nanos() * behind)
+        .ok()
+        .and_then(|nanos| due.checked_add(Duration::from_nanos(nanos)))
+        .unwrap_or(end);
+    (due, skipped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    /// A handle that keeps the frames written to it.
+    #[derive(Clone, Default)]
+    struct Log(Arc<Mutex<Vec<Vec<u8>>>>);
+
+    impl HandleTrait for Log {
+        fn read_bulk(&self, _data: &mut [u8], timeout: Duration) -> Result<usize, crate::Error> {
+            thread::sleep(timeout);
+            Err(std::io::Error::from(std::io::ErrorKind::TimedOut).into())
+        }
+
+        fn write_bulk(&self, data: &[u8], _timeout: Duration) -> Result<usize, crate::Error> {
+            self.0.lock().unwrap().push(data.to_vec());
+            Ok(data.len())
+        }
+    }
+
+    fn is_emergency(frame: &[u8]) -> bool {
+        frame[0] == crate::device_type::EMMERGENCY
+    }
+
+    #[test]
+    fn mean_jitter_does_not_truncate_the_run_count() {
+        let stats = TaskStats {
+            runs: u64::from(u32::MAX) + 1,
+            total_jitter: Duration::from_secs(1 << 32),
+            ..TaskStats::default()
+        };
+        assert_eq!(stats.mean_jitter(), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn emergency_is_sent_while_a_task_blocks() {
+        let bus = Log::default();
+        let second = Log::default();
+        let finished = Arc::new(AtomicBool::new(false));
+        let mut scheduler = Scheduler::new(&bus);
+        let task_finished = Arc::clone(&finished);
+        scheduler.every("blocked", Duration::from_secs(1), move |_| {
+            thread::sleep(Duration::from_millis(300));
+            task_finished.store(true, Ordering::SeqCst);
+            Ok(())
+        });
+        let control = scheduler.control_with_handle(second.clone());
+        let stopper_finished = Arc::clone(&finished);
+        let stopper = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            control.emergency().unwrap();
+            stopper_finished.load(Ordering::SeqCst)
+        });
+        scheduler.run().unwrap();
+
+        // The frame went out over the second handle while the task was still running.
+        assert!(!stopper.join().unwrap());
+        let sent = second.0.lock().unwrap();
+        assert!(sent.iter().any(|frame| is_emergency(frame)));
+        // The scheduler also sends it once the task returns.
+        let sent = bus.0.lock().unwrap();
+        assert!(sent.iter().any(|frame| is_emergency(frame)));
+    }
+
+    #[test]
+    fn catching_up_does_not_truncate_the_periods_behind() {
+        let due = Instant::now();
+        let period = Duration::from_micros(1);
+        let end = due + Duration::from_secs(5000) + Duration::from_nanos(500);
+        let (next, skipped) = catch_up(due, end, period);
+        assert_eq!(skipped, 5_000_000_000);
+        assert_eq!(next, due + Duration::from_secs(5000));
+
+        let (next, skipped) = catch_up(
+            due,
+            due + Duration::from_millis(25),
+            Duration::from_millis(10),
+        );
+        assert_eq!(skipped, 2);
+        assert_eq!(next, due + Duration::from_millis(20));
+    }
+
+    #[test]
+    fn fixed_rate_ticks_do_not_drift() {
+        let bus = Log::default();
+        let starts = RefCell::new(Vec::new());
+        let mut scheduler = Scheduler::new(&bus);
+        scheduler.every("tick", Duration::from_millis(20), |_| {
+            starts.borrow_mut().push(Instant::now());
+            thread::sleep(Duration::from_millis(3));
+            Ok(())
+        });
+        let stats = scheduler.run_for(Duration::from_millis(405)).unwrap();
+        drop(scheduler);
+
+        let starts = starts.into_inner();
+        assert!(starts.len() >= 20, "{} runs", starts.len());
+        assert_eq!(stats[0].overruns, 0);
+        for (index, start) in starts.iter().enumerate() {
+            // Never early, and late only by the jitter of one run rather than by the sum of
+            // every run's duration.
+            let offset = *start - starts[0];
+            assert!(
+                offset >= Duration::from_millis(20) * index as u32,
+                "{:?}",
+                offset
+            );
+            assert!(
+                offset < Duration::from_millis(20) * index as u32 + Duration::from_millis(15),
+                "run {} at {:?}",
+                index,
+                offset
+            );
+        }
+    }
+
+    #[test]
+    fn higher_priority_runs_first() {
+        let bus = Log::default();
+        let order = RefCell::new(Vec::new());
+        let mut scheduler = Scheduler::new(&bus);
+        scheduler.every("low", Duration::from_secs(1), |_| {
+            order.borrow_mut().push("low");
+            Ok(())
+        });
+        scheduler.every_with_priority("high", Duration::from_secs(1), 5, |_| {
+            order.borrow_mut().push("high");
+            Ok(())
+        });
+        scheduler.run_for(Duration::from_millis(20)).unwrap();
+        drop(scheduler);
+        assert_eq!(order.into_inner(), ["high", "low"]);
+    }
+
+    #[test]
+    fn a_slow_run_counts_an_overrun_and_skips_periods() {
+        let bus = Log::default();
+        let mut first = true;
+        let mut scheduler = Scheduler::new(&bus);
+        scheduler.every("slow", Duration::from_millis(20), |_| {
+            if std::mem::take(&mut first) {
+                thread::sleep(Duration::from_millis(50));
+            }
+            Ok(())
+        });
+        // The first run ends past 40 ms, so the run due at 20 ms is skipped and the one due
+        // at 40 ms runs at once; the next is due at 60 ms.
+        let stats = scheduler.run_for(Duration::from_millis(55)).unwrap();
+        assert_eq!(stats[0].runs, 2);
+        assert_eq!(stats[0].overruns, 1);
+        assert_eq!(stats[0].skipped, 1);
+    }
+
+    #[test]
+    fn shutdown_actions_run_on_stop() {
+        let bus = Log::default();
+        let mut scheduler = Scheduler::new(&bus);
+        scheduler.every("idle", Duration::from_millis(5), |_| Ok(()));
+        scheduler.on_shutdown(|handle| {
+            handle.write_bulk(&[0x01, 0x60, 0x02, 0, 0, 0, 0, 0], Duration::ZERO)?;
+            Err(std::io::Error::other("first action failed").into())
+        });
+        scheduler.on_shutdown(|handle| {
+            handle.write_bulk(&[0x02, 0x60, 0x02, 0, 0, 0, 0, 0], Duration::ZERO)?;
+            Ok(())
+        });
+        let control = scheduler.control();
+        let stopper = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(30));
+            control.stop();
+        });
+        let error = scheduler.run().unwrap_err();
+        stopper.join().unwrap();
+
+        assert!(error.to_string().contains("first action failed"));
+        let sent = bus.0.lock().unwrap();
+        assert_eq!(sent.len(), 2);
+        assert_eq!(sent[0][0], 0x01);
+        assert_eq!(sent[1][0], 0x02);
+        assert!(!sent.iter().any(|frame| is_emergency(frame)));
+    }
+}
@@ -11,13 +11,13 @@ pub mod mode {
     pub const SINGLE_POWER: u8 = 3;
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub struct LimSwStatus {
     pub limsw_0: bool,
     pub limsw_1: bool,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub struct SdStatus {
     pub address: u8,
     pub semi_id: u8,
@@ -120,6 +120,45 @@ pub fn send_powers(
     return receive_status(handle, address);
 }
 
+/// Requests the status of the specified SD device without changing its output.
+///
+/// # Arguments
+///
+/// * `handle` - A reference to an object implementing the USBHandleTrait.
+/// * `address` - The address of the SD device.
+///
+/// # Returns
+///
+/// A result containing the number of bytes written or a Error.
+/// The reply can be read with `receive_status`.
+///
+/// # Example
+///
+/// Sample code to request and retrieve status data from the SD at address 0x10.
+/// ```rust,no_run
+/// use motor_lib::{USBHandle, Error, sd};
+/// fn main() -> Result<(), Error> {
+///     let handle = USBHandle::new(0x483, 0x5740, 1);
+///     sd::request_status(&handle, 0x10)?;
+///     let status = sd::receive_status(&handle, 0x10)?;
+///     println!("{:?}", status);
+///     Ok(())
+/// }
+/// ```
+pub fn request_status(handle: &impl HandleTrait, address: u8) -> Result<usize, crate::Error> {
+    let send_buf: [u8; 8] = [
+        address | device_type::SD,
+        device_type::MASTER,
+        mode::STATUS,
+        0,
+        0,
+        0,
+        0,
+        0,
+    ];
+    handle.write_bulk(&send_buf, Duration::from_millis(5000))
+}
+
 /// Receive a data from the specified SD device.
 ///
 /// # Arguments
@@ -148,16 +187,29 @@ pub fn receive_status(handle: &impl HandleTrait, address: u8) -> Result<SdStatus
     loop {
         handle.read_bulk(&mut receive_buf, Duration::from_millis(5000))?;
         if (address | device_type::SD) == receive_buf[0] {
-            return Ok(SdStatus {
-                address: receive_buf[0],
-                semi_id: receive_buf[1],
-                port_0: ((receive_buf[2] as i16) << 8 | (receive_buf[3] as i16)),
-                port_1: ((receive_buf[4] as i16) << 8 | (receive_buf[5] as i16)),
-                limsw: LimSwStatus {
-                    limsw_0: receive_buf[6] == 1,
-                    limsw_1: receive_buf[7] == 1,
-                },
-            });
+            return Ok(decode_status(&receive_buf));
         }
     }
 }
+
+/// Decodes a status frame received from an SD device.
+///
+/// # Arguments
+///
+/// * `receive_buf` - The frame received from the SD device.
+///
+/// # Returns
+///
+/// The status of the SD device.
+pub fn decode_status(receive_buf: &[u8; 8]) -> SdStatus {
+    SdStatus {
+        address: receive_buf[0],
+        semi_id: receive_buf[1],
+        port_0: ((receive_buf[2] as i16) << 8 | (receive_buf[3] as i16)),
+        port_1: ((receive_buf[4] as i16) << 8 | (receive_buf[5] as i16)),
+        limsw: LimSwStatus {
+            limsw_0: receive_buf[6] == 1,
+            limsw_1: receive_buf[7] == 1,
+        },
+    }
+}
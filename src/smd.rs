@@ -10,7 +10,7 @@ pub mod mode {
     pub const ANGLES: u8 = 2;
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub struct SmdStatus {
     pub address: u8,
     pub semi_id: u8,
@@ -106,6 +106,45 @@ pub fn send_angles(
     return receive_status(handle, address);
 }
 
+/// Requests the status of the specified SMD device without changing its output.
+///
+/// # Arguments
+///
+/// * `handle` - A reference to an object implementing the USBHandleTrait.
+/// * `address` - The address of the SMD device.
+///
+/// # Returns
+///
+/// A result containing the number of bytes written or a Error.
+/// The reply can be read with `receive_status`.
+///
+/// # Example
+///
+/// Sample code to request and retrieve status data from the SMD at address 0x20.
+/// ```rust,no_run
+/// use motor_lib::{USBHandle, Error, smd};
+/// fn main() -> Result<(), Error> {
+///     let handle = USBHandle::new(0x483, 0x5740, 1);
+///     smd::request_status(&handle, 0x20)?;
+///     let status = smd::receive_status(&handle, 0x20)?;
+///     println!("{:?}", status);
+///     Ok(())
+/// }
+/// ```
+pub fn request_status(handle: &impl HandleTrait, address: u8) -> Result<usize, crate::Error> {
+    let send_buf: [u8; 8] = [
+        address | device_type::SMD,
+        device_type::MASTER,
+        mode::STATUS,
+        0,
+        0,
+        0,
+        0,
+        0,
+    ];
+    handle.write_bulk(&send_buf, Duration::from_millis(5000))
+}
+
 /// Receive a data from the specified SMD device.
 ///
 /// # Arguments
@@ -134,12 +173,25 @@ pub fn receive_status(handle: &impl HandleTrait, address: u8) -> Result<SmdStatu
     loop {
         handle.read_bulk(&mut receive_buf, Duration::from_millis(5000))?;
         if (address | device_type::SMD) == receive_buf[0] {
-            return Ok(SmdStatus {
-                address: receive_buf[0],
-                semi_id: receive_buf[1],
-                angle_0: ((receive_buf[2] as i16) << 8 | (receive_buf[3] as i16)),
-                angle_1: ((receive_buf[4] as i16) << 8 | (receive_buf[5] as i16)),
-            });
+            return Ok(decode_status(&receive_buf));
         }
     }
 }
+
+/// Decodes a status frame received from an SMD device.
+///
+/// # Arguments
+///
+/// * `receive_buf` - The frame received from the SMD device.
+///
+/// # Returns
+///
+/// The status of the SMD device.
+pub fn decode_status(receive_buf: &[u8; 8]) -> SmdStatus {
+    SmdStatus {
+        address: receive_buf[0],
+        semi_id: receive_buf[1],
+        angle_0: ((receive_buf[2] as i16) << 8 | (receive_buf[3] as i16)),
+        angle_1: ((receive_buf[4] as i16) << 8 | (receive_buf[5] as i16)),
+    }
+}
@@ -11,7 +11,7 @@ pub mod mode {
     pub const COLOR: u8 = 3;
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 
 pub struct Color {
     pub red: u8,
@@ -19,7 +19,7 @@ pub struct Color {
     pub blue: u8,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub struct SrStatus {
     pub address: f32,
     pub voltage: bool,
@@ -27,6 +27,54 @@ pub struct SrStatus {
     pub freq: f32,
 }
 
+/// Decodes a status frame received from the SR device.
+///
+/// The status has the layout of the COLOR command: the LED voltage in the fourth byte,
+/// then the green, red and blue intensities and the frequency in quarters of a hertz.
+///
+/// # Arguments
+///
+/// * `receive_buf` - The frame received from the SR device.
+///
+/// # Returns
+///
+/// The status of the SR device.
+pub fn decode_status(receive_buf: &[u8; 8]) -> SrStatus {
+    SrStatus {
+        address: receive_buf[0] as f32,
+        voltage: receive_buf[3] != 0,
+        color: Color {
+            red: receive_buf[5],
+            green: receive_buf[4],
+            blue: receive_buf[6],
+        },
+        freq: receive_buf[7] as f32 / 4.0,
+    }
+}
+
+/// Requests the status of the SR device.
+///
+/// # Arguments
+///
+/// * `handle` - A reference to an object implementing the USBHandleTrait.
+///
+/// # Returns
+///
+/// A result containing the number of bytes written or an Error.
+pub fn request_status(handle: &impl HandleTrait) -> Result<usize, crate::Error> {
+    let send_buf: [u8; 8] = [
+        device_type::SR,
+        device_type::MASTER,
+        mode::STATUS,
+        0,
+        0,
+        0,
+        0,
+        0,
+    ];
+    handle.write_bulk(&send_buf, Duration::from_millis(5000))
+}
+
 /// Sends a stop command to the SR device.
 ///
 /// # Arguments
@@ -34,9 +82,9 @@ pub struct SrStatus {
 /// * `handle` - A reference to an object implementing the USBHandleTrait.
 ///
 /// # Returns
-/// 
+///
 /// A result indicating success or an Error.
-/// 
+///
 /// # Example
 ///
 /// Sample code to send a stop command to the SR device.